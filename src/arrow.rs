@@ -0,0 +1,720 @@
+// Apache Arrow IPC files, also known as Feather v2, with flat schemas only.
+//
+// Files are written in the IPC file format: the schema and a record batch per
+// `batch_size` rows as encapsulated flatbuffer messages, then a footer
+// listing where the batches are. Columns are nullable and of the bool, int64,
+// float64, date32, timestamp (milliseconds, without a time zone) and utf8
+// types; their buffers can be compressed as LZ4 frames. Reading takes the
+// file and the stream format alike, with columns of the primitive, date,
+// time, decimal and string types. Dictionary encoded and nested columns are
+// not supported, nor zstd compression.
+
+use crate::{
+    compress,
+    convert::{self, Compression, Counted, Rows, Table, Values},
+    flatbuffer::{self, Field, Object},
+    Error,
+};
+use anyhow::{bail, Result};
+use std::{borrow::Cow, fs, io::Write, path::Path};
+
+const MAGIC: &[u8] = b"ARROW1";
+// Marks the start of a message since format version 0.15
+const CONTINUATION: u32 = 0xffff_ffff;
+const METADATA_V5: i16 = 4;
+
+// Message headers
+const SCHEMA: u8 = 1;
+const DICTIONARY_BATCH: u8 = 2;
+const RECORD_BATCH: u8 = 3;
+
+// Types
+const NULL: u8 = 1;
+const INT: u8 = 2;
+const FLOATING_POINT: u8 = 3;
+const BINARY: u8 = 4;
+const UTF8: u8 = 5;
+const BOOL: u8 = 6;
+const DECIMAL: u8 = 7;
+const DATE: u8 = 8;
+const TIME: u8 = 9;
+const TIMESTAMP: u8 = 10;
+const FIXED_SIZE_BINARY: u8 = 15;
+const LARGE_BINARY: u8 = 19;
+const LARGE_UTF8: u8 = 20;
+
+// Compression codecs
+const LZ4_FRAME: u8 = 0;
+
+// Sizes of the Block, FieldNode and Buffer structs
+const BLOCK: usize = 24;
+const FIELD_NODE: usize = 16;
+const BUFFER: usize = 16;
+
+fn invalid(reason: impl Into<String>) -> anyhow::Error {
+    Error::InvalidArrow(reason.into()).into()
+}
+
+fn unsupported(feature: impl Into<String>) -> anyhow::Error {
+    Error::Unsupported {
+        format: "Arrow IPC",
+        feature: feature.into(),
+    }
+    .into()
+}
+
+// Writing.
+
+fn field(name: &str, values: &Values) -> Object {
+    let (tag, ty) = match values {
+        Values::Boolean(_) => (BOOL, Object::default()),
+        Values::Integer(_) => (
+            INT,
+            Object::default()
+                .with(0, Field::I32(64))
+                .with(1, Field::Bool(true)),
+        ),
+        // Double precision
+        Values::Float(_) => (FLOATING_POINT, Object::default().with(0, Field::I16(2))),
+        // Days
+        Values::Date(_) => (DATE, Object::default().with(0, Field::I16(0))),
+        // Milliseconds, without a time zone
+        Values::DateTime(_) => (TIMESTAMP, Object::default().with(0, Field::I16(1))),
+        Values::String(_) => (UTF8, Object::default()),
+    };
+    Object::default()
+        .with(0, Field::String(name.to_string()))
+        .with(1, Field::Bool(true))
+        .with(2, Field::U8(tag))
+        .with(3, Field::Table(ty))
+        .with(5, Field::Tables(Vec::new()))
+}
+
+// Validity bitmap, left out when every value is there.
+fn validity<T>(values: &[Option<T>]) -> Vec<u8> {
+    if values.iter().all(Option::is_some) {
+        return Vec::new();
+    }
+    bits(values.iter().map(Option::is_some))
+}
+
+fn bits(bits: impl Iterator<Item = bool>) -> Vec<u8> {
+    let mut out = Vec::new();
+    for (index, bit) in bits.enumerate() {
+        if index % 8 == 0 {
+            out.push(0);
+        }
+        if bit {
+            out[index / 8] |= 1 << (index % 8);
+        }
+    }
+    out
+}
+
+fn nulls<T>(values: &[Option<T>]) -> usize {
+    values.iter().filter(|value| value.is_none()).count()
+}
+
+// Buffers of values of fixed width, nulls as zeros, and the null count.
+fn fixed<T: Copy + Default, const N: usize>(
+    values: &[Option<T>],
+    bytes: impl Fn(T) -> [u8; N],
+) -> (Vec<Vec<u8>>, usize) {
+    let data = values
+        .iter()
+        .flat_map(|value| bytes(value.unwrap_or_default()))
+        .collect();
+    (vec![validity(values), data], nulls(values))
+}
+
+// The buffers of a column and its null count.
+fn buffers(values: &Values, rows: std::ops::Range<usize>) -> Result<(Vec<Vec<u8>>, usize)> {
+    Ok(match values {
+        Values::Boolean(values) => {
+            let values = &values[rows];
+            let data = bits(values.iter().map(|value| value.unwrap_or(false)));
+            (vec![validity(values), data], nulls(values))
+        }
+        Values::Integer(values) => fixed(&values[rows], i64::to_le_bytes),
+        Values::Float(values) => fixed(&values[rows], f64::to_le_bytes),
+        Values::Date(values) => fixed(&values[rows], i32::to_le_bytes),
+        Values::DateTime(values) => fixed(&values[rows], i64::to_le_bytes),
+        Values::String(values) => {
+            let values = &values[rows];
+            let mut offsets = 0i32.to_le_bytes().to_vec();
+            let mut data = Vec::new();
+            for value in values {
+                data.extend(value.as_deref().unwrap_or_default().as_bytes());
+                let Ok(offset) = i32::try_from(data.len()) else {
+                    bail!(unsupported("strings over 2 GiB in a record batch"));
+                };
+                offsets.extend(offset.to_le_bytes());
+            }
+            (vec![validity(values), offsets, data], nulls(values))
+        }
+    })
+}
+
+// Where a message is in the file, for the footer.
+fn block(offset: u64, metadata: usize, body: usize) -> Vec<u8> {
+    let mut bytes = (offset as i64).to_le_bytes().to_vec();
+    bytes.extend((metadata as i32).to_le_bytes());
+    bytes.extend([0; 4]);
+    bytes.extend((body as i64).to_le_bytes());
+    bytes
+}
+
+// Writes an encapsulated message, returning its block.
+fn message<W: Write>(
+    out: &mut Counted<W>,
+    header_type: u8,
+    header: Object,
+    body: &[u8],
+) -> Result<Vec<u8>> {
+    let message = Object::default()
+        .with(0, Field::I16(METADATA_V5))
+        .with(1, Field::U8(header_type))
+        .with(2, Field::Table(header))
+        .with(3, Field::I64(body.len() as i64));
+    let metadata = flatbuffer::finish(&message);
+    let offset = out.position;
+    out.write(&CONTINUATION.to_le_bytes())?;
+    out.write(&(metadata.len() as i32).to_le_bytes())?;
+    out.write(&metadata)?;
+    out.write(body)?;
+    Ok(block(offset, metadata.len() + 8, body.len()))
+}
+
+// Whether buffers are compressed, failing for the codecs Arrow does not know.
+pub fn compressed(compression: Compression) -> Result<bool> {
+    Ok(match compression {
+        Compression::None => false,
+        Compression::Lz4 => true,
+        Compression::Snappy => bail!(unsupported("snappy compression")),
+        Compression::Gzip => bail!(unsupported("gzip compression")),
+    })
+}
+
+// Writes `table` with a record batch per `batch_size` rows.
+pub fn write<W: Write>(
+    out: W,
+    table: &Table,
+    compression: Compression,
+    batch_size: usize,
+) -> Result<()> {
+    let compressed = compressed(compression)?;
+    let fields = table
+        .names
+        .iter()
+        .zip(&table.columns)
+        .map(|(name, values)| field(name, values))
+        .collect();
+    // Little-endian
+    let schema = Object::default()
+        .with(0, Field::I16(0))
+        .with(1, Field::Tables(fields));
+
+    let mut out = Counted::new(out);
+    out.write(MAGIC)?;
+    out.write(&[0; 2])?;
+    message(&mut out, SCHEMA, schema.clone(), &[])?;
+    let mut blocks = Vec::new();
+    for start in (0..table.rows).step_by(batch_size) {
+        let rows = start..(start + batch_size).min(table.rows);
+        let (mut nodes, mut locations, mut body) = (Vec::new(), Vec::new(), Vec::new());
+        for values in &table.columns {
+            let (buffers, nulls) = buffers(values, rows.clone())?;
+            nodes.extend((rows.len() as i64).to_le_bytes());
+            nodes.extend((nulls as i64).to_le_bytes());
+            for buffer in buffers {
+                // Compressed buffers start with their size, empty ones stay empty
+                let buffer = match compressed && !buffer.is_empty() {
+                    true => {
+                        let mut bytes = (buffer.len() as i64).to_le_bytes().to_vec();
+                        bytes.extend(compress::lz4_frame(&buffer));
+                        bytes
+                    }
+                    false => buffer,
+                };
+                locations.extend((body.len() as i64).to_le_bytes());
+                locations.extend((buffer.len() as i64).to_le_bytes());
+                body.extend(buffer);
+                body.resize(body.len().next_multiple_of(8), 0);
+            }
+        }
+        let mut batch = Object::default()
+            .with(0, Field::I64(rows.len() as i64))
+            .with(
+                1,
+                Field::Structs {
+                    bytes: nodes,
+                    size: FIELD_NODE,
+                },
+            )
+            .with(
+                2,
+                Field::Structs {
+                    bytes: locations,
+                    size: BUFFER,
+                },
+            );
+        if compressed {
+            let compression = Object::default()
+                .with(0, Field::U8(LZ4_FRAME))
+                .with(1, Field::U8(0));
+            batch = batch.with(3, Field::Table(compression));
+        }
+        blocks.extend(message(&mut out, RECORD_BATCH, batch, &body)?);
+    }
+    // End of the stream
+    out.write(&CONTINUATION.to_le_bytes())?;
+    out.write(&0u32.to_le_bytes())?;
+
+    let footer = Object::default()
+        .with(0, Field::I16(METADATA_V5))
+        .with(1, Field::Table(schema))
+        .with(
+            2,
+            Field::Structs {
+                bytes: Vec::new(),
+                size: BLOCK,
+            },
+        )
+        .with(
+            3,
+            Field::Structs {
+                bytes: blocks,
+                size: BLOCK,
+            },
+        );
+    let footer = flatbuffer::finish(&footer);
+    out.write(&footer)?;
+    out.write(&(footer.len() as i32).to_le_bytes())?;
+    out.write(MAGIC)?;
+    out.finish()
+}
+
+// Reading.
+
+// Type of a column, as far as turning its values into cells goes.
+#[derive(Clone, Copy)]
+enum Type {
+    Null,
+    Bool,
+    Int { bytes: usize, signed: bool },
+    Float { bytes: usize },
+    Decimal { bytes: usize, scale: i32 },
+    // Units per day
+    Date { units: i64 },
+    // Units per second
+    Time { bytes: usize, units: i64 },
+    Timestamp { units: i64 },
+    FixedBinary { bytes: usize },
+    Binary { text: bool, large: bool },
+}
+
+impl Type {
+    // Buffers of a column after its validity bitmap.
+    fn buffers(&self) -> usize {
+        match self {
+            Type::Null => 0,
+            Type::Binary { .. } => 2,
+            _ => 1,
+        }
+    }
+}
+
+struct Column {
+    name: String,
+    ty: Type,
+}
+
+// Units per second of a TimeUnit.
+fn units(unit: i16) -> i64 {
+    10i64.pow(3 * unit.clamp(0, 3) as u32)
+}
+
+fn column(field: flatbuffer::Table) -> Result<Column> {
+    let name = field.string(0).unwrap_or_default().to_string();
+    if field.table(4).is_some() {
+        bail!(unsupported(format!("dictionary encoded column {:?}", name)));
+    }
+    let ty = field.table(3);
+    let int = |id, default| ty.and_then(|ty| ty.i32(id)).unwrap_or(default);
+    let short = |id, default| ty.and_then(|ty| ty.i16(id)).unwrap_or(default);
+    let ty = match field.u8(2).unwrap_or(0) {
+        NULL => Type::Null,
+        BOOL => Type::Bool,
+        INT if matches!(int(0, 0), 8 | 16 | 32 | 64) => Type::Int {
+            bytes: int(0, 0) as usize / 8,
+            signed: ty.and_then(|ty| ty.bool(1)).unwrap_or(false),
+        },
+        FLOATING_POINT => Type::Float {
+            bytes: 2 << short(0, 0).clamp(0, 2),
+        },
+        DECIMAL if matches!(int(2, 128), 32 | 64 | 128) => Type::Decimal {
+            bytes: int(2, 128) as usize / 8,
+            scale: int(1, 0),
+        },
+        DATE => Type::Date {
+            units: match short(0, 1) {
+                0 => 1,
+                _ => 86_400_000,
+            },
+        },
+        TIME if matches!(int(1, 32), 32 | 64) => Type::Time {
+            bytes: int(1, 32) as usize / 8,
+            units: units(short(0, 1)),
+        },
+        TIMESTAMP => Type::Timestamp {
+            units: units(short(0, 0)),
+        },
+        FIXED_SIZE_BINARY => Type::FixedBinary {
+            bytes: int(0, 0).max(0) as usize,
+        },
+        BINARY | UTF8 | LARGE_BINARY | LARGE_UTF8 => {
+            let tag = field.u8(2).unwrap_or(0);
+            Type::Binary {
+                text: matches!(tag, UTF8 | LARGE_UTF8),
+                large: matches!(tag, LARGE_BINARY | LARGE_UTF8),
+            }
+        }
+        12 | 13 | 14 | 16 | 17 | 21 => bail!(unsupported(format!("nested column {:?}", name))),
+        tag => bail!(unsupported(format!("type {} of column {:?}", tag, name))),
+    };
+    Ok(Column { name, ty })
+}
+
+// Little-endian integer of 1 to 16 bytes, sign extended if `signed`.
+fn integer(bytes: &[u8], signed: bool) -> i128 {
+    let negative = signed && bytes.last().is_some_and(|byte| byte & 0x80 != 0);
+    let mut value = [if negative { 0xff } else { 0 }; 16];
+    value[..bytes.len()].copy_from_slice(bytes);
+    i128::from_le_bytes(value)
+}
+
+fn half(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let fraction = (bits & 0x3ff) as f32;
+    sign * match exponent {
+        0 => fraction * 2f32.powi(-24),
+        31 if fraction == 0.0 => f32::INFINITY,
+        31 => f32::NAN,
+        _ => (1.0 + fraction / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+// Cell of a value of fixed width.
+fn cell(ty: Type, bytes: &[u8]) -> String {
+    match ty {
+        Type::Int { signed, .. } => integer(bytes, signed).to_string(),
+        Type::Float { bytes: 2 } => half(integer(bytes, false) as u16).to_string(),
+        Type::Float { bytes: 4 } => {
+            f32::from_le_bytes(bytes.try_into().unwrap_or_default()).to_string()
+        }
+        Type::Float { .. } => f64::from_le_bytes(bytes.try_into().unwrap_or_default()).to_string(),
+        Type::Decimal { scale, .. } => convert::decimal(integer(bytes, true), scale),
+        Type::Date { units } => convert::date((integer(bytes, true) as i64).div_euclid(units)),
+        Type::Time { units, .. } => convert::time_of_day(integer(bytes, true) as i64, units),
+        Type::Timestamp { units } => convert::timestamp(integer(bytes, true) as i64, units),
+        _ => convert::binary(bytes),
+    }
+}
+
+// Reads the cells of a record batch into `rows`.
+fn read_batch(
+    batch: flatbuffer::Table,
+    body: &[u8],
+    columns: &[Column],
+    rows: &mut Vec<Vec<String>>,
+) -> Result<()> {
+    let corrupt = || invalid("a record batch is corrupt");
+    let length = usize::try_from(batch.i64(0).unwrap_or(0)).map_err(|_| corrupt())?;
+    let nodes = batch.structs(1, FIELD_NODE).ok_or_else(corrupt)?;
+    let locations = batch.structs(2, BUFFER).ok_or_else(corrupt)?;
+    let compressed = match batch.table(3) {
+        None => false,
+        Some(compression) if compression.u8(0).unwrap_or(LZ4_FRAME) == LZ4_FRAME => true,
+        Some(_) => bail!(unsupported("zstd compression")),
+    };
+    let mut locations = locations.into_iter();
+    let mut buffer = || -> Result<Cow<[u8]>> {
+        let location = locations.next().ok_or_else(corrupt)?;
+        let offset = i64::from_le_bytes(location[..8].try_into()?);
+        let len = i64::from_le_bytes(location[8..].try_into()?);
+        let bytes = usize::try_from(offset)
+            .ok()
+            .zip(usize::try_from(len).ok())
+            .and_then(|(offset, len)| body.get(offset..offset.checked_add(len)?))
+            .ok_or_else(|| invalid("a buffer is out of its message"))?;
+        if !compressed || bytes.is_empty() {
+            return Ok(Cow::Borrowed(bytes));
+        }
+        let size = i64::from_le_bytes(bytes.get(..8).ok_or_else(corrupt)?.try_into()?);
+        // Buffers that would not shrink are left uncompressed
+        if size == -1 {
+            return Ok(Cow::Borrowed(&bytes[8..]));
+        }
+        let size = usize::try_from(size).map_err(|_| corrupt())?;
+        compress::unlz4_frame(&bytes[8..], size)
+            .filter(|out| out.len() == size)
+            .map(Cow::Owned)
+            .ok_or_else(|| invalid("a compressed buffer is corrupt"))
+    };
+
+    // Every column but a null one takes at least a bit a row of the body
+    let sized = columns
+        .iter()
+        .any(|column| !matches!(column.ty, Type::Null));
+    if sized && length / 8 > body.len() {
+        bail!(corrupt());
+    }
+    let start = rows.len();
+    rows.resize_with(start + length, || Vec::with_capacity(columns.len()));
+    for (index, column) in columns.iter().enumerate() {
+        let count = nodes
+            .get(index)
+            .map(|node| i64::from_le_bytes(node[..8].try_into().unwrap_or_default()));
+        if count != Some(length as i64) {
+            bail!(invalid(format!(
+                "column {:?} does not have a value for every row",
+                column.name
+            )));
+        }
+        let truncated = || invalid(format!("column {:?} is truncated", column.name));
+        let mut buffers = Vec::new();
+        if !matches!(column.ty, Type::Null) {
+            buffers.push(buffer()?);
+        }
+        for _ in 0..column.ty.buffers() {
+            buffers.push(buffer()?);
+        }
+        let valid = |index: usize| match buffers.first() {
+            Some(bitmap) if !bitmap.is_empty() => bitmap
+                .get(index / 8)
+                .is_some_and(|byte| byte >> (index % 8) & 1 == 1),
+            _ => true,
+        };
+        for (index, row) in rows[start..].iter_mut().enumerate() {
+            if matches!(column.ty, Type::Null) || !valid(index) {
+                row.push(String::new());
+                continue;
+            }
+            let data = &buffers[1];
+            let cell = match column.ty {
+                Type::Bool => {
+                    let byte = data.get(index / 8).ok_or_else(truncated)?;
+                    (byte >> (index % 8) & 1 == 1).to_string()
+                }
+                Type::Binary { text, large } => {
+                    let width = if large { 8 } else { 4 };
+                    let offset = |index: usize| -> Option<usize> {
+                        let bytes = data.get(index * width..(index + 1) * width)?;
+                        usize::try_from(integer(bytes, true)).ok()
+                    };
+                    let range = offset(index).zip(offset(index + 1));
+                    let bytes = range
+                        .and_then(|(start, end)| buffers[2].get(start..end))
+                        .ok_or_else(truncated)?;
+                    match text {
+                        true => String::from_utf8_lossy(bytes).into_owned(),
+                        false => convert::binary(bytes),
+                    }
+                }
+                ty => {
+                    let width = match ty {
+                        Type::Int { bytes, .. }
+                        | Type::Float { bytes }
+                        | Type::Decimal { bytes, .. }
+                        | Type::Time { bytes, .. }
+                        | Type::FixedBinary { bytes } => bytes,
+                        Type::Date { units: 1 } => 4,
+                        _ => 8,
+                    };
+                    let bytes = data
+                        .get(index * width..(index + 1) * width)
+                        .ok_or_else(truncated)?;
+                    cell(ty, bytes)
+                }
+            };
+            row.push(cell);
+        }
+    }
+    Ok(())
+}
+
+// Reads every row of a file or stream, the column names as header.
+pub fn read(path: &Path) -> Result<Rows> {
+    let data = fs::read(path)?;
+    let file = data.starts_with(MAGIC);
+    // A file cut short could otherwise pass for a stream of fewer rows
+    if file && (data.len() < 2 * MAGIC.len() + 2 || !data.ends_with(MAGIC)) {
+        bail!(invalid("it starts but does not end with ARROW1"));
+    }
+    let mut position = if file { 8 } else { 0 };
+    let word = |position: usize| -> Option<u32> {
+        Some(u32::from_le_bytes(
+            data.get(position..position + 4)?.try_into().ok()?,
+        ))
+    };
+    let mut columns: Option<Vec<Column>> = None;
+    let mut rows = Vec::new();
+    // Streams may end without the end marker
+    while let Some(mut len) = word(position) {
+        position += 4;
+        if len == CONTINUATION {
+            len = word(position).ok_or_else(|| invalid("a message is truncated"))?;
+            position += 4;
+        }
+        if len == 0 {
+            break;
+        }
+        let metadata = data
+            .get(position..position + len as usize)
+            .ok_or_else(|| invalid("a message is truncated"))?;
+        position += len as usize;
+        let message = flatbuffer::root(metadata).ok_or_else(|| invalid("a message is corrupt"))?;
+        let body_len = usize::try_from(message.i64(3).unwrap_or(0))
+            .map_err(|_| invalid("a message is corrupt"))?;
+        let body = data
+            .get(position..position.saturating_add(body_len))
+            .ok_or_else(|| invalid("a message body is truncated"))?;
+        position += body_len;
+        let header = message.table(2);
+        match (message.u8(1), header) {
+            (Some(SCHEMA), Some(schema)) => {
+                let fields = schema.tables(1).unwrap_or_default();
+                columns = Some(fields.into_iter().map(column).collect::<Result<_>>()?);
+            }
+            (Some(DICTIONARY_BATCH), _) => bail!(unsupported("dictionary encoded columns")),
+            (Some(RECORD_BATCH), Some(batch)) => {
+                let Some(columns) = &columns else {
+                    bail!(invalid("a record batch comes before the schema"));
+                };
+                read_batch(batch, body, columns, &mut rows)?;
+            }
+            _ => {}
+        }
+    }
+    let Some(columns) = columns else {
+        bail!(invalid("it has no schema"));
+    };
+    Ok(Rows {
+        header: Some(columns.into_iter().map(|column| column.name).collect()),
+        rows,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{schema::ColumnType, sort::TempFile};
+    use std::fs;
+
+    fn scratch(name: &str) -> TempFile {
+        TempFile(std::env::temp_dir().join(format!(
+            "csv_handler-{}-arrow-{}",
+            std::process::id(),
+            name
+        )))
+    }
+
+    // Rows with a column of every type, nulls and text that needs escaping
+    // in other formats.
+    fn rows() -> (Vec<String>, Vec<Vec<String>>) {
+        let names = ["flag", "count", "price", "day", "seen", "note"];
+        let mut rows = vec![
+            [
+                "true",
+                "-7",
+                "3.5",
+                "2023-01-05",
+                "2023-01-05 10:20:30",
+                "héllo, \"world\"",
+            ],
+            ["false", "", "", "", "", ""],
+            [
+                "",
+                "9007199254740993",
+                "-0.25",
+                "1969-12-31",
+                "1900-01-01 00:00:00",
+                "x",
+            ],
+        ];
+        for _ in 0..40 {
+            rows.push([
+                "true",
+                "1",
+                "1000",
+                "2024-02-29",
+                "2038-01-19 03:14:08",
+                "repeated",
+            ]);
+        }
+        let strings = |row: &[&str]| row.iter().map(|cell| cell.to_string()).collect();
+        (
+            strings(&names),
+            rows.iter().map(|row| strings(row)).collect(),
+        )
+    }
+
+    fn table() -> Table {
+        let (names, rows) = rows();
+        let types = [
+            ColumnType::Boolean,
+            ColumnType::Integer,
+            ColumnType::Float,
+            ColumnType::Date,
+            ColumnType::DateTime,
+            ColumnType::String,
+        ];
+        let mut table = Table::new(names, &types);
+        for (index, row) in rows.iter().enumerate() {
+            table.add(index + 1, row).unwrap();
+        }
+        table
+    }
+
+    fn written(compression: Compression, group: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+        write(&mut out, &table(), compression, group).unwrap();
+        out
+    }
+
+    fn read_bytes(name: &str, data: &[u8]) -> Result<Rows> {
+        let file = scratch(name);
+        fs::write(&file.0, data).unwrap();
+        read(&file.0)
+    }
+
+    #[test]
+    fn round_trip() {
+        let (names, rows) = rows();
+        for compression in [Compression::None, Compression::Lz4] {
+            for group in [1, 7, 65_536] {
+                let read = read_bytes("round_trip", &written(compression, group)).unwrap();
+                assert_eq!(read.header.as_ref(), Some(&names));
+                assert_eq!(read.rows, rows, "{:?} in groups of {}", compression, group);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_corrupt_input() {
+        let data = written(Compression::None, 16);
+        for len in 0..data.len() {
+            assert!(
+                read_bytes("truncated", &data[..len]).is_err(),
+                "{} bytes",
+                len
+            );
+        }
+        for position in 0..data.len() {
+            let mut data = data.clone();
+            data[position] ^= 0x5a;
+            let _ = read_bytes("flipped", &data);
+        }
+    }
+}
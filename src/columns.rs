@@ -0,0 +1,157 @@
+// Column level operations, applied the same way to the header and to every
+// row by both the in-memory and the streaming implementations.
+
+use crate::{expr::Expr, resolve_column, Error};
+use anyhow::{bail, Result};
+
+// Where the cells of a new column come from.
+#[derive(Debug, Clone)]
+pub enum CellSource {
+    Value(String),
+    Expr(Expr),
+}
+
+#[derive(Debug, Clone)]
+pub enum ColumnOp {
+    // Inserts a column before 0-based index `at`
+    Add {
+        at: usize,
+        name: String,
+        source: CellSource,
+    },
+    Drop(usize),
+    Move {
+        from: usize,
+        to: usize,
+    },
+    // Keeps only the listed columns, in that order
+    Select(Vec<usize>),
+    // Changes the header only
+    Rename {
+        index: usize,
+        name: String,
+    },
+}
+
+impl ColumnOp {
+    // Builds an add-column operation. `at` names the column the new one is
+    // inserted before and defaults to the end; `expr` wins over `value`.
+    pub fn add(
+        header: Option<&[String]>,
+        cols: usize,
+        name: Option<String>,
+        at: Option<&str>,
+        value: Option<String>,
+        expr: Option<&str>,
+    ) -> Result<Self> {
+        if name.is_some() && header.is_none() {
+            bail!(Error::NoHeader);
+        }
+        let at = match at {
+            Some(column) => resolve_column(header, cols, column)?,
+            None => cols,
+        };
+        let resolve = |column: &str| resolve_column(header, cols, column);
+        let source = match expr {
+            Some(expr) => CellSource::Expr(Expr::parse(expr, &resolve)?),
+            None => CellSource::Value(value.unwrap_or_default()),
+        };
+        Ok(ColumnOp::Add {
+            at,
+            name: name.unwrap_or_default(),
+            source,
+        })
+    }
+
+    pub fn drop(header: Option<&[String]>, cols: usize, column: &str) -> Result<Self> {
+        Ok(ColumnOp::Drop(resolve_column(header, cols, column)?))
+    }
+
+    // `to` is the 1-based position the column ends up at.
+    pub fn move_to(
+        header: Option<&[String]>,
+        cols: usize,
+        column: &str,
+        to: usize,
+    ) -> Result<Self> {
+        let from = resolve_column(header, cols, column)?;
+        if to == 0 || to > cols {
+            bail!(Error::ColumnIndexOutOfBound { index: to, cols });
+        }
+        Ok(ColumnOp::Move { from, to: to - 1 })
+    }
+
+    pub fn select(header: Option<&[String]>, cols: usize, columns: &[String]) -> Result<Self> {
+        let indices = columns
+            .iter()
+            .map(|column| resolve_column(header, cols, column))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(ColumnOp::Select(indices))
+    }
+
+    pub fn rename(
+        header: Option<&[String]>,
+        cols: usize,
+        column: &str,
+        name: String,
+    ) -> Result<Self> {
+        if header.is_none() {
+            bail!(Error::NoHeader);
+        }
+        let index = resolve_column(header, cols, column)?;
+        Ok(ColumnOp::Rename { index, name })
+    }
+
+    // Column count after the operation.
+    pub fn cols_after(&self, cols: usize) -> usize {
+        match self {
+            ColumnOp::Add { .. } => cols + 1,
+            ColumnOp::Drop(_) => cols - 1,
+            ColumnOp::Move { .. } | ColumnOp::Rename { .. } => cols,
+            ColumnOp::Select(indices) => indices.len(),
+        }
+    }
+
+    // Whether data rows change, as opposed to the header alone.
+    pub fn changes_rows(&self) -> bool {
+        !matches!(self, ColumnOp::Rename { .. })
+    }
+
+    // Short rows are handled leniently: missing cells read as empty and
+    // operations on them are skipped.
+    pub fn apply(&self, row: &mut Vec<String>, is_header: bool) -> Result<()> {
+        match self {
+            ColumnOp::Add { at, name, source } => {
+                let cell = match (is_header, source) {
+                    (true, _) => name.clone(),
+                    (false, CellSource::Value(value)) => value.clone(),
+                    (false, CellSource::Expr(expr)) => expr.eval(row)?.into_cell(),
+                };
+                row.insert((*at).min(row.len()), cell);
+            }
+            ColumnOp::Drop(index) => {
+                if *index < row.len() {
+                    row.remove(*index);
+                }
+            }
+            ColumnOp::Move { from, to } => {
+                if *from < row.len() {
+                    let cell = row.remove(*from);
+                    row.insert((*to).min(row.len()), cell);
+                }
+            }
+            ColumnOp::Select(indices) => {
+                *row = indices
+                    .iter()
+                    .map(|index| row.get(*index).cloned().unwrap_or_default())
+                    .collect();
+            }
+            ColumnOp::Rename { index, name } => {
+                if is_header && *index < row.len() {
+                    row[*index] = name.clone();
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,882 @@
+// Compression used by the file formats read and written: deflate (RFC 1951)
+// in zip archives and gzip streams (RFC 1952), snappy, and LZ4 blocks and
+// frames.
+//
+// The encoders favour simplicity over ratio: deflate output uses the fixed
+// Huffman codes only, and all of them find matches through a hash of the next
+// four bytes without chains. Decoders return None on corrupt input.
+
+const MIN_MATCH: usize = 4;
+const HASH_BITS: u32 = 14;
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut index = 0;
+    while index < 256 {
+        let mut crc = index as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                0xedb8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[index] = crc;
+        index += 1;
+    }
+    table
+};
+
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &byte| {
+        CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+fn hash(bytes: &[u8]) -> usize {
+    let value = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    (value.wrapping_mul(0x1e35_a7bd) >> (32 - HASH_BITS)) as usize
+}
+
+// A run of bytes to copy: literals before it, then `len` bytes from `offset`
+// back.
+struct Match {
+    literals: std::ops::Range<usize>,
+    offset: usize,
+    len: usize,
+}
+
+// Greedy matching of `data` against itself, at most `max_len` bytes per match
+// and `max_offset` bytes back. The literals after the last match are left to
+// the caller.
+fn matches(data: &[u8], max_len: usize, max_offset: usize) -> (Vec<Match>, usize) {
+    let mut table = vec![usize::MAX; 1 << HASH_BITS];
+    let mut found = Vec::new();
+    let (mut position, mut literal_start) = (0, 0);
+    while position + MIN_MATCH <= data.len() {
+        let slot = &mut table[hash(&data[position..])];
+        let candidate = std::mem::replace(slot, position);
+        let usable = candidate != usize::MAX
+            && position - candidate <= max_offset
+            && data[candidate..candidate + MIN_MATCH] == data[position..position + MIN_MATCH];
+        if !usable {
+            position += 1;
+            continue;
+        }
+        let len = data[position..]
+            .iter()
+            .zip(&data[candidate..])
+            .take(max_len)
+            .take_while(|(a, b)| a == b)
+            .count();
+        found.push(Match {
+            literals: literal_start..position,
+            offset: position - candidate,
+            len,
+        });
+        position += len;
+        literal_start = position;
+    }
+    (found, literal_start)
+}
+
+// Deflate.
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+// Order in which code length code lengths are sent
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+struct Bits<'a> {
+    data: &'a [u8],
+    position: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl Bits<'_> {
+    fn bits(&mut self, count: u32) -> Option<u32> {
+        while self.count < count {
+            let byte = *self.data.get(self.position)?;
+            self.position += 1;
+            self.buffer |= (byte as u32) << self.count;
+            self.count += 8;
+        }
+        let value = self.buffer & ((1u64 << count) - 1) as u32;
+        self.buffer >>= count;
+        self.count -= count;
+        Some(value)
+    }
+}
+
+// Canonical Huffman code: the number of codes of every length and the symbols
+// ordered by code.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; 16];
+        for length in 1..15 {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        Self { counts, symbols }
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Option<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for length in 1..16 {
+            code |= bits.bits(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - first < count {
+                return Some(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        None
+    }
+}
+
+fn fixed_lengths() -> [u8; 288] {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    lengths
+}
+
+// A buffer for the output of decompressing `data`, with room for `size`
+// bytes as far as `data` could hold them, since sizes come from the file.
+fn buffer(size: usize, data: &[u8]) -> Vec<u8> {
+    // Deflate compresses by at most 1032 to 1, the other formats by less
+    Vec::with_capacity(size.min(data.len().saturating_mul(1032)))
+}
+
+// Decompresses raw deflate data, `size` being a hint of the output size.
+pub fn inflate(data: &[u8], size: usize) -> Option<Vec<u8>> {
+    let mut out = buffer(size, data);
+    let mut bits = Bits {
+        data,
+        position: 0,
+        buffer: 0,
+        count: 0,
+    };
+    loop {
+        let last = bits.bits(1)? == 1;
+        match bits.bits(2)? {
+            0 => {
+                // Stored block, starting at the next byte
+                bits.buffer = 0;
+                bits.count = 0;
+                let len = data.get(bits.position..bits.position + 2)?;
+                let len = u16::from_le_bytes([len[0], len[1]]) as usize;
+                let start = bits.position + 4;
+                out.extend_from_slice(data.get(start..start + len)?);
+                bits.position = start + len;
+            }
+            1 => {
+                let literals = Huffman::new(&fixed_lengths());
+                let distances = Huffman::new(&[5; 30]);
+                codes(&mut bits, &mut out, &literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_codes(&mut bits)?;
+                codes(&mut bits, &mut out, &literals, &distances)?;
+            }
+            _ => return None,
+        }
+        if last {
+            return Some(out);
+        }
+    }
+}
+
+fn dynamic_codes(bits: &mut Bits) -> Option<(Huffman, Huffman)> {
+    let literal_count = bits.bits(5)? as usize + 257;
+    let distance_count = bits.bits(5)? as usize + 1;
+    let code_count = bits.bits(4)? as usize + 4;
+    let mut code_lengths = [0u8; 19];
+    for &index in &CODE_LENGTH_ORDER[..code_count] {
+        code_lengths[index] = bits.bits(3)? as u8;
+    }
+    let code = Huffman::new(&code_lengths);
+
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let (length, repeat) = match code.decode(bits)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => (*lengths.last()?, 3 + bits.bits(2)?),
+            17 => (0, 3 + bits.bits(3)?),
+            18 => (0, 11 + bits.bits(7)?),
+            _ => return None,
+        };
+        lengths.extend(std::iter::repeat_n(length, repeat as usize));
+    }
+    if lengths.len() > literal_count + distance_count {
+        return None;
+    }
+    Some((
+        Huffman::new(&lengths[..literal_count]),
+        Huffman::new(&lengths[literal_count..]),
+    ))
+}
+
+// Decodes the symbols of a compressed block up to its end.
+fn codes(
+    bits: &mut Bits,
+    out: &mut Vec<u8>,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Option<()> {
+    loop {
+        let symbol = literals.decode(bits)? as usize;
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Some(()),
+            _ => {
+                let index = symbol - 257;
+                let len = *LENGTH_BASE.get(index)? as usize
+                    + bits.bits(*LENGTH_EXTRA.get(index)? as u32)? as usize;
+                let index = distances.decode(bits)? as usize;
+                let distance = *DISTANCE_BASE.get(index)? as usize
+                    + bits.bits(*DISTANCE_EXTRA.get(index)? as u32)? as usize;
+                if distance > out.len() {
+                    return None;
+                }
+                // Copies may overlap what they produce
+                let start = out.len() - distance;
+                for index in start..start + len {
+                    out.push(out[index]);
+                }
+            }
+        }
+    }
+}
+
+// Writes bits from the least significant one on, as deflate packs them.
+struct BitWriter {
+    out: Vec<u8>,
+    buffer: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn bits(&mut self, value: u32, count: u32) {
+        self.buffer |= (value as u64) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.out.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    // Huffman codes go most significant bit first.
+    fn code(&mut self, code: u32, len: u32) {
+        self.bits(code.reverse_bits() >> (32 - len), len);
+    }
+
+    fn literal(&mut self, symbol: usize) {
+        match symbol {
+            0..=143 => self.code(0x30 + symbol as u32, 8),
+            144..=255 => self.code(0x190 + (symbol - 144) as u32, 9),
+            256..=279 => self.code((symbol - 256) as u32, 7),
+            _ => self.code(0xc0 + (symbol - 280) as u32, 8),
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.buffer as u8);
+        }
+        self.out
+    }
+}
+
+// Compresses `data` as a single deflate block with the fixed codes.
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter {
+        out: Vec::with_capacity(data.len() / 2 + 16),
+        buffer: 0,
+        count: 0,
+    };
+    // Last block, fixed codes
+    writer.bits(1, 1);
+    writer.bits(1, 2);
+    let (found, tail) = matches(data, 258, 32 * 1024);
+    for Match {
+        literals,
+        offset,
+        len,
+    } in found
+    {
+        for &byte in &data[literals] {
+            writer.literal(byte as usize);
+        }
+        let index = LENGTH_BASE.partition_point(|&base| base as usize <= len) - 1;
+        writer.literal(257 + index);
+        writer.bits(
+            (len - LENGTH_BASE[index] as usize) as u32,
+            LENGTH_EXTRA[index] as u32,
+        );
+        let index = DISTANCE_BASE.partition_point(|&base| base as usize <= offset) - 1;
+        writer.code(index as u32, 5);
+        writer.bits(
+            (offset - DISTANCE_BASE[index] as usize) as u32,
+            DISTANCE_EXTRA[index] as u32,
+        );
+    }
+    for &byte in &data[tail..] {
+        writer.literal(byte as usize);
+    }
+    writer.literal(256);
+    writer.finish()
+}
+
+// Wraps deflate data in a gzip stream.
+pub fn gzip(data: &[u8]) -> Vec<u8> {
+    // Magic, deflate, no flags or time, unknown system
+    let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+    out.extend(deflate(data));
+    out.extend(crc32(data).to_le_bytes());
+    out.extend((data.len() as u32).to_le_bytes());
+    out
+}
+
+// Decompresses a gzip stream, or zlib data as some writers produce instead.
+pub fn gunzip(data: &[u8], size: usize) -> Option<Vec<u8>> {
+    if data.starts_with(&[0x78]) {
+        return inflate(data.get(2..)?, size);
+    }
+    if !data.starts_with(&[0x1f, 0x8b, 8]) {
+        return None;
+    }
+    let flags = *data.get(3)?;
+    let mut position = 10;
+    // Extra field, file name, comment and header checksum
+    if flags & 4 != 0 {
+        let len = data.get(position..position + 2)?;
+        position += 2 + u16::from_le_bytes([len[0], len[1]]) as usize;
+    }
+    for flag in [8, 16] {
+        if flags & flag != 0 {
+            position += data.get(position..)?.iter().position(|&b| b == 0)? + 1;
+        }
+    }
+    if flags & 2 != 0 {
+        position += 2;
+    }
+    let out = inflate(data.get(position..)?, size)?;
+    let trailer = data.get(data.len().checked_sub(8)?..)?;
+    let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+    (crc == crc32(&out)).then_some(out)
+}
+
+// Snappy.
+
+fn varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn snappy_literal(out: &mut Vec<u8>, literal: &[u8]) {
+    if literal.is_empty() {
+        return;
+    }
+    let len = literal.len() - 1;
+    if len < 60 {
+        out.push((len as u8) << 2);
+    } else {
+        let bytes = (len as u32).to_le_bytes();
+        let count = 4 - bytes.iter().rev().take_while(|&&b| b == 0).count();
+        out.push(((59 + count) as u8) << 2);
+        out.extend(&bytes[..count]);
+    }
+    out.extend(literal);
+}
+
+pub fn snappy(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() / 2 + 16);
+    varint(&mut out, data.len() as u64);
+    let (found, tail) = matches(data, 64, u16::MAX as usize);
+    for Match {
+        literals,
+        offset,
+        len,
+    } in found
+    {
+        snappy_literal(&mut out, &data[literals]);
+        // Copy with a two byte offset
+        out.push((((len - 1) as u8) << 2) | 2);
+        out.extend((offset as u16).to_le_bytes());
+    }
+    snappy_literal(&mut out, &data[tail..]);
+    out
+}
+
+pub fn unsnappy(data: &[u8]) -> Option<Vec<u8>> {
+    let (mut size, mut position, mut shift) = (0usize, 0, 0);
+    loop {
+        let byte = *data.get(position)?;
+        position += 1;
+        size |= ((byte & 0x7f) as usize).checked_shl(shift)?;
+        shift += 7;
+        if byte < 0x80 {
+            break;
+        }
+    }
+    let mut out = buffer(size, data);
+    while position < data.len() {
+        let tag = data[position];
+        position += 1;
+        let (len, offset) = match tag & 3 {
+            0 => {
+                let len = match (tag >> 2) as usize {
+                    len @ 0..=59 => len + 1,
+                    count => {
+                        let bytes = data.get(position..position + count - 59)?;
+                        position += count - 59;
+                        bytes.iter().rev().fold(0, |len, &b| len << 8 | b as usize) + 1
+                    }
+                };
+                if out.len() + len > size {
+                    return None;
+                }
+                out.extend_from_slice(data.get(position..position + len)?);
+                position += len;
+                continue;
+            }
+            1 => {
+                let low = *data.get(position)? as usize;
+                position += 1;
+                (
+                    4 + ((tag >> 2) & 7) as usize,
+                    ((tag >> 5) as usize) << 8 | low,
+                )
+            }
+            2 => {
+                let bytes = data.get(position..position + 2)?;
+                position += 2;
+                let offset = u16::from_le_bytes([bytes[0], bytes[1]]) as usize;
+                ((tag >> 2) as usize + 1, offset)
+            }
+            _ => {
+                let bytes = data.get(position..position + 4)?;
+                position += 4;
+                let offset = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                ((tag >> 2) as usize + 1, offset as usize)
+            }
+        };
+        if offset == 0 || offset > out.len() || out.len() + len > size {
+            return None;
+        }
+        let start = out.len() - offset;
+        for index in start..start + len {
+            out.push(out[index]);
+        }
+    }
+    (out.len() == size).then_some(out)
+}
+
+// LZ4.
+
+// Frames are written with blocks of at most 4 MiB.
+const LZ4_MAGIC: u32 = 0x184d_2204;
+const LZ4_BLOCK: usize = 4 << 20;
+
+// The last match must start this far from the end of a block, and the last
+// five bytes be literals.
+const LZ4_END_LITERALS: usize = 12;
+
+const PRIME_1: u32 = 2_654_435_761;
+const PRIME_2: u32 = 2_246_822_519;
+const PRIME_3: u32 = 3_266_489_917;
+const PRIME_4: u32 = 668_265_263;
+const PRIME_5: u32 = 374_761_393;
+
+// The xxHash32 checksum of LZ4 frames.
+fn xxh32(data: &[u8]) -> u32 {
+    let word = |bytes: &[u8]| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    let round = |acc: u32, lane: u32| {
+        acc.wrapping_add(lane.wrapping_mul(PRIME_2))
+            .rotate_left(13)
+            .wrapping_mul(PRIME_1)
+    };
+    let stripes = data.chunks_exact(16);
+    let rest = stripes.remainder();
+    let mut hash = if data.len() >= 16 {
+        let mut lanes = [
+            PRIME_1.wrapping_add(PRIME_2),
+            PRIME_2,
+            0,
+            0u32.wrapping_sub(PRIME_1),
+        ];
+        for stripe in stripes {
+            for (lane, bytes) in lanes.iter_mut().zip(stripe.chunks_exact(4)) {
+                *lane = round(*lane, word(bytes));
+            }
+        }
+        lanes[0]
+            .rotate_left(1)
+            .wrapping_add(lanes[1].rotate_left(7))
+            .wrapping_add(lanes[2].rotate_left(12))
+            .wrapping_add(lanes[3].rotate_left(18))
+    } else {
+        PRIME_5
+    };
+    hash = hash.wrapping_add(data.len() as u32);
+    let words = rest.chunks_exact(4);
+    let bytes = words.remainder();
+    for bytes in words {
+        hash = hash
+            .wrapping_add(word(bytes).wrapping_mul(PRIME_3))
+            .rotate_left(17)
+            .wrapping_mul(PRIME_4);
+    }
+    for &byte in bytes {
+        hash = hash
+            .wrapping_add((byte as u32).wrapping_mul(PRIME_5))
+            .rotate_left(11)
+            .wrapping_mul(PRIME_1);
+    }
+    hash ^= hash >> 15;
+    hash = hash.wrapping_mul(PRIME_2);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(PRIME_3);
+    hash ^ (hash >> 16)
+}
+
+// Lengths past what a field holds continue in bytes of 255 and a last one
+// below it.
+fn lz4_length(out: &mut Vec<u8>, mut len: usize) {
+    while len >= 255 {
+        out.push(255);
+        len -= 255;
+    }
+    out.push(len as u8);
+}
+
+// Compresses `data` as a single LZ4 block, without its size.
+pub fn lz4_block(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() / 2 + 16);
+    let searched = &data[..data.len().saturating_sub(LZ4_END_LITERALS)];
+    let (found, tail) = matches(searched, usize::MAX, u16::MAX as usize);
+    let sequence = |out: &mut Vec<u8>, literals: &[u8], copy: Option<(usize, usize)>| {
+        let match_len = copy.map_or(0, |(_, len)| len - MIN_MATCH);
+        out.push(((literals.len().min(15) as u8) << 4) | match_len.min(15) as u8);
+        if literals.len() >= 15 {
+            lz4_length(out, literals.len() - 15);
+        }
+        out.extend(literals);
+        if let Some((offset, _)) = copy {
+            out.extend((offset as u16).to_le_bytes());
+            if match_len >= 15 {
+                lz4_length(out, match_len - 15);
+            }
+        }
+    };
+    for Match {
+        literals,
+        offset,
+        len,
+    } in found
+    {
+        sequence(&mut out, &data[literals], Some((offset, len)));
+    }
+    sequence(&mut out, &data[tail..], None);
+    out
+}
+
+// Decompresses an LZ4 block holding `size` bytes at most.
+pub fn unlz4_block(data: &[u8], size: usize) -> Option<Vec<u8>> {
+    let mut out = buffer(size, data);
+    unlz4_into(data, &mut out, size)?;
+    Some(out)
+}
+
+// Appends the contents of an LZ4 block to `out`, whose bytes matches may
+// refer to as frames of linked blocks do. The block adds `size` bytes at most.
+fn unlz4_into(data: &[u8], out: &mut Vec<u8>, size: usize) -> Option<()> {
+    let limit = out.len() + size;
+    let mut position = 0;
+    let length = |position: &mut usize, mut len: usize| -> Option<usize> {
+        if len == 15 {
+            loop {
+                let byte = *data.get(*position)?;
+                *position += 1;
+                len += byte as usize;
+                if byte != 255 {
+                    break;
+                }
+            }
+        }
+        Some(len)
+    };
+    loop {
+        let token = *data.get(position)?;
+        position += 1;
+        let len = length(&mut position, (token >> 4) as usize)?;
+        out.extend_from_slice(data.get(position..position + len)?);
+        position += len;
+        // The last sequence has no match
+        if position == data.len() {
+            break;
+        }
+        let offset = data.get(position..position + 2)?;
+        let offset = u16::from_le_bytes([offset[0], offset[1]]) as usize;
+        position += 2;
+        let len = length(&mut position, (token & 15) as usize)? + MIN_MATCH;
+        if offset == 0 || offset > out.len() || out.len() + len > limit {
+            return None;
+        }
+        let start = out.len() - offset;
+        for index in start..start + len {
+            out.push(out[index]);
+        }
+    }
+    (out.len() <= limit).then_some(())
+}
+
+// Compresses `data` as an LZ4 frame of independent blocks with a content
+// checksum.
+pub fn lz4_frame(data: &[u8]) -> Vec<u8> {
+    let mut out = LZ4_MAGIC.to_le_bytes().to_vec();
+    // Version 1, independent blocks, content checksum; 4 MiB blocks
+    let descriptor = [0x64, 0x70];
+    out.extend(descriptor);
+    out.push((xxh32(&descriptor) >> 8) as u8);
+    for block in data.chunks(LZ4_BLOCK) {
+        let compressed = lz4_block(block);
+        // Blocks that would grow are stored, flagged by the high bit
+        if compressed.len() < block.len() {
+            out.extend((compressed.len() as u32).to_le_bytes());
+            out.extend(compressed);
+        } else {
+            out.extend((block.len() as u32 | 1 << 31).to_le_bytes());
+            out.extend(block);
+        }
+    }
+    out.extend(0u32.to_le_bytes());
+    out.extend(xxh32(data).to_le_bytes());
+    out
+}
+
+// Decompresses an LZ4 frame, `size` being a hint of the output size.
+pub fn unlz4_frame(data: &[u8], size: usize) -> Option<Vec<u8>> {
+    let word = |position: usize| -> Option<u32> {
+        let bytes = data.get(position..position + 4)?;
+        Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    };
+    if word(0)? != LZ4_MAGIC {
+        return None;
+    }
+    let flags = *data.get(4)?;
+    let block_size = 1 << (8 + 2 * ((*data.get(5)? >> 4) & 7));
+    if flags >> 6 != 1 || block_size < 1 << 16 {
+        return None;
+    }
+    let block_checksums = flags & 0x10 != 0;
+    let content_checksum = flags & 4 != 0;
+    // Content size and dictionary id, then the header checksum
+    let mut position = 6 + if flags & 8 != 0 { 8 } else { 0 } + if flags & 1 != 0 { 4 } else { 0 };
+    position += 1;
+    let mut out = buffer(size, data);
+    loop {
+        let len = word(position)?;
+        position += 4;
+        if len == 0 {
+            break;
+        }
+        let stored = len & 1 << 31 != 0;
+        let len = (len & !(1 << 31)) as usize;
+        let block = data.get(position..position + len)?;
+        position += len + if block_checksums { 4 } else { 0 };
+        match stored {
+            true => out.extend_from_slice(block),
+            false => unlz4_into(block, &mut out, block_size)?,
+        }
+    }
+    if content_checksum && word(position)? != xxh32(&out) {
+        return None;
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &[u8] = include_bytes!("../tests/fixtures/sample.csv");
+
+    // Inputs that exercise literals only, long matches, overlapping copies
+    // and matches far back.
+    fn inputs() -> Vec<Vec<u8>> {
+        let mut noise = Vec::new();
+        let mut state = 1u32;
+        for _ in 0..100_000 {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            noise.push((state >> 16) as u8);
+        }
+        let mut far = noise[..40_000].to_vec();
+        far.extend_from_slice(&noise[..40_000]);
+        vec![
+            Vec::new(),
+            b"a".to_vec(),
+            b"abcabcabcabcabcabcabcabc".to_vec(),
+            vec![0; 70_000],
+            SAMPLE.to_vec(),
+            noise,
+            far,
+        ]
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn xxh32_check_values() {
+        assert_eq!(xxh32(b""), 0x02cc_5d05);
+        assert_eq!(xxh32(b"abc"), 0x32d1_53ff);
+    }
+
+    #[test]
+    fn deflate_round_trip() {
+        for input in inputs() {
+            assert_eq!(
+                inflate(&deflate(&input), input.len()).as_deref(),
+                Some(&input[..])
+            );
+            assert_eq!(
+                gunzip(&gzip(&input), input.len()).as_deref(),
+                Some(&input[..])
+            );
+        }
+    }
+
+    #[test]
+    fn snappy_round_trip() {
+        for input in inputs() {
+            assert_eq!(unsnappy(&snappy(&input)).as_deref(), Some(&input[..]));
+        }
+    }
+
+    #[test]
+    fn lz4_round_trip() {
+        for input in inputs() {
+            let block = lz4_block(&input);
+            assert_eq!(
+                unlz4_block(&block, input.len()).as_deref(),
+                Some(&input[..])
+            );
+            assert_eq!(
+                unlz4_frame(&lz4_frame(&input), 0).as_deref(),
+                Some(&input[..])
+            );
+        }
+    }
+
+    // Written by Python's gzip and zlib modules.
+    #[test]
+    fn reads_gzip_and_zlib_fixtures() {
+        let gzip = include_bytes!("../tests/fixtures/sample.csv.gz");
+        let zlib = include_bytes!("../tests/fixtures/sample.csv.zlib");
+        assert_eq!(gunzip(gzip, 0).as_deref(), Some(SAMPLE));
+        assert_eq!(gunzip(zlib, 0).as_deref(), Some(SAMPLE));
+    }
+
+    // Written by liblz4's LZ4_compress_default and the lz4 command line tool
+    // with linked blocks.
+    #[test]
+    fn reads_lz4_fixtures() {
+        let block = include_bytes!("../tests/fixtures/sample.csv.lz4block");
+        let frame = include_bytes!("../tests/fixtures/sample.csv.lz4");
+        assert_eq!(unlz4_block(block, SAMPLE.len()).as_deref(), Some(SAMPLE));
+        assert_eq!(unlz4_frame(frame, 0).as_deref(), Some(SAMPLE));
+    }
+
+    // A stream put together by hand from the format description, with every
+    // kind of literal and copy, none of which the writer here produces all of.
+    #[test]
+    fn reads_every_snappy_element() {
+        let mut data = vec![23, 0x0c];
+        data.extend(b"abcd");
+        // Copies with a one, two and four byte offset
+        data.extend([0x11, 0x04, 0x12, 0x0c, 0x00, 0x0b, 0x02, 0, 0, 0]);
+        // A literal with its length in an extra byte
+        data.extend([0xf0, 0x02]);
+        data.extend(b"xyz");
+        assert_eq!(
+            unsnappy(&data).as_deref(),
+            Some(&b"abcdabcdabcdabcdadadxyz"[..])
+        );
+    }
+
+    // Corrupt input fails rather than panicking or returning other data.
+    #[test]
+    fn rejects_corrupt_input() {
+        let compressed = [
+            gzip(SAMPLE),
+            snappy(SAMPLE),
+            lz4_frame(SAMPLE),
+            lz4_block(SAMPLE),
+        ];
+        for (kind, data) in compressed.iter().enumerate() {
+            let decode = |data: &[u8]| match kind {
+                0 => gunzip(data, SAMPLE.len()),
+                1 => unsnappy(data),
+                2 => unlz4_frame(data, SAMPLE.len()),
+                _ => unlz4_block(data, SAMPLE.len()),
+            };
+            for len in 0..data.len() {
+                assert_ne!(decode(&data[..len]).as_deref(), Some(SAMPLE));
+            }
+            for position in 0..data.len() {
+                let mut data = data.clone();
+                data[position] ^= 0x5a;
+                let _ = decode(&data);
+            }
+        }
+        // A length far beyond what the data holds
+        assert_eq!(unsnappy(&[0xff, 0xff, 0xff, 0xff, 0x0f, 0x00, b'a']), None);
+        assert_eq!(inflate(&[0xff; 4], usize::MAX), None);
+    }
+}
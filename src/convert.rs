@@ -0,0 +1,653 @@
+// Conversion of the rows to and from JSON, and the typed columns shared by
+// the Parquet and Arrow IPC formats.
+//
+// JSON output is an array with an object per row keyed by the header, or an
+// array of cells when the file has none; NDJSON writes the same values one
+// per line. Cells are strings unless column types are given, in which case
+// numbers and booleans are written as such and null markers as null. Nesting
+// turns dotted column names like `address.city` into nested objects.
+//
+// JSON input is read the other way around: a document holding an array of
+// records, or a single one, or NDJSON with a record per line. Records that
+// are objects become rows under a header made of every key in the order it
+// was first seen; records that are arrays become headerless rows. Nested
+// values are flattened into dotted columns (`address.city`, `tags.0`) or
+// kept as JSON text in a single cell.
+//
+// Parquet and Arrow hold a single type per column, so rows written to them
+// are first converted into typed columns, and values read from them are
+// turned back into cells the way the rest of the tool parses them: ISO dates,
+// datetimes with a space, and decimals with a point.
+
+use crate::{
+    datetime::{self, DateTime},
+    json::{self, Json},
+    schema::{self, ColumnType},
+    Error,
+};
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::Write,
+    path::Path,
+};
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    // A single array, indented
+    Json,
+    // One compact value per line, also known as JSON Lines
+    #[value(alias = "jsonl")]
+    Ndjson,
+    // Apache Parquet, a column per type of the schema
+    Parquet,
+    // The Apache Arrow IPC file format, also known as Feather v2
+    #[value(alias = "ipc", alias = "feather")]
+    Arrow,
+    // A GitHub flavoured Markdown table
+    #[value(alias = "md")]
+    Markdown,
+    // An HTML table, in a document of its own unless asked for a fragment
+    Html,
+    // A LaTeX `tabular` environment
+    #[value(alias = "tex")]
+    Latex,
+}
+
+// Compression of Parquet pages or Arrow buffers. Arrow only knows LZ4 among
+// these.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    None,
+    Snappy,
+    Gzip,
+    Lz4,
+}
+
+// Largest integer a JSON number holds exactly in most readers.
+const MAX_SAFE_INTEGER: i64 = (1 << 53) - 1;
+
+pub struct JsonWriter<W: Write> {
+    out: W,
+    format: Format,
+    // Keys leading to each column, None to write arrays
+    paths: Option<Vec<Vec<String>>>,
+    types: Vec<Option<ColumnType>>,
+    rows: usize,
+}
+
+impl<W: Write> JsonWriter<W> {
+    // Rows are keyed by `header` when there is one, split at dots if `nest`
+    // is set. `types` may be shorter than the rows, cells of columns without
+    // a type stay strings.
+    pub fn new(
+        out: W,
+        format: Format,
+        header: Option<&[String]>,
+        nest: bool,
+        types: Vec<Option<ColumnType>>,
+    ) -> Result<Self> {
+        let paths = header.map(|header| {
+            header
+                .iter()
+                .map(|name| match nest {
+                    true => name.split('.').map(String::from).collect(),
+                    false => vec![name.clone()],
+                })
+                .collect::<Vec<Vec<String>>>()
+        });
+        // A column named like an object holding nested ones has nowhere to go
+        if let (true, Some(header), Some(paths)) = (nest, header, &paths) {
+            for path in paths {
+                let prefix = paths
+                    .iter()
+                    .position(|prefix| prefix.len() < path.len() && path.starts_with(prefix));
+                if let Some(other) = prefix {
+                    bail!(Error::NestingConflict {
+                        column: header[other].clone(),
+                        nested: path.join("."),
+                    });
+                }
+            }
+        }
+        Ok(Self {
+            out,
+            format,
+            paths,
+            types,
+            rows: 0,
+        })
+    }
+
+    fn value(&self, index: usize, cell: &str) -> Json {
+        let Some(ty) = self.types.get(index).copied().flatten() else {
+            return Json::String(cell.to_string());
+        };
+        if schema::is_null(cell) {
+            return Json::Null;
+        }
+        let value = match ty {
+            ColumnType::Boolean => schema::parse_bool(cell).map(Json::Bool),
+            // Larger integers would lose digits as numbers, and leading
+            // zeros of codes would be lost
+            ColumnType::Integer => cell
+                .trim()
+                .parse::<i64>()
+                .ok()
+                .filter(|n| n.abs() <= MAX_SAFE_INTEGER && !has_leading_zero(cell))
+                .map(|n| Json::Number(n.to_string())),
+            ColumnType::Float => schema::parse_float(cell).map(Json::float),
+            ColumnType::Date | ColumnType::DateTime | ColumnType::String => None,
+        };
+        // Outliers of a column inferred with less than full confidence
+        value.unwrap_or_else(|| Json::String(cell.to_string()))
+    }
+
+    fn row(&self, row: &[String]) -> Json {
+        let Some(paths) = &self.paths else {
+            let cells = row.iter().enumerate();
+            return Json::Array(cells.map(|(index, cell)| self.value(index, cell)).collect());
+        };
+        let mut members = Vec::new();
+        for index in 0..paths.len().max(row.len()) {
+            let value = match row.get(index) {
+                Some(cell) => self.value(index, cell),
+                // Short rows
+                None => Json::Null,
+            };
+            match paths.get(index) {
+                Some(path) => insert(&mut members, path, value),
+                // Cells of long rows past the header are keyed by column number
+                None => members.push(((index + 1).to_string(), value)),
+            }
+        }
+        Json::Object(members)
+    }
+
+    pub fn write(&mut self, row: &[String]) -> Result<()> {
+        let value = self.row(row);
+        match self.format {
+            Format::Ndjson => writeln!(self.out, "{}", value.to_string(false))?,
+            _ => {
+                let separator = if self.rows == 0 { "[" } else { "," };
+                let value = value.to_string(true).replace('\n', "\n  ");
+                write!(self.out, "{}\n  {}", separator, value)?;
+            }
+        }
+        self.rows += 1;
+        Ok(())
+    }
+
+    // Closes the array and flushes, returning the number of rows written.
+    pub fn finish(mut self) -> Result<usize> {
+        if self.format != Format::Ndjson {
+            match self.rows {
+                0 => writeln!(self.out, "[]")?,
+                _ => writeln!(self.out, "\n]")?,
+            }
+        }
+        self.out.flush()?;
+        Ok(self.rows)
+    }
+}
+
+// Adds `value` under the keys of `path`, creating the objects on the way.
+fn insert(members: &mut Vec<(String, Json)>, path: &[String], value: Json) {
+    let [key, rest @ ..] = path else {
+        return;
+    };
+    if rest.is_empty() {
+        members.push((key.clone(), value));
+        return;
+    }
+    let position = members
+        .iter()
+        .position(|(name, member)| name == key && matches!(member, Json::Object(_)));
+    let position = position.unwrap_or_else(|| {
+        members.push((key.clone(), Json::Object(Vec::new())));
+        members.len() - 1
+    });
+    if let Json::Object(children) = &mut members[position].1 {
+        insert(children, rest, value);
+    }
+}
+
+// Format of the file read.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum InputFormat {
+    Csv,
+    Json,
+    #[value(alias = "jsonl")]
+    Ndjson,
+    Xlsx,
+    Parquet,
+    #[value(alias = "ipc", alias = "feather")]
+    Arrow,
+}
+
+impl InputFormat {
+    // Format told by the file extension, CSV unless it is a JSON,
+    // spreadsheet, Parquet or Arrow one.
+    pub fn from_path(path: &Path) -> Self {
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        match extension.to_ascii_lowercase().as_str() {
+            "json" => InputFormat::Json,
+            "ndjson" | "jsonl" => InputFormat::Ndjson,
+            "xlsx" => InputFormat::Xlsx,
+            "parquet" | "parq" | "pq" => InputFormat::Parquet,
+            "arrow" | "arrows" | "feather" | "ipc" => InputFormat::Arrow,
+            _ => InputFormat::Csv,
+        }
+    }
+}
+
+// How nested arrays and objects of JSON input end up in cells.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Flatten {
+    // A column per nested value, named by its path like `address.city` or
+    // `tags.0`
+    Dotted,
+    // The nested value as JSON text in a single cell
+    Json,
+}
+
+// Header and rows read from JSON input, the header None when the records are
+// arrays. Parquet and Arrow input always has a header.
+pub struct Rows {
+    pub header: Option<Vec<String>>,
+    pub rows: Vec<Vec<String>>,
+}
+
+// Reads the records of a JSON or NDJSON file.
+pub fn read(path: &Path, format: InputFormat, flatten: Flatten) -> Result<Rows> {
+    let source = fs::read_to_string(path)?;
+    let mut records = Vec::new();
+    match format {
+        InputFormat::Ndjson => {
+            for (index, line) in source.lines().enumerate() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                // Errors point at the line of the file, not of the record
+                let record = json::parse(line).map_err(|error| match error {
+                    Error::InvalidJson { column, reason, .. } => Error::InvalidJson {
+                        line: index + 1,
+                        column,
+                        reason,
+                    },
+                    error => error,
+                })?;
+                records.push(record);
+            }
+        }
+        _ => match json::parse(&source)? {
+            Json::Array(items) => records = items,
+            record => records.push(record),
+        },
+    }
+
+    let mut columns = Columns::default();
+    let mut keyed = Vec::new();
+    let mut rows = Vec::new();
+    for (index, record) in records.into_iter().enumerate() {
+        match record {
+            Json::Object(members) => {
+                let mut cells = Vec::new();
+                for (key, value) in members {
+                    flatten_into(&mut cells, &mut columns, key, value, flatten, 1).map_err(
+                        |reason| Error::InvalidRecord {
+                            record: index + 1,
+                            reason,
+                        },
+                    )?;
+                }
+                // A member named like the path of a nested one, as in
+                // {"a":{"b":1},"a.b":2}, would lose one of the two values
+                let mut seen = HashSet::new();
+                if let Some((column, _)) = cells.iter().find(|(column, _)| !seen.insert(*column)) {
+                    bail!(Error::InvalidRecord {
+                        record: index + 1,
+                        reason: format!(
+                            "has more than one value for column {:?}",
+                            columns.names[*column]
+                        ),
+                    });
+                }
+                keyed.push(cells);
+            }
+            Json::Array(items) => rows.push(items.into_iter().map(cell).collect()),
+            _ => bail!(Error::InvalidRecord {
+                record: index + 1,
+                reason: "is neither an object nor an array".to_string(),
+            }),
+        }
+        if !keyed.is_empty() && !rows.is_empty() {
+            bail!(Error::InvalidRecord {
+                record: index + 1,
+                reason: "mixes objects and arrays as records".to_string(),
+            });
+        }
+    }
+    if rows.is_empty() && !keyed.is_empty() {
+        let width = columns.names.len();
+        for cells in keyed {
+            let mut row = vec![String::new(); width];
+            for (column, value) in cells {
+                row[column] = value;
+            }
+            rows.push(row);
+        }
+        return Ok(Rows {
+            header: Some(columns.names),
+            rows,
+        });
+    }
+    Ok(Rows { header: None, rows })
+}
+
+// Column names in the order they were first seen.
+#[derive(Default)]
+struct Columns {
+    names: Vec<String>,
+    positions: HashMap<String, usize>,
+}
+
+impl Columns {
+    fn position(&mut self, name: String) -> usize {
+        if let Some(&position) = self.positions.get(&name) {
+            return position;
+        }
+        self.names.push(name.clone());
+        self.positions.insert(name, self.names.len() - 1);
+        self.names.len() - 1
+    }
+}
+
+// Adds the cells of the member `key` to `cells`, nested ones under their
+// dotted path when flattening that way. `depth` is that of the member in its
+// record, failing with the reason when it is too deep.
+fn flatten_into(
+    cells: &mut Vec<(usize, String)>,
+    columns: &mut Columns,
+    key: String,
+    value: Json,
+    flatten: Flatten,
+    depth: usize,
+) -> Result<(), String> {
+    if depth > json::MAX_DEPTH {
+        return Err(format!(
+            "nests values more than {} levels deep",
+            json::MAX_DEPTH
+        ));
+    }
+    let members: Vec<(String, Json)> = match value {
+        Json::Object(members) if flatten == Flatten::Dotted && !members.is_empty() => members,
+        Json::Array(items) if flatten == Flatten::Dotted && !items.is_empty() => items
+            .into_iter()
+            .enumerate()
+            .map(|(index, item)| (index.to_string(), item))
+            .collect(),
+        value => {
+            cells.push((columns.position(key), cell(value)));
+            return Ok(());
+        }
+    };
+    for (name, value) in members {
+        let key = format!("{}.{}", key, name);
+        flatten_into(cells, columns, key, value, flatten, depth + 1)?;
+    }
+    Ok(())
+}
+
+// Text of a value in a cell: strings as they are, null as an empty cell and
+// anything else as JSON.
+fn cell(value: Json) -> String {
+    match value {
+        Json::Null => String::new(),
+        Json::String(text) => text,
+        value => value.to_string(false),
+    }
+}
+
+// Values of a column of the binary formats, None for nulls.
+pub enum Values {
+    Boolean(Vec<Option<bool>>),
+    Integer(Vec<Option<i64>>),
+    Float(Vec<Option<f64>>),
+    // Days since 1970-01-01
+    Date(Vec<Option<i32>>),
+    // Milliseconds since 1970-01-01 00:00:00, without a time zone
+    DateTime(Vec<Option<i64>>),
+    String(Vec<Option<String>>),
+}
+
+impl Values {
+    pub fn ty(&self) -> ColumnType {
+        match self {
+            Values::Boolean(_) => ColumnType::Boolean,
+            Values::Integer(_) => ColumnType::Integer,
+            Values::Float(_) => ColumnType::Float,
+            Values::Date(_) => ColumnType::Date,
+            Values::DateTime(_) => ColumnType::DateTime,
+            Values::String(_) => ColumnType::String,
+        }
+    }
+}
+
+// Rows converted column by column for Parquet and Arrow output.
+pub struct Table {
+    pub names: Vec<String>,
+    pub columns: Vec<Values>,
+    pub rows: usize,
+}
+
+impl Table {
+    pub fn new(names: Vec<String>, types: &[ColumnType]) -> Self {
+        let columns = types
+            .iter()
+            .map(|ty| match ty {
+                ColumnType::Boolean => Values::Boolean(Vec::new()),
+                ColumnType::Integer => Values::Integer(Vec::new()),
+                ColumnType::Float => Values::Float(Vec::new()),
+                ColumnType::Date => Values::Date(Vec::new()),
+                ColumnType::DateTime => Values::DateTime(Vec::new()),
+                ColumnType::String => Values::String(Vec::new()),
+            })
+            .collect();
+        Self {
+            names,
+            columns,
+            rows: 0,
+        }
+    }
+
+    // Adds row `row_index`, 1-based. Cells missing from short rows are null,
+    // long rows have nowhere to go. Inferred types fit every value, so only
+    // declared ones fail.
+    pub fn add(&mut self, row_index: usize, row: &[String]) -> Result<()> {
+        if row.len() > self.columns.len() {
+            bail!(Error::FieldCountMismatch {
+                expected: self.columns.len(),
+                found: row.len(),
+            });
+        }
+        for (index, column) in self.columns.iter_mut().enumerate() {
+            let cell = row.get(index).map_or("", String::as_str);
+            let null = schema::is_null(cell);
+            let parsed = match column {
+                Values::Boolean(values) => push(values, null, || schema::parse_bool(cell)),
+                Values::Integer(values) => push(values, null, || cell.trim().parse().ok()),
+                Values::Float(values) => push(values, null, || schema::parse_float(cell)),
+                Values::Date(values) => push(values, null, || date_value(cell)),
+                Values::DateTime(values) => push(values, null, || datetime_value(cell)),
+                // Null markers are text like any other in a string column
+                Values::String(values) => push(values, cell.is_empty(), || Some(cell.to_string())),
+            };
+            if !parsed {
+                bail!(Error::InvalidSchema(format!(
+                    "column {:?} is declared {} but row {} has {:?}",
+                    self.names[index],
+                    column.ty().name(),
+                    row_index,
+                    cell
+                )));
+            }
+        }
+        self.rows += 1;
+        Ok(())
+    }
+}
+
+fn date_value(cell: &str) -> Option<i32> {
+    let date = datetime::parse(cell).filter(|date| date.time.is_none())?;
+    i32::try_from(date.days()).ok()
+}
+
+// Milliseconds since 1970-01-01 00:00:00 of a date or datetime, whose
+// fraction of a second and time zone are dropped.
+fn datetime_value(cell: &str) -> Option<i64> {
+    let date = datetime::parse(cell)?;
+    let (hour, minute, second) = date.time.unwrap_or_default();
+    let seconds = date.days() * 86_400 + (hour * 3600 + minute * 60 + second) as i64;
+    Some(seconds * 1000)
+}
+
+// Whether a cell that parses as `ty` reads back from Parquet and Arrow as
+// other text: `01234` as 1234, `1.50` as 1.5, `yes` as true, `31.12.2023` as
+// 2023-12-31, a datetime without its time zone or fraction of a second, or a
+// null marker as an empty cell.
+pub fn changes(ty: ColumnType, cell: &str) -> bool {
+    if cell.is_empty() || ty == ColumnType::String {
+        return false;
+    }
+    if schema::is_null(cell) {
+        return true;
+    }
+    let text = match ty {
+        ColumnType::Boolean => schema::parse_bool(cell).map(|value| value.to_string()),
+        ColumnType::Integer => cell
+            .trim()
+            .parse::<i64>()
+            .ok()
+            .map(|value| value.to_string()),
+        ColumnType::Float => schema::parse_float(cell).map(|value| value.to_string()),
+        ColumnType::Date => date_value(cell).map(|days| date(days as i64)),
+        ColumnType::DateTime => datetime_value(cell).map(|ms| timestamp(ms, 1000)),
+        ColumnType::String => None,
+    };
+    text.is_some_and(|text| text != cell)
+}
+
+// Pushes the parsed value, or None for a null, returning false if the value
+// does not parse.
+fn push<T>(values: &mut Vec<Option<T>>, null: bool, parse: impl FnOnce() -> Option<T>) -> bool {
+    if null {
+        values.push(None);
+        return true;
+    }
+    match parse() {
+        Some(value) => {
+            values.push(Some(value));
+            true
+        }
+        None => false,
+    }
+}
+
+// Integers written with leading zeros, as in codes, which a number would lose.
+pub fn has_leading_zero(cell: &str) -> bool {
+    let digits = cell.trim().trim_start_matches(['-', '+']);
+    digits.len() > 1 && digits.starts_with('0') && digits.bytes().all(|b| b.is_ascii_digit())
+}
+
+// Cells of values read from Parquet and Arrow.
+
+pub fn date(days: i64) -> String {
+    DateTime::from_days(days, None).to_string()
+}
+
+// A count of `units` per second since 1970-01-01, with the fraction of the
+// second when there is one.
+pub fn timestamp(value: i64, units: i64) -> String {
+    let (seconds, fraction) = (value.div_euclid(units), value.rem_euclid(units));
+    let (days, seconds) = (seconds.div_euclid(86_400), seconds.rem_euclid(86_400));
+    let time = (
+        (seconds / 3600) as u32,
+        (seconds / 60 % 60) as u32,
+        (seconds % 60) as u32,
+    );
+    DateTime::from_days(days, Some(time)).to_string() + &fraction_of(fraction, units)
+}
+
+// A count of `units` per second since midnight.
+pub fn time_of_day(value: i64, units: i64) -> String {
+    let (seconds, fraction) = (value.div_euclid(units), value.rem_euclid(units));
+    format!(
+        "{:02}:{:02}:{:02}{}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        fraction_of(fraction, units)
+    )
+}
+
+fn fraction_of(fraction: i64, units: i64) -> String {
+    if fraction == 0 {
+        return String::new();
+    }
+    let digits = units.ilog10() as usize;
+    format!(".{:0digits$}", fraction, digits = digits)
+}
+
+// An integer with `scale` of its digits after the decimal point.
+pub fn decimal(value: i128, scale: i32) -> String {
+    if scale <= 0 {
+        let zeros = "0".repeat(scale.unsigned_abs() as usize);
+        return match value {
+            0 => "0".to_string(),
+            value => format!("{}{}", value, zeros),
+        };
+    }
+    let digits = format!(
+        "{:0width$}",
+        value.unsigned_abs(),
+        width = scale as usize + 1
+    );
+    let (whole, fraction) = digits.split_at(digits.len() - scale as usize);
+    let sign = if value < 0 { "-" } else { "" };
+    format!("{}{}.{}", sign, whole, fraction)
+}
+
+// Binary values as text when they are UTF-8, and as hex digits otherwise.
+pub fn binary(bytes: &[u8]) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(_) => bytes.iter().map(|byte| format!("{:02x}", byte)).collect(),
+    }
+}
+
+// Output that keeps count of the bytes written, for formats whose metadata
+// points at offsets in the file.
+pub struct Counted<W: Write> {
+    out: W,
+    pub position: u64,
+}
+
+impl<W: Write> Counted<W> {
+    pub fn new(out: W) -> Self {
+        Self { out, position: 0 }
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        self.out.write_all(bytes)?;
+        self.position += bytes.len() as u64;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
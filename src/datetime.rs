@@ -0,0 +1,125 @@
+// Lenient date and datetime parsing for sorting and type inference.
+//
+// Accepted forms are ISO `2023-12-31`, `2023/12/31` and European
+// `31.12.2023`, optionally followed by a time `23:59` or `23:59:59` after a
+// `T` or a space. Fractional seconds and time zone suffixes are ignored.
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+    // Hour, minute and second, when the value has a time part
+    pub time: Option<(u32, u32, u32)>,
+}
+
+pub fn parse(value: &str) -> Option<DateTime> {
+    let value = value.trim();
+    let split = value.find(['T', ' ']).unwrap_or(value.len());
+    let (date, time) = value.split_at(split);
+    let (year, month, day) = parse_date(date)?;
+    let time = match time.get(1..) {
+        None | Some("") => None,
+        Some(time) => Some(parse_time(time)?),
+    };
+    Some(DateTime {
+        year,
+        month,
+        day,
+        time,
+    })
+}
+
+fn number<T: std::str::FromStr>(part: &str, len: std::ops::RangeInclusive<usize>) -> Option<T> {
+    if !len.contains(&part.len()) || !part.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    part.parse().ok()
+}
+
+fn parse_date(date: &str) -> Option<(i32, u32, u32)> {
+    let separator = date.chars().find(|c| matches!(c, '-' | '/' | '.'))?;
+    let parts: Vec<&str> = date.split(separator).collect();
+    let [a, b, c] = parts[..] else {
+        return None;
+    };
+    let (year, month, day) = match separator {
+        '.' => (number(c, 4..=4)?, number(b, 1..=2)?, number(a, 1..=2)?),
+        _ => (number(a, 4..=4)?, number(b, 1..=2)?, number(c, 1..=2)?),
+    };
+    if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+        return None;
+    }
+    Some((year, month, day))
+}
+
+fn parse_time(time: &str) -> Option<(u32, u32, u32)> {
+    // Drop fractional seconds and zone designators such as `Z` or `+02:00`
+    let end = time.find(['.', 'Z', '+', '-']).unwrap_or(time.len());
+    let parts: Vec<&str> = time[..end].split(':').collect();
+    let (hour, minute, second) = match parts[..] {
+        [h, m] => (number(h, 1..=2)?, number(m, 2..=2)?, 0),
+        [h, m, s] => (number(h, 1..=2)?, number(m, 2..=2)?, number(s, 2..=2)?),
+        _ => return None,
+    };
+    if hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    Some((hour, minute, second))
+}
+
+fn days_in_month(year: i32, month: u32) -> u32 {
+    match month {
+        2 if (year % 4 == 0 && year % 100 != 0) || year % 400 == 0 => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+impl DateTime {
+    // Days since 1970-01-01, negative before it.
+    pub fn days(&self) -> i64 {
+        // Years counted from March so the leap day comes last
+        let year = self.year as i64 - i64::from(self.month <= 2);
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let month = (self.month as i64 + 9) % 12;
+        let day_of_year = (153 * month + 2) / 5 + self.day as i64 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        era * 146097 + day_of_era - 719468
+    }
+
+    // The date `days` after 1970-01-01.
+    pub fn from_days(days: i64, time: Option<(u32, u32, u32)>) -> Self {
+        let days = days + 719468;
+        let era = days.div_euclid(146097);
+        let day_of_era = days - era * 146097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * month + 2) / 5 + 1) as u32;
+        let month = if month < 10 { month + 3 } else { month - 9 } as u32;
+        let year = (year_of_era + era * 400 + i64::from(month <= 2)) as i32;
+        DateTime {
+            year,
+            month,
+            day,
+            time,
+        }
+    }
+}
+
+// ISO date, followed by the time after a space when there is one.
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)?;
+        if let Some((hour, minute, second)) = self.time {
+            write!(f, " {:02}:{:02}:{:02}", hour, minute, second)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,228 @@
+// Delimiter, quoting and record terminator settings shared by the reader and
+// the writer, plus a sniffer that guesses them from a sample of the input.
+
+use std::{
+    fs::File,
+    io::{Read, Result},
+    path::Path,
+};
+
+// How many bytes of the input the sniffer looks at.
+const SNIFF_LEN: u64 = 8 * 1024;
+const CANDIDATE_DELIMITERS: [u8; 4] = [b',', b';', b'\t', b'|'];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Terminator {
+    // LF or CRLF on read, whichever the input turns out to use on write
+    Auto,
+    Lf,
+    Crlf,
+    // Any other single byte, e.g. CR or RS
+    Byte(u8),
+}
+
+impl Terminator {
+    pub fn sequence(&self) -> String {
+        match self {
+            Terminator::Auto | Terminator::Lf => "\n".to_string(),
+            Terminator::Crlf => "\r\n".to_string(),
+            Terminator::Byte(b) => (*b as char).to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Dialect {
+    pub delimiter: u8,
+    pub quote: u8,
+    // Escapes the next byte instead of doubling quotes when set
+    pub escape: Option<u8>,
+    pub terminator: Terminator,
+}
+
+impl Default for Dialect {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            quote: b'"',
+            escape: None,
+            terminator: Terminator::Auto,
+        }
+    }
+}
+
+impl Dialect {
+    // Guesses the dialect from the first few KB of a file.
+    pub fn sniff_file(path: &Path) -> Result<Self> {
+        let mut sample = Vec::new();
+        File::open(path)?.take(SNIFF_LEN).read_to_end(&mut sample)?;
+        Ok(Self::sniff(&sample))
+    }
+
+    pub fn sniff(sample: &[u8]) -> Self {
+        let mut dialect = Self::default();
+
+        // Files with bare CRs and no LF at all use CR as the terminator.
+        let terminator = if sample.contains(&b'\r') && !sample.contains(&b'\n') {
+            dialect.terminator = Terminator::Byte(b'\r');
+            b'\r'
+        } else {
+            b'\n'
+        };
+
+        // A single quote only wins if it opens fields more often than a double quote.
+        let opens = |q: u8| {
+            sample
+                .windows(2)
+                .filter(|w| {
+                    w[1] == q && (CANDIDATE_DELIMITERS.contains(&w[0]) || w[0] == terminator)
+                })
+                .count()
+                + (sample.first() == Some(&q)) as usize
+        };
+        if opens(b'\'') > opens(b'"') {
+            dialect.quote = b'\'';
+        }
+
+        let backslash_escaped = sample
+            .windows(2)
+            .any(|w| w[0] == b'\\' && w[1] == dialect.quote);
+        let doubled = sample
+            .windows(2)
+            .any(|w| w[0] == dialect.quote && w[1] == dialect.quote);
+        if backslash_escaped && !doubled {
+            dialect.escape = Some(b'\\');
+        }
+
+        // The last line of the sample is usually cut short, so it is ignored.
+        let mut lines: Vec<&[u8]> = sample.split(|b| *b == terminator).collect();
+        if lines.len() > 1 {
+            lines.pop();
+        }
+
+        let mut best: Option<(usize, usize, u8)> = None;
+        for &delimiter in &CANDIDATE_DELIMITERS {
+            let counts: Vec<usize> = lines
+                .iter()
+                .filter(|line| !line.is_empty())
+                .map(|line| count_outside_quotes(line, delimiter, dialect.quote))
+                .collect();
+            let Some(&first) = counts.first() else {
+                continue;
+            };
+            if first == 0 {
+                continue;
+            }
+            // Prefer the delimiter that splits the most lines consistently,
+            // then the one that produces the most fields.
+            let consistent = counts.iter().filter(|c| **c == first).count();
+            let candidate = (consistent, first, delimiter);
+            if best.is_none_or(|b| (candidate.0, candidate.1) > (b.0, b.1)) {
+                best = Some(candidate);
+            }
+        }
+        if let Some((_, _, delimiter)) = best {
+            dialect.delimiter = delimiter;
+        }
+
+        dialect
+    }
+}
+
+// Counts occurrences of `needle` in a line, skipping quoted sections. Quoted
+// line breaks make this approximate, which is good enough for sniffing.
+fn count_outside_quotes(line: &[u8], needle: u8, quote: u8) -> usize {
+    let mut in_quotes = false;
+    let mut count = 0;
+    for &b in line {
+        if b == quote {
+            in_quotes = !in_quotes;
+        } else if b == needle && !in_quotes {
+            count += 1;
+        }
+    }
+    count
+}
+
+// Parses a single-byte option value, accepting escapes such as `\t` and names
+// such as `tab` for characters that are awkward to pass on the command line.
+pub fn parse_byte(value: &str) -> std::result::Result<u8, String> {
+    match value {
+        "\\t" | "tab" => Ok(b'\t'),
+        "\\n" => Ok(b'\n'),
+        "\\r" => Ok(b'\r'),
+        "\\\\" => Ok(b'\\'),
+        "space" => Ok(b' '),
+        _ if value.len() == 1 && value.is_ascii() => Ok(value.as_bytes()[0]),
+        _ => Err(format!(
+            "expected a single ASCII character, got {:?}",
+            value
+        )),
+    }
+}
+
+pub fn parse_terminator(value: &str) -> std::result::Result<Terminator, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "auto" => Ok(Terminator::Auto),
+        "lf" | "\\n" => Ok(Terminator::Lf),
+        "crlf" | "\\r\\n" => Ok(Terminator::Crlf),
+        "cr" => Ok(Terminator::Byte(b'\r')),
+        _ => parse_byte(value).map(|b| match b {
+            b'\n' => Terminator::Lf,
+            b => Terminator::Byte(b),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delimiters() {
+        for delimiter in [b',', b';', b'\t', b'|'] {
+            let sample = "name,price,note\napple,1.5,\"x;y|z\"\npear,2,\n"
+                .replace(',', &(delimiter as char).to_string());
+            let dialect = Dialect::sniff(sample.as_bytes());
+            assert_eq!(dialect.delimiter, delimiter, "{:?}", sample);
+        }
+        // The most consistent one wins over the most frequent
+        let dialect = Dialect::sniff(b"a;b;c,d,e,f\n1;2;3\n4;5;6,7\n");
+        assert_eq!(dialect.delimiter, b';');
+    }
+
+    #[test]
+    fn line_endings() {
+        assert_eq!(
+            Dialect::sniff(b"a,b\r\n1,2\r\n").terminator,
+            Terminator::Auto
+        );
+        let dialect = Dialect::sniff(b"a;b\r1;2\r3;4\r");
+        assert_eq!(dialect.terminator, Terminator::Byte(b'\r'));
+        assert_eq!(dialect.delimiter, b';');
+    }
+
+    #[test]
+    fn quotes_and_escapes() {
+        let dialect = Dialect::sniff(b"'a','b'\n'1,5','x'\n");
+        assert_eq!((dialect.quote, dialect.escape), (b'\'', None));
+
+        let dialect = Dialect::sniff(b"a,b\n\"say \\\"hi\\\" now\",1\n");
+        assert_eq!((dialect.quote, dialect.escape), (b'"', Some(b'\\')));
+        // Doubled quotes mean a backslash before a quote is just data
+        let dialect = Dialect::sniff(b"a,b\n\"C:\\\",\"say \"\"hi\"\"\"\n");
+        assert_eq!(dialect.escape, None);
+    }
+
+    #[test]
+    fn one_column_keeps_the_default() {
+        for sample in [
+            &b"name\nalice\nbob\n"[..],
+            b"\"a, b\"\n\"c; d\"\n",
+            b"x\ny;z\n",
+            b"",
+        ] {
+            assert_eq!(Dialect::sniff(sample), Dialect::default(), "{:?}", sample);
+        }
+    }
+}
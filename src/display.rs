@@ -0,0 +1,749 @@
+// Table formatting shared by the in-memory and streaming display paths.
+//
+// Widths are measured in terminal columns rather than bytes, so accented,
+// CJK and emoji text lines up. Columns whose cells are all numbers are
+// right-aligned unless an alignment is given for them. Cells may span
+// several lines, from line breaks in the data or from wrapping at the
+// maximum column width. Tables wider than the terminal are split into panels
+// of columns or printed a record at a time.
+
+use crate::{
+    schema::parse_float,
+    width::{cluster_width, display_width, graphemes},
+};
+use anyhow::Result;
+use clap::ValueEnum;
+use std::io::{self, BufWriter, Write};
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Align {
+    Left,
+    Right,
+    Center,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Default)]
+pub enum Style {
+    // Cells separated by `|`, the header underlined
+    #[default]
+    Plain,
+    // Full grid drawn with `+`, `-` and `|`
+    Ascii,
+    // Full grid drawn with box-drawing characters
+    Unicode,
+    // Like unicode, with rounded corners
+    Rounded,
+    // GitHub flavoured Markdown table
+    Markdown,
+    // Cells separated by two spaces, the header underlined
+    Compact,
+}
+
+// What happens to cells wider than the maximum column width.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Default)]
+pub enum Overflow {
+    // Cut the cell and end it with an ellipsis
+    #[default]
+    Truncate,
+    // Break the cell into lines at spaces, or anywhere in long words
+    Wrap,
+}
+
+// How a table wider than the terminal is shown.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Default)]
+pub enum Fit {
+    // Split the columns into panels printed one after the other
+    #[default]
+    Panels,
+    // Print every row as a block of `name | value` lines
+    Vertical,
+    // Print the lines however long they are
+    None,
+}
+
+// Rendering settings from the command line.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    // Alignment by 0-based column, automatic where not given
+    pub align: Vec<Option<Align>>,
+    pub style: Style,
+    // Show the data row number in a first column
+    pub row_numbers: bool,
+    // Leave out the rule under the header
+    pub no_header_rule: bool,
+    // Widest a column may get, in terminal columns
+    pub max_width: Option<usize>,
+    pub overflow: Overflow,
+    // Terminal width to fit the table into, None for no limit
+    pub width: Option<usize>,
+    // What to do when the table is wider than the terminal
+    pub fit: Fit,
+    // Columns repeated at the left of every panel
+    pub pin: Vec<usize>,
+    // Unpinned columns to scroll past, showing a single panel
+    pub scroll: Option<usize>,
+}
+
+// A horizontal rule: the ends, the fill and the joints between columns.
+#[derive(Clone)]
+struct Rule {
+    left: &'static str,
+    fill: &'static str,
+    cross: &'static str,
+    right: &'static str,
+}
+
+// The characters a style draws with.
+#[derive(Clone)]
+struct Chars {
+    left: &'static str,
+    separator: &'static str,
+    right: &'static str,
+    top: Option<Rule>,
+    middle: Option<Rule>,
+    bottom: Option<Rule>,
+}
+
+const fn rule(
+    left: &'static str,
+    fill: &'static str,
+    cross: &'static str,
+    right: &'static str,
+) -> Option<Rule> {
+    Some(Rule {
+        left,
+        fill,
+        cross,
+        right,
+    })
+}
+
+impl Style {
+    fn chars(&self) -> Chars {
+        match self {
+            Style::Plain => Chars {
+                left: "",
+                separator: "| ",
+                right: "",
+                top: None,
+                middle: rule("", "-", "+-", ""),
+                bottom: None,
+            },
+            Style::Compact => Chars {
+                left: "",
+                separator: "  ",
+                right: "",
+                top: None,
+                middle: rule("", "-", "  ", ""),
+                bottom: None,
+            },
+            Style::Ascii => Chars {
+                left: "| ",
+                separator: " | ",
+                right: " |",
+                top: rule("+-", "-", "-+-", "-+"),
+                middle: rule("+-", "-", "-+-", "-+"),
+                bottom: rule("+-", "-", "-+-", "-+"),
+            },
+            Style::Unicode => Chars {
+                left: "│ ",
+                separator: " │ ",
+                right: " │",
+                top: rule("┌─", "─", "─┬─", "─┐"),
+                middle: rule("├─", "─", "─┼─", "─┤"),
+                bottom: rule("└─", "─", "─┴─", "─┘"),
+            },
+            Style::Rounded => Chars {
+                left: "│ ",
+                separator: " │ ",
+                right: " │",
+                top: rule("╭─", "─", "─┬─", "─╮"),
+                middle: rule("├─", "─", "─┼─", "─┤"),
+                bottom: rule("╰─", "─", "─┴─", "─╯"),
+            },
+            // The rule under the header carries the alignment, see `Table::rule`
+            Style::Markdown => Chars {
+                left: "| ",
+                separator: " | ",
+                right: " |",
+                top: None,
+                middle: rule("| ", "-", " | ", " |"),
+                bottom: None,
+            },
+        }
+    }
+}
+
+// Markdown cells cannot hold pipes or line breaks and would render HTML, so
+// those are escaped, HTML as by the HTML renderer, and line breaks become <br>.
+fn markdown_cell(cell: &str) -> String {
+    let mut out = String::with_capacity(cell.len());
+    for c in cell.replace("\r\n", "\n").chars() {
+        match c {
+            '|' => out.push_str("\\|"),
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '\n' => out.push_str("<br>"),
+            c => out.push(c),
+        }
+    }
+    visible(&out)
+}
+
+// Like `truncate` on the escaped cell, but cut between source characters so
+// an entity such as `&amp;` or a `<br>` is never split.
+fn truncate_markdown(cell: &str, width: usize) -> String {
+    let mut out = String::new();
+    let mut used = 0;
+    for cluster in graphemes(&cell.replace("\r\n", "\n")) {
+        let escaped = markdown_cell(cluster);
+        let escaped_width = display_width(&escaped);
+        if used + escaped_width + 1 > width {
+            break;
+        }
+        used += escaped_width;
+        out.push_str(&escaped);
+    }
+    out.push('…');
+    out
+}
+
+// Widest line of a cell.
+fn cell_width(cell: &str) -> usize {
+    cell.lines()
+        .map(|line| display_width(&visible(line.trim_end_matches('\r'))))
+        .max()
+        .unwrap_or(0)
+}
+
+// Measures the columns of the rows about to be displayed.
+pub struct Layout {
+    options: Options,
+    widths: Vec<usize>,
+    // Whether every non-empty cell of the column so far is a number, None
+    // until a non-empty cell has been seen
+    numeric: Vec<Option<bool>>,
+    // Number of the first row and count of rows measured, for row numbers
+    first_row: usize,
+    rows: usize,
+}
+
+impl Layout {
+    // The header counts towards the widths but not towards alignment.
+    pub fn new(header: Option<&Vec<String>>, cols: usize, options: &Options) -> Self {
+        let mut layout = Self {
+            options: options.clone(),
+            widths: vec![0; cols],
+            numeric: Vec::new(),
+            first_row: 1,
+            rows: 0,
+        };
+        if let Some(header) = header {
+            layout.update_widths(header);
+        }
+        layout
+    }
+
+    // Numbers rows from `first` rather than 1, as when showing a page.
+    pub fn number_from(mut self, first: usize) -> Self {
+        self.first_row = first;
+        self
+    }
+
+    fn update_widths(&mut self, row: &[String]) {
+        if row.len() > self.widths.len() {
+            self.widths.resize(row.len(), 0);
+        }
+        let markdown = self.options.style == Style::Markdown;
+        for (width, cell) in self.widths.iter_mut().zip(row) {
+            let cell_width = match markdown {
+                true => display_width(&markdown_cell(cell)),
+                false => cell_width(cell),
+            };
+            *width = (*width).max(cell_width);
+        }
+    }
+
+    pub fn measure(&mut self, row: &[String]) {
+        self.rows += 1;
+        self.update_widths(row);
+        if row.len() > self.numeric.len() {
+            self.numeric.resize(row.len(), None);
+        }
+        for (numeric, cell) in self.numeric.iter_mut().zip(row) {
+            if !cell.trim().is_empty() {
+                *numeric = Some(numeric.unwrap_or(true) && parse_float(cell).is_some());
+            }
+        }
+    }
+
+    pub fn finish(self) -> Table {
+        let options = self.options;
+        let aligns: Vec<Align> = (0..self.widths.len())
+            .map(|index| match options.align.get(index).copied().flatten() {
+                Some(align) => align,
+                None if self.numeric.get(index) == Some(&Some(true)) => Align::Right,
+                None => Align::Left,
+            })
+            .collect();
+        // A markdown delimiter row cell needs at least three dashes
+        let least = if options.style == Style::Markdown {
+            3
+        } else {
+            1
+        };
+        let widths: Vec<usize> = self
+            .widths
+            .into_iter()
+            .map(|width| options.max_width.map_or(width, |max| width.min(max.max(1))))
+            .map(|width| width.max(least))
+            .collect();
+        let number_width = options.row_numbers.then(|| {
+            let last = self.first_row + self.rows.saturating_sub(1);
+            last.to_string().len().max(least)
+        });
+        Table {
+            chars: options.style.chars(),
+            columns: (0..widths.len()).collect(),
+            options,
+            number_width,
+            widths,
+            aligns,
+        }
+    }
+}
+
+// Column widths, alignment and style, ready to print rows with.
+#[derive(Clone)]
+pub struct Table {
+    options: Options,
+    chars: Chars,
+    // Width of the row number column, None when it is not shown
+    number_width: Option<usize>,
+    widths: Vec<usize>,
+    aligns: Vec<Align>,
+    // Columns shown, all unless the table has been split into panels
+    columns: Vec<usize>,
+}
+
+impl Table {
+    // Width and alignment of every column shown, the row number first.
+    fn slots(&self) -> Vec<(usize, Align)> {
+        self.number_width
+            .map(|width| (width, Align::Right))
+            .into_iter()
+            .chain(
+                self.columns
+                    .iter()
+                    .map(|&index| (self.widths[index], self.aligns[index])),
+            )
+            .collect()
+    }
+
+    // Width and alignment of a column, for views that draw their own grid.
+    pub fn column_width(&self, col: usize) -> usize {
+        self.widths.get(col).copied().unwrap_or(0)
+    }
+
+    pub fn column_align(&self, col: usize) -> Align {
+        self.aligns.get(col).copied().unwrap_or(Align::Left)
+    }
+
+    // Terminal columns a line of the table takes.
+    pub fn width(&self) -> usize {
+        let slots = self.slots();
+        let cells: usize = slots.iter().map(|(width, _)| width).sum();
+        display_width(self.chars.left)
+            + cells
+            + display_width(self.chars.separator) * slots.len().saturating_sub(1)
+            + display_width(self.chars.right)
+    }
+
+    // Draws a rule, None if the style has none in that place.
+    fn rule(&self, rule: &Option<Rule>) -> Option<String> {
+        let rule = rule.as_ref()?;
+        let markdown = self.options.style == Style::Markdown;
+        let segments: Vec<String> = self
+            .slots()
+            .into_iter()
+            .map(|(width, align)| {
+                let mut segment = rule.fill.repeat(width);
+                if markdown {
+                    // Alignment markers, `:--`, `--:` or `:-:`
+                    if matches!(align, Align::Left | Align::Center) {
+                        segment.replace_range(0..1, ":");
+                    }
+                    if matches!(align, Align::Right | Align::Center) {
+                        segment.replace_range(width - 1..width, ":");
+                    }
+                }
+                segment
+            })
+            .collect();
+        Some(format!(
+            "{}{}{}",
+            rule.left,
+            segments.join(rule.cross),
+            rule.right
+        ))
+    }
+
+    // Fits a cell into its column: one entry per line, each at most `width`
+    // wide.
+    fn fit(&self, cell: &str, width: usize) -> Vec<String> {
+        if self.options.style == Style::Markdown {
+            let escaped = markdown_cell(cell);
+            return vec![match display_width(&escaped) > width {
+                true => truncate_markdown(cell, width),
+                false => escaped,
+            }];
+        }
+        let lines = cell
+            .lines()
+            .map(|line| visible(line.trim_end_matches('\r')));
+        let mut fitted: Vec<String> = match self.options.overflow {
+            Overflow::Wrap => lines.flat_map(|line| wrap(&line, width)).collect(),
+            Overflow::Truncate => lines
+                .map(|line| match display_width(&line) > width {
+                    true => truncate(&line, width),
+                    false => line,
+                })
+                .collect(),
+        };
+        if fitted.is_empty() {
+            fitted.push(String::new());
+        }
+        fitted
+    }
+
+    // Formats the shown columns of a row, with `number` in the row number
+    // column. Short rows are filled with blank cells so the columns stay
+    // aligned.
+    fn format_cells(&self, number: &str, row: &[String]) -> String {
+        let cells = self.number_width.map(|_| number).into_iter().chain(
+            self.columns
+                .iter()
+                .map(|&index| row.get(index).map_or("", String::as_str)),
+        );
+        let slots = self.slots();
+        let lines: Vec<Vec<String>> = cells
+            .zip(&slots)
+            .map(|(cell, &(width, _))| self.fit(cell, width))
+            .collect();
+        let height = lines.iter().map(Vec::len).max().unwrap_or(1);
+        (0..height)
+            .map(|line| {
+                let cells: Vec<String> = lines
+                    .iter()
+                    .zip(&slots)
+                    .map(|(cell, &(width, align))| {
+                        pad(cell.get(line).map_or("", String::as_str), width, align)
+                    })
+                    .collect();
+                format!(
+                    "{}{}{}",
+                    self.chars.left,
+                    cells.join(self.chars.separator),
+                    self.chars.right
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    pub fn format_row(&self, number: usize, row: &[String]) -> String {
+        self.format_cells(&number.to_string(), row)
+    }
+
+    // Column names, made of column numbers when the file has no header.
+    fn names(&self, header: Option<&Vec<String>>) -> Vec<String> {
+        match header {
+            Some(header) => header.clone(),
+            None => (1..=self.widths.len()).map(|n| n.to_string()).collect(),
+        }
+    }
+
+    // The top of the table and the header row, if any, with a rule
+    // separating it from the data. Markdown tables always have a header.
+    pub fn header_lines(&self, header: Option<&Vec<String>>) -> Vec<String> {
+        let mut lines: Vec<String> = self.rule(&self.chars.top).into_iter().collect();
+        if header.is_none() && self.options.style != Style::Markdown {
+            return lines;
+        }
+        lines.extend(
+            self.format_cells("#", &self.names(header))
+                .lines()
+                .map(String::from),
+        );
+        if self.options.style == Style::Markdown || !self.options.no_header_rule {
+            lines.extend(self.rule(&self.chars.middle));
+        }
+        lines
+    }
+
+    // The bottom of the table, if the style draws one.
+    pub fn footer_line(&self) -> Option<String> {
+        self.rule(&self.chars.bottom)
+    }
+
+    fn write_header(&self, out: &mut impl Write, header: Option<&Vec<String>>) -> Result<()> {
+        for line in self.header_lines(header) {
+            writeln!(out, "{}", line)?;
+        }
+        Ok(())
+    }
+
+    fn write_footer(&self, out: &mut impl Write) -> Result<()> {
+        if let Some(bottom) = self.footer_line() {
+            writeln!(out, "{}", bottom)?;
+        }
+        Ok(())
+    }
+
+    fn unpinned(&self) -> Vec<usize> {
+        self.columns
+            .iter()
+            .copied()
+            .filter(|index| !self.options.pin.contains(index))
+            .collect()
+    }
+
+    // The pinned columns and as many of `rest` as fit into `width`, at least
+    // one. Returns the panel and how many of `rest` it took.
+    fn panel(&self, rest: &[usize], width: Option<usize>) -> (Table, usize) {
+        let mut panel = self.clone();
+        panel.columns = self.options.pin.clone();
+        let mut taken = 0;
+        for &index in rest {
+            panel.columns.push(index);
+            if taken > 0 && width.is_some_and(|width| panel.width() > width) {
+                panel.columns.pop();
+                break;
+            }
+            taken += 1;
+        }
+        (panel, taken)
+    }
+
+    // A single panel scrolled past `scroll` unpinned columns.
+    pub fn scrolled(&self, scroll: usize, width: Option<usize>) -> Table {
+        let rest = self.unpinned();
+        self.panel(&rest[scroll.min(rest.len())..], width).0
+    }
+
+    // Number of columns that can be scrolled past.
+    pub fn scrollable(&self) -> usize {
+        self.unpinned().len()
+    }
+
+    // Splits the table into panels that each fit into `width`, every one
+    // repeating the pinned columns.
+    pub fn panels(&self, width: Option<usize>) -> Vec<Table> {
+        let rest = self.unpinned();
+        let mut panels = Vec::new();
+        let mut offset = 0;
+        while offset < rest.len() || panels.is_empty() {
+            let (panel, taken) = self.panel(&rest[offset..], width);
+            panels.push(panel);
+            offset += taken;
+        }
+        panels
+    }
+
+    // Prints the table with the header and the numbered rows `rows` gives,
+    // fitted into the terminal as the options say. `rows` is called again for
+    // every panel.
+    pub fn print<I, R>(
+        &self,
+        header: Option<&Vec<String>>,
+        rows: impl Fn() -> Result<I>,
+    ) -> Result<()>
+    where
+        I: Iterator<Item = Result<(usize, R)>>,
+        R: AsRef<[String]>,
+    {
+        // Errors writing, such as a closed pipe, stop the display
+        let mut out = BufWriter::new(io::stdout().lock());
+        let width = self.options.width;
+        let fits = width.is_none_or(|width| self.width() <= width);
+        let panels = match (self.options.scroll, self.options.fit) {
+            (Some(scroll), _) => vec![self.scrolled(scroll, width)],
+            (None, Fit::Vertical) if !fits => {
+                let width = width.unwrap_or(usize::MAX);
+                self.print_records(&mut out, header, rows()?, width)?;
+                out.flush()?;
+                return Ok(());
+            }
+            (None, Fit::Panels) if !fits => self.panels(width),
+            _ => vec![self.clone()],
+        };
+        for (index, panel) in panels.iter().enumerate() {
+            if index > 0 {
+                writeln!(out)?;
+            }
+            panel.write_header(&mut out, header)?;
+            for row in rows()? {
+                let (number, row) = row?;
+                writeln!(out, "{}", panel.format_row(number, row.as_ref()))?;
+            }
+            panel.write_footer(&mut out)?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    // Prints every row as a block of `name | value` lines, like the expanded
+    // display of psql.
+    fn print_records<I, R>(
+        &self,
+        out: &mut impl Write,
+        header: Option<&Vec<String>>,
+        rows: I,
+        width: usize,
+    ) -> Result<()>
+    where
+        I: Iterator<Item = Result<(usize, R)>>,
+        R: AsRef<[String]>,
+    {
+        let names: Vec<String> = self.names(header).iter().map(|n| visible(n)).collect();
+        let name_width = names
+            .iter()
+            .map(|name| display_width(name))
+            .max()
+            .unwrap_or(0);
+        let value_width = self
+            .widths
+            .iter()
+            .copied()
+            .max()
+            .unwrap_or(0)
+            .min(width.saturating_sub(name_width + 3))
+            .max(1);
+        let plain = Table {
+            options: Options {
+                style: Style::Plain,
+                ..self.options.clone()
+            },
+            ..self.clone()
+        };
+        for row in rows {
+            let (number, row) = row?;
+            let label = format!("-[ RECORD {} ]", number);
+            let fill = (name_width + 1).saturating_sub(label.len());
+            writeln!(
+                out,
+                "{}{}+{}",
+                label,
+                "-".repeat(fill),
+                "-".repeat(value_width + 1)
+            )?;
+            for (index, name) in names.iter().enumerate() {
+                let value = row.as_ref().get(index).map_or("", String::as_str);
+                for (line, text) in plain.fit(value, value_width).iter().enumerate() {
+                    let name = if line == 0 { name.as_str() } else { "" };
+                    writeln!(out, "{} | {}", pad(name, name_width, Align::Left), text)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Pads `cell` with spaces to `width` columns.
+pub fn pad(cell: &str, width: usize, align: Align) -> String {
+    let padding = width.saturating_sub(display_width(cell));
+    let (left, right) = match align {
+        Align::Left => (0, padding),
+        Align::Right => (padding, 0),
+        Align::Center => (padding / 2, padding - padding / 2),
+    };
+    format!("{}{}{}", " ".repeat(left), cell, " ".repeat(right))
+}
+
+// Shows the line breaks of a cell as `↵`, for views with a line per row.
+pub fn single_line(cell: &str) -> String {
+    visible(&cell.replace("\r\n", "↵").replace(['\n', '\r'], "↵"))
+}
+
+// Shows control characters as visible text: a tab as `␉` and the others in
+// caret notation, such as `^[` for escape. Printed as they are, they would
+// take no column while moving the cursor or driving the terminal.
+pub fn visible(text: &str) -> String {
+    if !text.chars().any(char::is_control) {
+        return text.to_string();
+    }
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c as u32 {
+            0x09 => out.push('␉'),
+            0x7F => out.push_str("^?"),
+            code @ 0x00..=0x1F => {
+                out.push('^');
+                out.push(char::from(code as u8 + 0x40));
+            }
+            code @ 0x80..=0x9F => {
+                out.push_str("M-^");
+                out.push(char::from(code as u8 - 0x40));
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+// Cuts text down to `width` columns, the last one taken by an ellipsis.
+pub fn truncate(text: &str, width: usize) -> String {
+    let mut out = String::new();
+    let mut used = 0;
+    for cluster in graphemes(text) {
+        let cluster_width = cluster_width(cluster);
+        if used + cluster_width + 1 > width {
+            break;
+        }
+        used += cluster_width;
+        out.push_str(cluster);
+    }
+    out.push('…');
+    out
+}
+
+// Breaks a line into lines of at most `width` columns, at spaces where
+// possible.
+fn wrap(line: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut current = String::new();
+    let mut used = 0;
+    for word in line.split(' ') {
+        let word_width = display_width(word);
+        let needed = if current.is_empty() {
+            word_width
+        } else {
+            used + 1 + word_width
+        };
+        if needed <= width {
+            if !current.is_empty() {
+                current.push(' ');
+            }
+            current.push_str(word);
+            used = needed;
+            continue;
+        }
+        if !current.is_empty() {
+            lines.push(std::mem::take(&mut current));
+            used = 0;
+        }
+        // Words wider than the column are broken between grapheme clusters
+        for cluster in graphemes(word) {
+            let cluster_width = cluster_width(cluster);
+            if used + cluster_width > width && !current.is_empty() {
+                lines.push(std::mem::take(&mut current));
+                used = 0;
+            }
+            current.push_str(cluster);
+            used += cluster_width;
+        }
+    }
+    lines.push(current);
+    lines
+}
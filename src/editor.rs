@@ -0,0 +1,425 @@
+// Full-screen spreadsheet editor over a file loaded in memory.
+//
+// Every change goes through `CSVManipulation::modify`, `insert` and `delete`,
+// so it is checked exactly like the same change made on the command line,
+// and `:w` writes through `CSVData::save`, keeping untouched rows of CSV
+// files verbatim.
+//
+// Keys: arrows or h/j/k/l move, page up/down move a page, g/G go to the first
+// or last row, Enter or i edits the cell (Enter keeps, Esc or Ctrl-C drops
+// the edit), o and O insert a blank row below or above, D deletes the row,
+// < and > narrow or widen the column, u undoes. `:w` saves, `:q`, q or Ctrl-C
+// quits, `:q!` quits without saving and `:wq` does both.
+
+use crate::{
+    display::{self, pad, single_line, truncate, Align, Layout},
+    schema::Schema,
+    terminal::{self, Key, Screen},
+    width::display_width,
+    writer::QuoteStyle,
+    CSVData, CSVManipulation, Error, FitPolicy,
+};
+use anyhow::{bail, Result};
+use std::{io::IsTerminal, path::PathBuf};
+
+// Widest a column starts out, wider cells are cut until the column is widened.
+const INITIAL_WIDTH: usize = 24;
+
+// A change as it can be undone.
+enum Change {
+    // The row, 1-based, and its cells before the change
+    Cell { row: usize, before: Vec<String> },
+    Inserted { row: usize },
+    Deleted { row: usize, values: Vec<String> },
+}
+
+enum Mode {
+    Normal,
+    // Editing the cell under the cursor, the text typed so far
+    Edit(String),
+    // `:` followed by a command
+    Command(String),
+}
+
+struct Editor<'a> {
+    data: &'a mut CSVData,
+    path: PathBuf,
+    quote_style: Option<QuoteStyle>,
+    // Column types of spreadsheets saved
+    schema: Option<&'a Schema>,
+    widths: Vec<usize>,
+    aligns: Vec<Align>,
+    // Cursor, 0-based
+    row: usize,
+    col: usize,
+    // First row and column on screen
+    top: usize,
+    left: usize,
+    // Terminal rows and columns
+    size: (usize, usize),
+    mode: Mode,
+    undo: Vec<Change>,
+    // Length of the undo stack when last saved
+    saved: usize,
+    message: Option<String>,
+}
+
+impl Editor<'_> {
+    fn rows(&self) -> usize {
+        self.data.data.len()
+    }
+
+    // Rows on screen, below the header and its rule and above the status line.
+    fn height(&self) -> usize {
+        self.size.0.saturating_sub(3).max(1)
+    }
+
+    fn gutter(&self) -> usize {
+        self.rows().max(1).to_string().len()
+    }
+
+    fn cell(&self, row: usize, col: usize) -> &str {
+        self.data
+            .data
+            .get(row)
+            .and_then(|row| row.get(col))
+            .map_or("", String::as_str)
+    }
+
+    // Columns from `left` that fit on screen.
+    fn visible(&self) -> Vec<usize> {
+        let mut used = self.gutter();
+        let mut visible = Vec::new();
+        for col in self.left..self.widths.len() {
+            used += 3 + self.widths[col];
+            if used > self.size.1 && !visible.is_empty() {
+                break;
+            }
+            visible.push(col);
+        }
+        visible
+    }
+
+    // Moves the view so the cursor is on screen.
+    fn follow(&mut self) {
+        self.row = self.row.min(self.rows().saturating_sub(1));
+        self.col = self.col.min(self.widths.len().saturating_sub(1));
+        let height = self.height();
+        if self.row < self.top {
+            self.top = self.row;
+        } else if self.row >= self.top + height {
+            self.top = self.row + 1 - height;
+        }
+        if self.col < self.left {
+            self.left = self.col;
+        }
+        while self.left < self.col && !self.visible().contains(&self.col) {
+            self.left += 1;
+        }
+    }
+
+    fn format_line(&self, gutter: &str, cells: &[(String, bool)], visible: &[usize]) -> String {
+        let mut line = pad(gutter, self.gutter(), Align::Right);
+        for (&col, (text, selected)) in visible.iter().zip(cells) {
+            let width = self.widths[col];
+            let text = single_line(text);
+            let text = match display_width(&text) > width {
+                true => truncate(&text, width),
+                false => text,
+            };
+            let cell = pad(&text, width, self.aligns[col]);
+            match selected {
+                true => line.push_str(&format!(" | \x1b[7m{}\x1b[0m", cell)),
+                false => line.push_str(&format!(" | {}", cell)),
+            }
+        }
+        line
+    }
+
+    fn draw(&self, screen: &Screen) -> Result<()> {
+        let visible = self.visible();
+        let names: Vec<(String, bool)> = visible
+            .iter()
+            .map(|&col| {
+                let name = match &self.data.header {
+                    Some(header) => header.get(col).cloned().unwrap_or_default(),
+                    None => (col + 1).to_string(),
+                };
+                (name, false)
+            })
+            .collect();
+        let mut lines = vec![self.format_line("#", &names, &visible)];
+        let rule: Vec<String> = visible
+            .iter()
+            .map(|&col| "-".repeat(self.widths[col]))
+            .collect();
+        lines.push(format!(
+            "{}-+-{}",
+            "-".repeat(self.gutter()),
+            rule.join("-+-")
+        ));
+        for row in self.top..(self.top + self.height()).min(self.rows()) {
+            let cells: Vec<(String, bool)> = visible
+                .iter()
+                .map(|&col| {
+                    let selected = row == self.row && col == self.col;
+                    match (&self.mode, selected) {
+                        (Mode::Edit(text), true) => (text.clone(), true),
+                        _ => (self.cell(row, col).to_string(), selected),
+                    }
+                })
+                .collect();
+            lines.push(self.format_line(&(row + 1).to_string(), &cells, &visible));
+        }
+        lines.resize(self.size.0.saturating_sub(1), "~".to_string());
+
+        let status = match &self.mode {
+            Mode::Command(text) => format!(":{}", text),
+            Mode::Edit(text) => format!("edit: {}", text),
+            Mode::Normal => {
+                let mut status = match self.rows() {
+                    0 => " no rows, o inserts one".to_string(),
+                    rows => format!(
+                        " row {} of {}, column {} of {}",
+                        self.row + 1,
+                        rows,
+                        self.col + 1,
+                        self.widths.len()
+                    ),
+                };
+                if self.undo.len() != self.saved {
+                    status.push_str(" [modified]");
+                }
+                if let Some(message) = &self.message {
+                    status.push_str(&format!(" | {}", message));
+                }
+                status
+            }
+        };
+
+        let mut frame = String::from("\x1b[H");
+        for line in &lines {
+            frame.push_str(line);
+            frame.push_str("\x1b[K\r\n");
+        }
+        let status = single_line(&status);
+        let status = match display_width(&status) > self.size.1 {
+            true => truncate(&status, self.size.1),
+            false => status,
+        };
+        frame.push_str(&format!("\x1b[7m{}\x1b[0m\x1b[K", status));
+        screen.draw(&frame)?;
+        Ok(())
+    }
+
+    // Replaces the cell under the cursor, keeping the rest of the row.
+    fn set_cell(&mut self, value: String) -> Result<()> {
+        let row = self.row + 1;
+        let before = self.data.data[self.row].clone();
+        let mut after = before.clone();
+        match after.get_mut(self.col) {
+            Some(cell) if *cell == value => return Ok(()),
+            Some(cell) => *cell = value,
+            None => bail!(Error::ColumnIndexOutOfBound {
+                index: self.col + 1,
+                cols: after.len(),
+            }),
+        }
+        self.data.modify(row, None, after)?;
+        self.undo.push(Change::Cell { row, before });
+        Ok(())
+    }
+
+    // Inserts a blank row to become row `at`, 1-based.
+    fn insert_row(&mut self, at: usize) -> Result<()> {
+        let blank = vec![String::new(); self.data.cols];
+        self.data.insert(at, blank, FitPolicy::Error)?;
+        self.undo.push(Change::Inserted { row: at });
+        self.row = at - 1;
+        Ok(())
+    }
+
+    fn delete_row(&mut self) -> Result<()> {
+        let row = self.row + 1;
+        let values = self.data.data[self.row].clone();
+        self.data.delete(row)?;
+        self.undo.push(Change::Deleted { row, values });
+        Ok(())
+    }
+
+    fn undo(&mut self) -> Result<()> {
+        let Some(change) = self.undo.pop() else {
+            self.message = Some("nothing to undo".to_string());
+            return Ok(());
+        };
+        let row = match change {
+            Change::Cell { row, before } => {
+                self.data.modify(row, None, before)?;
+                row
+            }
+            Change::Inserted { row } => {
+                self.data.delete(row)?;
+                row
+            }
+            Change::Deleted { row, values } => {
+                self.data.insert(row, values, FitPolicy::Error)?;
+                row
+            }
+        };
+        self.row = row - 1;
+        // Undoing past the save makes the data differ from the file for good
+        if self.undo.len() < self.saved {
+            self.saved = usize::MAX;
+        }
+        Ok(())
+    }
+
+    fn save(&mut self) -> Result<()> {
+        self.data
+            .save(self.path.clone(), self.quote_style, self.schema)?;
+        self.saved = self.undo.len();
+        self.message = Some(format!("wrote {}", self.path.display()));
+        Ok(())
+    }
+
+    // Runs a `:` command, returning false to quit.
+    fn command(&mut self, command: &str) -> Result<bool> {
+        let modified = self.undo.len() != self.saved;
+        match command.trim() {
+            "w" => self.save()?,
+            "wq" | "x" => {
+                self.save()?;
+                return Ok(false);
+            }
+            "q" if modified => {
+                self.message = Some("unsaved changes, :w saves them, :q! drops them".to_string())
+            }
+            "q" | "q!" => return Ok(false),
+            other => self.message = Some(format!("unknown command :{}", other)),
+        }
+        Ok(true)
+    }
+
+    fn normal_key(&mut self, key: Key) -> Result<bool> {
+        let height = self.height();
+        let has_row = self.row < self.rows();
+        match key {
+            Key::Down | Key::Char('j') => self.row += 1,
+            Key::Up | Key::Char('k') => self.row = self.row.saturating_sub(1),
+            Key::Right | Key::Char('l') => self.col += 1,
+            Key::Left | Key::Char('h') => self.col = self.col.saturating_sub(1),
+            Key::PageDown | Key::Char(' ') => self.row += height,
+            Key::PageUp => self.row = self.row.saturating_sub(height),
+            Key::Home | Key::Char('g') => self.row = 0,
+            Key::End | Key::Char('G') => self.row = self.rows().saturating_sub(1),
+            Key::Enter | Key::Char('i') if has_row && !self.widths.is_empty() => {
+                self.mode = Mode::Edit(self.cell(self.row, self.col).to_string())
+            }
+            Key::Char('o') => self.insert_row((self.row + 2).min(self.rows() + 1))?,
+            Key::Char('O') => self.insert_row(self.row.min(self.rows()) + 1)?,
+            Key::Char('D') if has_row => self.delete_row()?,
+            Key::Char('>') if !self.widths.is_empty() => self.widths[self.col] += 1,
+            Key::Char('<') if !self.widths.is_empty() => {
+                self.widths[self.col] = self.widths[self.col].saturating_sub(1).max(1)
+            }
+            Key::Char('u') => self.undo()?,
+            Key::Char(':') => self.mode = Mode::Command(String::new()),
+            Key::Char('q') | Key::Interrupt => return self.command("q"),
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    // Handles a key, returning false to quit.
+    fn key(&mut self, key: Key) -> Result<bool> {
+        self.message = None;
+        let mode = std::mem::replace(&mut self.mode, Mode::Normal);
+        let result = match (mode, key) {
+            (Mode::Normal, key) => self.normal_key(key),
+            (Mode::Edit(text), Key::Enter) => self.set_cell(text).map(|_| true),
+            (Mode::Command(text), Key::Enter) => self.command(&text),
+            (Mode::Edit(_) | Mode::Command(_), Key::Escape | Key::Interrupt) => Ok(true),
+            (Mode::Edit(mut text), key) => {
+                edit_line(&mut text, key);
+                self.mode = Mode::Edit(text);
+                Ok(true)
+            }
+            (Mode::Command(mut text), key) => {
+                edit_line(&mut text, key);
+                self.mode = Mode::Command(text);
+                Ok(true)
+            }
+        };
+        // A refused change leaves the data as it was, the editor goes on
+        let keep_going = result.unwrap_or_else(|e| {
+            self.message = Some(format!("{:#}", e));
+            true
+        });
+        self.follow();
+        Ok(keep_going)
+    }
+}
+
+fn edit_line(text: &mut String, key: Key) {
+    match key {
+        Key::Char(c) => text.push(c),
+        Key::Backspace => {
+            text.pop();
+        }
+        _ => {}
+    }
+}
+
+// Runs the editor until the user quits. `:w` writes to `path`.
+pub fn run(
+    data: &mut CSVData,
+    path: PathBuf,
+    quote_style: Option<QuoteStyle>,
+    schema: Option<&Schema>,
+    options: &display::Options,
+) -> Result<()> {
+    let size = match terminal::size() {
+        Some(size) if std::io::stdout().is_terminal() => size,
+        _ => bail!(Error::NotATerminal),
+    };
+    let mut layout = Layout::new(data.header.as_ref(), data.cols, options);
+    for row in &data.data {
+        layout.measure(row);
+    }
+    let table = layout.finish();
+    let cols = data
+        .cols
+        .max(data.data.iter().map(Vec::len).max().unwrap_or(0));
+    let widths = (0..cols)
+        .map(|col| table.column_width(col).clamp(1, INITIAL_WIDTH))
+        .collect();
+    let aligns = (0..cols).map(|col| table.column_align(col)).collect();
+    let mut editor = Editor {
+        data,
+        path,
+        quote_style,
+        schema,
+        widths,
+        aligns,
+        row: 0,
+        col: 0,
+        top: 0,
+        left: 0,
+        size,
+        mode: Mode::Normal,
+        undo: Vec::new(),
+        saved: 0,
+        message: None,
+    };
+    let mut screen = Screen::enter()?;
+    loop {
+        editor.draw(&screen)?;
+        let key = screen.read_key()?;
+        if !editor.key(key)? {
+            return Ok(());
+        }
+        if let Some(size) = terminal::size() {
+            editor.size = size;
+        }
+    }
+}
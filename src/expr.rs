@@ -0,0 +1,773 @@
+// A small expression language evaluated against one row at a time.
+//
+// Operands are numbers, double-quoted strings and column references: `$3`
+// for the third column, a bare header name such as `price`, or a backquoted
+// name such as `unit price` for names that are not identifiers. Cells are
+// text until an arithmetic operator needs a number, and `+` concatenates
+// whenever either side is not numeric.
+//
+// Predicates compare values with `== != < <= > >=`, numerically when both
+// sides are numbers and as text otherwise. A number is only ever unequal to
+// text that is not one, so `age < 30` skips rows with no age. Predicates
+// also match values against a regular expression with `~ /^A/` or `!~ /x/i`,
+// and combine with `&&`, `||` and `!`.
+// Given a schema, cells of date and boolean columns compare as dates and
+// booleans, and `true` and `false` are literals unless a column has the name.
+
+use crate::{
+    datetime::{self, DateTime},
+    regex::Regex,
+    schema::{self, ColumnType},
+    Error,
+};
+use anyhow::{bail, Result};
+use std::cmp::Ordering;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Text(String),
+    Bool(bool),
+    // Already resolved to a 0-based column index
+    Column(usize),
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    LeftParen,
+    RightParen,
+    Compare(CompareOp),
+    Match,
+    NotMatch,
+    And,
+    Or,
+    Not,
+    // A `/pattern/` literal, with the `i` flag for case-insensitive matching
+    Pattern(String, bool),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+}
+
+impl BinaryOp {
+    fn symbol(&self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Subtract => "-",
+            BinaryOp::Multiply => "*",
+            BinaryOp::Divide => "/",
+            BinaryOp::Remainder => "%",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompareOp {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+impl CompareOp {
+    fn holds(&self, ordering: Ordering) -> bool {
+        match self {
+            CompareOp::Equal => ordering == Ordering::Equal,
+            CompareOp::NotEqual => ordering != Ordering::Equal,
+            CompareOp::Less => ordering == Ordering::Less,
+            CompareOp::LessEqual => ordering != Ordering::Greater,
+            CompareOp::Greater => ordering == Ordering::Greater,
+            CompareOp::GreaterEqual => ordering != Ordering::Less,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Number(f64),
+    Text(String),
+    Bool(bool),
+    Column(usize),
+    // A column whose cells are read as the schema's type
+    Typed(usize, ColumnType),
+    Negate(Box<Expr>),
+    Binary(Box<Expr>, BinaryOp, Box<Expr>),
+    Compare(Box<Expr>, CompareOp, Box<Expr>),
+    // True when the pattern matches, or when it does not if negated
+    Match(Box<Expr>, Regex, bool),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Number(f64),
+    Text(String),
+    Bool(bool),
+    // Parsed date along with the cell it came from
+    Date(DateTime, String),
+}
+
+impl Value {
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            Value::Text(s) => s.trim().parse().ok(),
+            Value::Bool(_) | Value::Date(..) => None,
+        }
+    }
+
+    fn as_date(&self) -> Option<DateTime> {
+        match self {
+            Value::Date(date, _) => Some(*date),
+            Value::Text(s) => datetime::parse(s),
+            _ => None,
+        }
+    }
+
+    fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            Value::Text(s) => schema::parse_bool(s),
+            _ => None,
+        }
+    }
+
+    // Non-zero numbers and non-empty text count as true.
+    pub fn is_true(&self) -> bool {
+        match self {
+            Value::Number(n) => *n != 0.0,
+            Value::Text(s) => !s.is_empty(),
+            Value::Bool(b) => *b,
+            Value::Date(..) => true,
+        }
+    }
+
+    // Formats the value as a cell, printing whole numbers without a fraction.
+    pub fn into_cell(self) -> String {
+        match self {
+            Value::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => format!("{}", n as i64),
+            Value::Number(n) => n.to_string(),
+            Value::Text(s) => s,
+            Value::Bool(b) => b.to_string(),
+            Value::Date(_, s) => s,
+        }
+    }
+}
+
+fn parse_error(column: usize, reason: impl Into<String>) -> Error {
+    Error::ExpressionParse {
+        column,
+        reason: reason.into(),
+    }
+}
+
+// Splits the source into tokens paired with their 1-based character column.
+fn tokenize(source: &str, resolve: &dyn Fn(&str) -> Result<usize>) -> Result<Vec<(Token, usize)>> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let column = i + 1;
+        let c = chars[i];
+        let token = match c {
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '+' => Token::Plus,
+            '-' => Token::Minus,
+            '*' => Token::Star,
+            '%' => Token::Percent,
+            '(' => Token::LeftParen,
+            ')' => Token::RightParen,
+            '~' => Token::Match,
+            '=' | '!' | '<' | '>' => {
+                let equals = chars.get(i + 1) == Some(&'=');
+                let token = match (c, equals) {
+                    ('=', _) => Token::Compare(CompareOp::Equal),
+                    ('!', true) => Token::Compare(CompareOp::NotEqual),
+                    ('!', false) if chars.get(i + 1) == Some(&'~') => {
+                        i += 1;
+                        Token::NotMatch
+                    }
+                    ('!', false) => Token::Not,
+                    ('<', true) => Token::Compare(CompareOp::LessEqual),
+                    ('<', false) => Token::Compare(CompareOp::Less),
+                    ('>', true) => Token::Compare(CompareOp::GreaterEqual),
+                    _ => Token::Compare(CompareOp::Greater),
+                };
+                // `=` is accepted as well as `==`
+                if equals {
+                    i += 1;
+                }
+                token
+            }
+            '&' | '|' => {
+                if chars.get(i + 1) != Some(&c) {
+                    bail!(parse_error(column, format!("expected '{}{}'", c, c)));
+                }
+                i += 1;
+                if c == '&' {
+                    Token::And
+                } else {
+                    Token::Or
+                }
+            }
+            // After a match operator a slash starts a pattern, not a division
+            '/' if matches!(tokens.last(), Some((Token::Match | Token::NotMatch, _))) => {
+                let mut pattern = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        None => bail!(parse_error(column, "unterminated pattern")),
+                        Some('/') => break,
+                        Some('\\') if chars.get(i + 1) == Some(&'/') => {
+                            pattern.push('/');
+                            i += 2;
+                        }
+                        Some('\\') if i + 1 < chars.len() => {
+                            pattern.push('\\');
+                            pattern.push(chars[i + 1]);
+                            i += 2;
+                        }
+                        Some(&c) => {
+                            pattern.push(c);
+                            i += 1;
+                        }
+                    }
+                }
+                let case_insensitive = chars.get(i + 1) == Some(&'i');
+                if case_insensitive {
+                    i += 1;
+                }
+                Token::Pattern(pattern, case_insensitive)
+            }
+            '/' => Token::Slash,
+            '"' => {
+                let mut text = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        None => bail!(parse_error(column, "unterminated string")),
+                        Some('"') => break,
+                        Some('\\') if i + 1 < chars.len() => {
+                            text.push(chars[i + 1]);
+                            i += 2;
+                        }
+                        Some(&c) => {
+                            text.push(c);
+                            i += 1;
+                        }
+                    }
+                }
+                Token::Text(text)
+            }
+            '`' => {
+                let end = chars[i + 1..]
+                    .iter()
+                    .position(|&c| c == '`')
+                    .ok_or_else(|| parse_error(column, "unterminated column name"))?;
+                let name: String = chars[i + 1..i + 1 + end].iter().collect();
+                i += end + 1;
+                Token::Column(resolve(&name).map_err(|e| parse_error(column, e.to_string()))?)
+            }
+            '$' => {
+                let digits: String = chars[i + 1..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_digit())
+                    .collect();
+                if digits.is_empty() {
+                    bail!(parse_error(column, "expected a column number after '$'"));
+                }
+                i += digits.len();
+                Token::Column(resolve(&digits).map_err(|e| parse_error(column, e.to_string()))?)
+            }
+            c if c.is_ascii_digit() || c == '.' => {
+                let literal: String = chars[i..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_digit() || **c == '.')
+                    .collect();
+                let number = literal
+                    .parse()
+                    .map_err(|_| parse_error(column, format!("invalid number {:?}", literal)))?;
+                i += literal.len() - 1;
+                Token::Number(number)
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let name: String = chars[i..]
+                    .iter()
+                    .take_while(|c| c.is_alphanumeric() || **c == '_')
+                    .collect();
+                i += name.chars().count() - 1;
+                match (resolve(&name), name.as_str()) {
+                    (Ok(index), _) => Token::Column(index),
+                    (Err(_), "true") => Token::Bool(true),
+                    (Err(_), "false") => Token::Bool(false),
+                    (Err(e), _) => bail!(parse_error(column, e.to_string())),
+                }
+            }
+            c => bail!(parse_error(column, format!("unexpected character {:?}", c))),
+        };
+        tokens.push((token, column));
+        i += 1;
+    }
+    Ok(tokens)
+}
+
+// Deepest an expression may nest, in parentheses or in the tree of
+// operations it parses to, which evaluating walks recursively.
+const MAX_DEPTH: usize = 512;
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+    // Column just past the end of the source, for errors at the end
+    end: usize,
+    // Parentheses and prefix operators around the position
+    nesting: usize,
+    // Depth of the tree of the expression parsed last
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(t, _)| t)
+    }
+
+    fn column(&self) -> usize {
+        self.tokens
+            .get(self.position)
+            .map_or(self.end, |(_, column)| *column)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).map(|(t, _)| t.clone());
+        self.position += 1;
+        token
+    }
+
+    fn too_deep(column: usize) -> Error {
+        parse_error(
+            column,
+            format!("the expression nests more than {} levels deep", MAX_DEPTH),
+        )
+    }
+
+    // Depth of an operation on operands of depths `left` and `right`.
+    fn deeper(&mut self, column: usize, left: usize, right: usize) -> Result<usize> {
+        self.depth = left.max(right) + 1;
+        if self.depth > MAX_DEPTH {
+            bail!(Self::too_deep(column));
+        }
+        Ok(self.depth)
+    }
+
+    // Parses what an opening parenthesis or a prefix operator applies to.
+    fn nested(&mut self, column: usize, parse: fn(&mut Self) -> Result<Expr>) -> Result<Expr> {
+        if self.nesting == MAX_DEPTH {
+            bail!(Self::too_deep(column));
+        }
+        self.nesting += 1;
+        let expr = parse(self)?;
+        self.nesting -= 1;
+        Ok(expr)
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        let mut left = self.and()?;
+        let mut depth = self.depth;
+        while self.peek() == Some(&Token::Or) {
+            let column = self.column();
+            self.next();
+            let right = self.and()?;
+            depth = self.deeper(column, depth, self.depth)?;
+            left = Expr::Or(Box::new(left), Box::new(right));
+        }
+        self.depth = depth;
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        let mut left = self.comparison()?;
+        let mut depth = self.depth;
+        while self.peek() == Some(&Token::And) {
+            let column = self.column();
+            self.next();
+            let right = self.comparison()?;
+            depth = self.deeper(column, depth, self.depth)?;
+            left = Expr::And(Box::new(left), Box::new(right));
+        }
+        self.depth = depth;
+        Ok(left)
+    }
+
+    // Comparisons do not chain, `a < b < c` is an error.
+    fn comparison(&mut self) -> Result<Expr> {
+        let left = self.additive()?;
+        let depth = self.depth;
+        let expr = match self.peek() {
+            Some(&Token::Compare(op)) => {
+                let column = self.column();
+                self.next();
+                let right = self.additive()?;
+                self.deeper(column, depth, self.depth)?;
+                Expr::Compare(Box::new(left), op, Box::new(right))
+            }
+            Some(Token::Match | Token::NotMatch) => {
+                let negated = self.next() == Some(Token::NotMatch);
+                let column = self.column();
+                let (pattern, case_insensitive) = match self.next() {
+                    Some(Token::Pattern(pattern, case_insensitive)) => (pattern, case_insensitive),
+                    Some(Token::Text(pattern)) => (pattern, false),
+                    _ => bail!(parse_error(column, "expected a /pattern/ or a string")),
+                };
+                let regex = Regex::new(&pattern, case_insensitive).map_err(|reason| {
+                    parse_error(column, format!("invalid pattern: {}", reason))
+                })?;
+                self.deeper(column, depth, 0)?;
+                Expr::Match(Box::new(left), regex, negated)
+            }
+            _ => return Ok(left),
+        };
+        if matches!(
+            self.peek(),
+            Some(Token::Compare(_) | Token::Match | Token::NotMatch)
+        ) {
+            bail!(parse_error(self.column(), "comparisons cannot be chained"));
+        }
+        Ok(expr)
+    }
+
+    fn additive(&mut self) -> Result<Expr> {
+        let mut left = self.multiplicative()?;
+        let mut depth = self.depth;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Plus) => BinaryOp::Add,
+                Some(Token::Minus) => BinaryOp::Subtract,
+                _ => break,
+            };
+            let column = self.column();
+            self.next();
+            let right = self.multiplicative()?;
+            depth = self.deeper(column, depth, self.depth)?;
+            left = Expr::Binary(Box::new(left), op, Box::new(right));
+        }
+        self.depth = depth;
+        Ok(left)
+    }
+
+    fn multiplicative(&mut self) -> Result<Expr> {
+        let mut left = self.unary()?;
+        let mut depth = self.depth;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Star) => BinaryOp::Multiply,
+                Some(Token::Slash) => BinaryOp::Divide,
+                Some(Token::Percent) => BinaryOp::Remainder,
+                _ => break,
+            };
+            let column = self.column();
+            self.next();
+            let right = self.unary()?;
+            depth = self.deeper(column, depth, self.depth)?;
+            left = Expr::Binary(Box::new(left), op, Box::new(right));
+        }
+        self.depth = depth;
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        let column = self.column();
+        let negate = match self.peek() {
+            Some(Token::Minus) => true,
+            Some(Token::Not) => false,
+            _ => return self.primary(),
+        };
+        self.next();
+        let inner = Box::new(self.nested(column, Self::unary)?);
+        self.deeper(column, self.depth, 0)?;
+        Ok(match negate {
+            true => Expr::Negate(inner),
+            false => Expr::Not(inner),
+        })
+    }
+
+    fn primary(&mut self) -> Result<Expr> {
+        let column = self.column();
+        self.depth = 1;
+        match self.next() {
+            Some(Token::Number(n)) => Ok(Expr::Number(n)),
+            Some(Token::Text(s)) => Ok(Expr::Text(s)),
+            Some(Token::Bool(b)) => Ok(Expr::Bool(b)),
+            Some(Token::Column(index)) => Ok(Expr::Column(index)),
+            Some(Token::LeftParen) => {
+                let expr = self.nested(column, Self::or)?;
+                if self.peek() != Some(&Token::RightParen) {
+                    bail!(parse_error(self.column(), "expected ')'"));
+                }
+                self.next();
+                Ok(expr)
+            }
+            Some(_) => bail!(parse_error(column, "expected a value")),
+            None => bail!(parse_error(column, "unexpected end of expression")),
+        }
+    }
+}
+
+impl Expr {
+    // Parses `source`, resolving column names and numbers with `resolve`.
+    pub fn parse(source: &str, resolve: &dyn Fn(&str) -> Result<usize>) -> Result<Self> {
+        let tokens = tokenize(source, resolve)?;
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+            end: source.chars().count() + 1,
+            nesting: 0,
+            depth: 0,
+        };
+        let expr = parser.or()?;
+        if parser.peek().is_some() {
+            bail!(parse_error(parser.column(), "unexpected token"));
+        }
+        Ok(expr)
+    }
+
+    // Reads the cells of columns with a declared type as that type.
+    pub fn with_types(self, types: &[Option<ColumnType>]) -> Self {
+        let typed = |expr: Box<Expr>| Box::new(expr.with_types(types));
+        match self {
+            Expr::Column(index) => match types.get(index).copied().flatten() {
+                Some(ty) => Expr::Typed(index, ty),
+                None => Expr::Column(index),
+            },
+            Expr::Negate(inner) => Expr::Negate(typed(inner)),
+            Expr::Binary(left, op, right) => Expr::Binary(typed(left), op, typed(right)),
+            Expr::Compare(left, op, right) => Expr::Compare(typed(left), op, typed(right)),
+            Expr::Match(inner, regex, negated) => Expr::Match(typed(inner), regex, negated),
+            Expr::And(left, right) => Expr::And(typed(left), typed(right)),
+            Expr::Or(left, right) => Expr::Or(typed(left), typed(right)),
+            Expr::Not(inner) => Expr::Not(typed(inner)),
+            expr => expr,
+        }
+    }
+
+    pub fn eval(&self, row: &[String]) -> Result<Value> {
+        Ok(match self {
+            Expr::Number(n) => Value::Number(*n),
+            Expr::Text(s) => Value::Text(s.clone()),
+            Expr::Bool(b) => Value::Bool(*b),
+            // Cells past the end of a short row read as empty
+            Expr::Column(index) => Value::Text(row.get(*index).cloned().unwrap_or_default()),
+            // Cells that do not parse as the type stay text
+            Expr::Typed(index, ty) => {
+                let cell = row.get(*index).cloned().unwrap_or_default();
+                match ty {
+                    ColumnType::Boolean => match schema::parse_bool(&cell) {
+                        Some(b) => Value::Bool(b),
+                        None => Value::Text(cell),
+                    },
+                    ColumnType::Date | ColumnType::DateTime => match datetime::parse(&cell) {
+                        Some(date) => Value::Date(date, cell),
+                        None => Value::Text(cell),
+                    },
+                    ColumnType::Integer | ColumnType::Float => match schema::parse_float(&cell) {
+                        Some(n) => Value::Number(n),
+                        None => Value::Text(cell),
+                    },
+                    ColumnType::String => Value::Text(cell),
+                }
+            }
+            Expr::Negate(inner) => {
+                let value = inner.eval(row)?;
+                match value.as_number() {
+                    Some(n) => Value::Number(-n),
+                    None => bail!(Error::ExpressionEval(format!(
+                        "cannot negate {:?}",
+                        value.into_cell()
+                    ))),
+                }
+            }
+            Expr::Binary(left, op, right) => {
+                let left = left.eval(row)?;
+                let right = right.eval(row)?;
+                match (left.as_number(), right.as_number(), op) {
+                    (Some(a), Some(b), BinaryOp::Add) => Value::Number(a + b),
+                    (_, _, BinaryOp::Add) => {
+                        Value::Text(format!("{}{}", left.into_cell(), right.into_cell()))
+                    }
+                    (Some(a), Some(b), BinaryOp::Subtract) => Value::Number(a - b),
+                    (Some(a), Some(b), BinaryOp::Multiply) => Value::Number(a * b),
+                    (Some(_), Some(0.0), BinaryOp::Divide | BinaryOp::Remainder) => {
+                        bail!(Error::ExpressionEval("division by zero".to_string()))
+                    }
+                    (Some(a), Some(b), BinaryOp::Divide) => Value::Number(a / b),
+                    (Some(a), Some(b), BinaryOp::Remainder) => Value::Number(a % b),
+                    _ => bail!(Error::ExpressionEval(format!(
+                        "cannot apply '{}' to {:?} and {:?}",
+                        op.symbol(),
+                        left.into_cell(),
+                        right.into_cell()
+                    ))),
+                }
+            }
+            Expr::Compare(left, op, right) => {
+                let left = left.eval(row)?;
+                let right = right.eval(row)?;
+                // Either side being a date or a boolean decides how the
+                // other is read, falling back to text if it does not parse
+                let typed = match (&left, &right) {
+                    (Value::Date(..), _) | (_, Value::Date(..)) => {
+                        match (left.as_date(), right.as_date()) {
+                            (Some(a), Some(b)) => Some(a.cmp(&b)),
+                            _ => None,
+                        }
+                    }
+                    (Value::Bool(_), _) | (_, Value::Bool(_)) => {
+                        match (left.as_bool(), right.as_bool()) {
+                            (Some(a), Some(b)) => Some(a.cmp(&b)),
+                            _ => None,
+                        }
+                    }
+                    _ => match (left.as_number(), right.as_number()) {
+                        (Some(a), Some(b)) => Some(a.total_cmp(&b)),
+                        _ if matches!(left, Value::Number(_))
+                            || matches!(right, Value::Number(_)) =>
+                        {
+                            return Ok(Value::Bool(*op == CompareOp::NotEqual));
+                        }
+                        _ => None,
+                    },
+                };
+                let ordering = typed.unwrap_or_else(|| left.into_cell().cmp(&right.into_cell()));
+                Value::Bool(op.holds(ordering))
+            }
+            Expr::Match(inner, regex, negated) => {
+                let text = inner.eval(row)?.into_cell();
+                Value::Bool(regex.is_match(&text) != *negated)
+            }
+            Expr::And(left, right) => {
+                Value::Bool(left.eval(row)?.is_true() && right.eval(row)?.is_true())
+            }
+            Expr::Or(left, right) => {
+                Value::Bool(left.eval(row)?.is_true() || right.eval(row)?.is_true())
+            }
+            Expr::Not(inner) => Value::Bool(!inner.eval(row)?.is_true()),
+        })
+    }
+}
+
+// A row predicate, as used by the filter command.
+#[derive(Debug, Clone)]
+pub struct Filter {
+    expr: Expr,
+    // Keep the rows that do not match instead
+    invert: bool,
+}
+
+impl Filter {
+    // `types` holds the declared type of every column, empty without a schema.
+    pub fn new(
+        source: &str,
+        resolve: &dyn Fn(&str) -> Result<usize>,
+        types: &[Option<ColumnType>],
+        invert: bool,
+    ) -> Result<Self> {
+        Ok(Self {
+            expr: Expr::parse(source, resolve)?.with_types(types),
+            invert,
+        })
+    }
+
+    pub fn keeps(&self, row: &[String]) -> Result<bool> {
+        Ok(self.expr.eval(row)?.is_true() != self.invert)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Result<Expr> {
+        let resolve = |name: &str| match name {
+            "a" => Ok(0),
+            "b" => Ok(1),
+            _ => bail!(Error::UnknownColumn(name.to_string())),
+        };
+        Expr::parse(source, &resolve)
+    }
+
+    fn is_parse_error(result: Result<Expr>) -> bool {
+        matches!(
+            result.map_err(|e| e.downcast::<Error>()),
+            Err(Ok(Error::ExpressionParse { .. }))
+        )
+    }
+
+    #[test]
+    fn numbers_do_not_compare_with_text() {
+        let keeps = |source: &str, types: &[Option<ColumnType>], row: &[&str]| {
+            let row: Vec<String> = row.iter().map(|cell| cell.to_string()).collect();
+            let resolve = |name: &str| match name {
+                "a" => Ok(0),
+                _ => bail!(Error::UnknownColumn(name.to_string())),
+            };
+            Filter::new(source, &resolve, types, false)
+                .unwrap()
+                .keeps(&row)
+                .unwrap()
+        };
+        for types in [&[][..], &[Some(ColumnType::Integer)]] {
+            assert!(keeps("a < 30", types, &["12"]));
+            assert!(!keeps("a < 30", types, &["45"]));
+            for cell in ["", "n/a"] {
+                for source in ["a < 30", "a <= 30", "a > 30", "a >= 30", "a == 30"] {
+                    assert!(!keeps(source, types, &[cell]), "{} on {:?}", source, cell);
+                }
+                assert!(keeps("a != 30", types, &[cell]));
+            }
+        }
+        // Text still compares as text
+        assert!(keeps("a < \"b\"", &[], &["a"]));
+        assert!(keeps("a == \"\"", &[], &[""]));
+    }
+
+    // Run on a stack the size of the main thread's, which filters run on,
+    // rather than the smaller one of test threads.
+    #[test]
+    fn deep_nesting_is_rejected() {
+        std::thread::Builder::new()
+            .stack_size(8 << 20)
+            .spawn(deep_nesting)
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    fn deep_nesting() {
+        let parens = |depth: usize| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(parse(&parens(500)).is_ok());
+        assert!(is_parse_error(parse(&parens(20_000))));
+        assert!(parse(&format!("{}1", "!".repeat(500))).is_ok());
+        assert!(is_parse_error(parse(&format!("{}1", "!".repeat(60_000)))));
+        assert!(is_parse_error(parse(&format!("{}1", "- ".repeat(60_000)))));
+        // Chains of operators nest as deep as they are long
+        let chain = |terms: usize| vec!["a == 1"; terms].join(" || ");
+        let expr = parse(&chain(500)).unwrap();
+        assert_eq!(expr.eval(&["2".to_string()]).unwrap(), Value::Bool(false));
+        assert!(is_parse_error(parse(&chain(100_000))));
+        assert!(is_parse_error(parse(&vec!["1"; 100_000].join("+"))));
+    }
+}
@@ -0,0 +1,380 @@
+// Just enough of FlatBuffers for the metadata of Arrow IPC files: reading
+// tables, and building them.
+//
+// Buffers are little-endian. A table starts with the signed offset back to
+// its vtable, which lists where each field sits in the table, 0 for absent
+// ones. Fields holding strings, vectors or other tables are unsigned offsets
+// forward from the field itself, so the builder writes every table before
+// what it refers to.
+
+fn bytes_at<const N: usize>(buf: &[u8], position: usize) -> Option<[u8; N]> {
+    buf.get(position..position.checked_add(N)?)?.try_into().ok()
+}
+
+fn u16_at(buf: &[u8], position: usize) -> Option<u16> {
+    bytes_at(buf, position).map(u16::from_le_bytes)
+}
+
+fn u32_at(buf: &[u8], position: usize) -> Option<u32> {
+    bytes_at(buf, position).map(u32::from_le_bytes)
+}
+
+// A table read from a buffer.
+#[derive(Clone, Copy)]
+pub struct Table<'a> {
+    buf: &'a [u8],
+    position: usize,
+}
+
+// The root table of a buffer.
+pub fn root(buf: &[u8]) -> Option<Table<'_>> {
+    Table::at(buf, u32_at(buf, 0)? as usize)
+}
+
+impl<'a> Table<'a> {
+    fn at(buf: &'a [u8], position: usize) -> Option<Self> {
+        bytes_at::<4>(buf, position)?;
+        Some(Self { buf, position })
+    }
+
+    // Position of field `id` in the buffer, None when it is absent.
+    fn field(&self, id: usize) -> Option<usize> {
+        let back = i32::from_le_bytes(bytes_at(self.buf, self.position)?);
+        let vtable = usize::try_from(self.position as i64 - back as i64).ok()?;
+        let entry = 4 + 2 * id;
+        if entry + 2 > u16_at(self.buf, vtable)? as usize {
+            return None;
+        }
+        match u16_at(self.buf, vtable + entry)? {
+            0 => None,
+            offset => Some(self.position + offset as usize),
+        }
+    }
+
+    fn scalar<const N: usize>(&self, id: usize) -> Option<[u8; N]> {
+        bytes_at(self.buf, self.field(id)?)
+    }
+
+    pub fn u8(&self, id: usize) -> Option<u8> {
+        self.scalar(id).map(u8::from_le_bytes)
+    }
+
+    pub fn bool(&self, id: usize) -> Option<bool> {
+        self.u8(id).map(|value| value != 0)
+    }
+
+    pub fn i16(&self, id: usize) -> Option<i16> {
+        self.scalar(id).map(i16::from_le_bytes)
+    }
+
+    pub fn i32(&self, id: usize) -> Option<i32> {
+        self.scalar(id).map(i32::from_le_bytes)
+    }
+
+    pub fn i64(&self, id: usize) -> Option<i64> {
+        self.scalar(id).map(i64::from_le_bytes)
+    }
+
+    // Position a field refers to.
+    fn target(&self, id: usize) -> Option<usize> {
+        let field = self.field(id)?;
+        field.checked_add(u32_at(self.buf, field)? as usize)
+    }
+
+    pub fn table(&self, id: usize) -> Option<Table<'a>> {
+        Table::at(self.buf, self.target(id)?)
+    }
+
+    pub fn string(&self, id: usize) -> Option<&'a str> {
+        let (start, len) = self.vector(id)?;
+        std::str::from_utf8(self.buf.get(start..start.checked_add(len)?)?).ok()
+    }
+
+    // Start of the elements and length of a vector.
+    fn vector(&self, id: usize) -> Option<(usize, usize)> {
+        let position = self.target(id)?;
+        Some((position + 4, u32_at(self.buf, position)? as usize))
+    }
+
+    pub fn tables(&self, id: usize) -> Option<Vec<Table<'a>>> {
+        let (start, len) = self.vector(id)?;
+        (0..len)
+            .map(|index| {
+                let element = start.checked_add(index * 4)?;
+                Table::at(self.buf, element + u32_at(self.buf, element)? as usize)
+            })
+            .collect()
+    }
+
+    // Elements of a vector of structs `size` bytes long.
+    pub fn structs(&self, id: usize, size: usize) -> Option<Vec<&'a [u8]>> {
+        let (start, len) = self.vector(id)?;
+        let bytes = self
+            .buf
+            .get(start..start.checked_add(len.checked_mul(size)?)?)?;
+        Some(bytes.chunks_exact(size).collect())
+    }
+}
+
+// A field of a table to build.
+#[derive(Clone)]
+pub enum Field {
+    Bool(bool),
+    U8(u8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    String(String),
+    Table(Object),
+    Tables(Vec<Object>),
+    // Structs of `size` bytes each, aligned to 8 bytes
+    Structs { bytes: Vec<u8>, size: usize },
+}
+
+impl Field {
+    // Bytes the field takes in its table.
+    fn size(&self) -> usize {
+        match self {
+            Field::Bool(_) | Field::U8(_) => 1,
+            Field::I16(_) => 2,
+            Field::I64(_) => 8,
+            _ => 4,
+        }
+    }
+}
+
+// A table to build, its fields by id.
+#[derive(Clone, Default)]
+pub struct Object(Vec<(usize, Field)>);
+
+impl Object {
+    pub fn with(mut self, id: usize, field: Field) -> Self {
+        self.0.push((id, field));
+        self
+    }
+}
+
+struct Builder {
+    buf: Vec<u8>,
+}
+
+impl Builder {
+    // Pads until `position % align == rest`.
+    fn pad(&mut self, align: usize, rest: usize) {
+        while self.buf.len() % align != rest {
+            self.buf.push(0);
+        }
+    }
+
+    fn patch(&mut self, field: usize, target: usize) {
+        let offset = (target - field) as u32;
+        self.buf[field..field + 4].copy_from_slice(&offset.to_le_bytes());
+    }
+
+    fn table(&mut self, object: &Object) -> usize {
+        // Largest fields first, each aligned to its size
+        let mut fields: Vec<&(usize, Field)> = object.0.iter().collect();
+        fields.sort_by_key(|(_, field)| std::cmp::Reverse(field.size()));
+        let mut offsets = Vec::with_capacity(fields.len());
+        let mut size = 4usize;
+        for (_, field) in &fields {
+            size = size.next_multiple_of(field.size());
+            offsets.push(size);
+            size += field.size();
+        }
+
+        self.pad(2, 0);
+        let vtable = self.buf.len();
+        let slots = fields.iter().map(|(id, _)| id + 1).max().unwrap_or(0);
+        let mut entries = vec![0u16; slots];
+        for ((id, _), offset) in fields.iter().zip(&offsets) {
+            entries[*id] = *offset as u16;
+        }
+        self.buf.extend((4 + 2 * slots as u16).to_le_bytes());
+        self.buf.extend((size as u16).to_le_bytes());
+        for entry in entries {
+            self.buf.extend(entry.to_le_bytes());
+        }
+
+        self.pad(8, 0);
+        let table = self.buf.len();
+        self.buf.extend(((table - vtable) as i32).to_le_bytes());
+        self.buf.resize(table + size, 0);
+        let mut children = Vec::new();
+        for ((_, field), offset) in fields.iter().zip(offsets) {
+            let position = table + offset;
+            let bytes = match field {
+                Field::Bool(value) => vec![*value as u8],
+                Field::U8(value) => vec![*value],
+                Field::I16(value) => value.to_le_bytes().to_vec(),
+                Field::I32(value) => value.to_le_bytes().to_vec(),
+                Field::I64(value) => value.to_le_bytes().to_vec(),
+                field => {
+                    children.push((position, field));
+                    continue;
+                }
+            };
+            self.buf[position..position + bytes.len()].copy_from_slice(&bytes);
+        }
+        for (position, field) in children {
+            let target = self.child(field);
+            self.patch(position, target);
+        }
+        table
+    }
+
+    fn child(&mut self, field: &Field) -> usize {
+        match field {
+            Field::String(text) => {
+                self.pad(4, 0);
+                let position = self.buf.len();
+                self.buf.extend((text.len() as u32).to_le_bytes());
+                self.buf.extend(text.as_bytes());
+                self.buf.push(0);
+                position
+            }
+            Field::Table(object) => self.table(object),
+            Field::Tables(objects) => {
+                self.pad(4, 0);
+                let position = self.buf.len();
+                self.buf.extend((objects.len() as u32).to_le_bytes());
+                self.buf.resize(position + 4 + 4 * objects.len(), 0);
+                for (index, object) in objects.iter().enumerate() {
+                    let target = self.table(object);
+                    self.patch(position + 4 + 4 * index, target);
+                }
+                position
+            }
+            Field::Structs { bytes, size } => {
+                // The elements after the length are aligned
+                self.pad(8, 4);
+                let position = self.buf.len();
+                self.buf.extend(((bytes.len() / size) as u32).to_le_bytes());
+                self.buf.extend(bytes);
+                position
+            }
+            _ => unreachable!("scalars are written in their table"),
+        }
+    }
+}
+
+// Builds a buffer with `root` as its root table, padded to 8 bytes.
+pub fn finish(root: &Object) -> Vec<u8> {
+    let mut builder = Builder { buf: vec![0; 4] };
+    let table = builder.table(root);
+    builder.patch(0, table);
+    builder.pad(8, 0);
+    builder.buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let child = |name: &str| Object::default().with(0, Field::String(name.to_string()));
+        let mut structs = Vec::new();
+        for value in [1i64, -2] {
+            structs.extend(value.to_le_bytes());
+        }
+        let object = Object::default()
+            .with(0, Field::Bool(true))
+            .with(1, Field::U8(7))
+            .with(2, Field::I16(-3))
+            .with(3, Field::I32(1 << 20))
+            .with(4, Field::I64(-1 << 40))
+            .with(5, Field::String("héllo".to_string()))
+            .with(6, Field::Table(child("one")))
+            .with(7, Field::Tables(vec![child("a"), child("b")]))
+            .with(
+                9,
+                Field::Structs {
+                    bytes: structs,
+                    size: 8,
+                },
+            );
+        let buf = finish(&object);
+        assert_eq!(buf.len() % 8, 0);
+
+        let table = root(&buf).unwrap();
+        assert_eq!(table.bool(0), Some(true));
+        assert_eq!(table.u8(1), Some(7));
+        assert_eq!(table.i16(2), Some(-3));
+        assert_eq!(table.i32(3), Some(1 << 20));
+        assert_eq!(table.i64(4), Some(-1 << 40));
+        assert_eq!(table.string(5), Some("héllo"));
+        assert_eq!(table.table(6).and_then(|t| t.string(0)), Some("one"));
+        let names: Vec<_> = table
+            .tables(7)
+            .unwrap()
+            .iter()
+            .map(|t| t.string(0))
+            .collect();
+        assert_eq!(names, [Some("a"), Some("b")]);
+        // Absent, and beyond the end of the vtable
+        assert_eq!(table.i32(8), None);
+        assert_eq!(table.i32(100), None);
+        let structs = table.structs(9, 8).unwrap();
+        assert_eq!(
+            structs,
+            [&1i64.to_le_bytes()[..], &(-2i64).to_le_bytes()[..]]
+        );
+        // Scalars that need it are aligned to their size
+        let position = table.field(4).unwrap();
+        assert_eq!(position % 8, 0);
+    }
+
+    // A buffer laid out by hand with the vtable after its table, as other
+    // builders place vtables they share between tables.
+    #[test]
+    fn reads_vtables_after_their_table() {
+        #[rustfmt::skip]
+        let buf = [
+            // Root offset
+            4, 0, 0, 0,
+            // Table: offset to the vtable, -12, an i32 and an offset to a string
+            0xf4, 0xff, 0xff, 0xff, 42, 0, 0, 0, 12, 0, 0, 0,
+            // Vtable: its size, the table size, field 0 at 4 and field 1 at 8
+            8, 0, 12, 0, 4, 0, 8, 0,
+            // String
+            2, 0, 0, 0, b'h', b'i', 0, 0,
+        ];
+        let table = root(&buf).unwrap();
+        assert_eq!(table.i32(0), Some(42));
+        assert_eq!(table.string(1), Some("hi"));
+    }
+
+    #[test]
+    fn rejects_corrupt_input() {
+        let root_object = Object::default()
+            .with(0, Field::String("name".to_string()))
+            .with(
+                1,
+                Field::Tables(vec![Object::default().with(0, Field::I64(1))]),
+            );
+        let buf = finish(&root_object);
+        for len in 0..buf.len() {
+            if let Some(table) = root(&buf[..len]) {
+                let _ = table.string(0);
+                let _ = table.tables(1).map(|tables| {
+                    tables.iter().for_each(|t| {
+                        let _ = t.i64(0);
+                    })
+                });
+            }
+        }
+        for position in 0..buf.len() {
+            let mut buf = buf.clone();
+            buf[position] ^= 0xff;
+            if let Some(table) = root(&buf) {
+                let _ = table.string(0);
+                let _ = table.tables(1).map(|tables| {
+                    tables.iter().for_each(|t| {
+                        let _ = t.i64(0);
+                    })
+                });
+            }
+        }
+    }
+}
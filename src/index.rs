@@ -0,0 +1,239 @@
+// Sidecar index of where every record of a file starts, so a row can be read
+// without parsing the records before it.
+//
+// `index` writes it next to the file as `<file>.idx`. Offsets come from the
+// record reader, so quoted fields spanning lines are accounted for. The index
+// remembers the size and modification time of the file and the dialect it
+// was read with; when any of them changes it is ignored until rebuilt.
+//
+// Layout, all numbers little endian: the magic, file size (u64), mtime
+// seconds (u64) and nanoseconds (u32), delimiter, quote, escape flag and
+// byte, terminator byte or 0 for LF/CRLF (u8 each), record count (u64), then
+// the offset and line (u64 each) of the end of the last record followed by
+// those of the start of every record. A record starts where the previous one
+// ended, blank lines before it included.
+
+use crate::{
+    dialect::{Dialect, Terminator},
+    parser::Reader,
+};
+use anyhow::Result;
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+const MAGIC: &[u8; 8] = b"CSVIDX01";
+const HEADER_LEN: u64 = 8 + 8 + 8 + 4 + 5 + 8;
+const ENTRY_LEN: u64 = 16;
+
+pub struct Index {
+    path: PathBuf,
+    records: usize,
+    // Where the last record ends and the trailing blank lines, if any, begin
+    end: (u64, usize),
+}
+
+// Sidecar path of the index of `csv`.
+pub fn path_for(csv: &Path) -> PathBuf {
+    let mut name = csv.file_name().unwrap_or_default().to_os_string();
+    name.push(".idx");
+    csv.with_file_name(name)
+}
+
+// What the index is only valid for: the file as it is now, read with this
+// dialect.
+fn stamp(csv: &Path, dialect: Dialect) -> Result<Vec<u8>> {
+    let metadata = fs::metadata(csv)?;
+    let modified = metadata.modified()?.duration_since(UNIX_EPOCH)?;
+    let mut stamp = MAGIC.to_vec();
+    stamp.extend(metadata.len().to_le_bytes());
+    stamp.extend(modified.as_secs().to_le_bytes());
+    stamp.extend(modified.subsec_nanos().to_le_bytes());
+    let terminator = match dialect.terminator {
+        Terminator::Byte(byte) => byte,
+        // The reader takes LF and CRLF alike unless told of another byte
+        Terminator::Auto | Terminator::Lf | Terminator::Crlf => 0,
+    };
+    stamp.extend([
+        dialect.delimiter,
+        dialect.quote,
+        u8::from(dialect.escape.is_some()),
+        dialect.escape.unwrap_or(0),
+        terminator,
+    ]);
+    Ok(stamp)
+}
+
+fn read_u64(input: &mut impl Read) -> std::io::Result<u64> {
+    let mut bytes = [0; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+impl Index {
+    // Reads every record of `csv` and writes its index, returning the number
+    // of records indexed.
+    pub fn build(csv: &Path, dialect: Dialect) -> Result<usize> {
+        let stamp = stamp(csv, dialect)?;
+        let mut reader = Reader::new(BufReader::new(File::open(csv)?), dialect);
+        let mut starts = Vec::new();
+        let end = loop {
+            let position = reader.position();
+            match reader.next() {
+                Some(record) => {
+                    record?;
+                    starts.push(position);
+                }
+                None => break position,
+            }
+        };
+
+        let path = path_for(csv);
+        let mut out = BufWriter::new(File::create(&path)?);
+        out.write_all(&stamp)?;
+        out.write_all(&(starts.len() as u64).to_le_bytes())?;
+        for (offset, line) in std::iter::once(end).chain(starts.iter().copied()) {
+            out.write_all(&offset.to_le_bytes())?;
+            out.write_all(&(line as u64).to_le_bytes())?;
+        }
+        out.flush()?;
+        Ok(starts.len())
+    }
+
+    // The index of `csv`, None when there is none or it no longer matches the
+    // file or the dialect.
+    pub fn load(csv: &Path, dialect: Dialect) -> Result<Option<Self>> {
+        let path = path_for(csv);
+        let Ok(file) = File::open(&path) else {
+            return Ok(None);
+        };
+        let expected = stamp(csv, dialect)?;
+        let mut input = BufReader::new(file);
+        let mut found = vec![0; expected.len()];
+        if input.read_exact(&mut found).is_err() || found != expected {
+            return Ok(None);
+        }
+        let (Ok(records), Ok(offset), Ok(line)) = (
+            read_u64(&mut input),
+            read_u64(&mut input),
+            read_u64(&mut input),
+        ) else {
+            return Ok(None);
+        };
+        Ok(Some(Self {
+            path,
+            records: records as usize,
+            end: (offset, line as usize),
+        }))
+    }
+
+    // Number of records, the header included.
+    pub fn records(&self) -> usize {
+        self.records
+    }
+
+    // Byte offset and line at which the 0-based `record` starts, or where the
+    // last one ends for `record` equal to the number of records.
+    pub fn start(&self, record: usize) -> Result<(u64, usize)> {
+        if record >= self.records {
+            return Ok(self.end);
+        }
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(
+            HEADER_LEN + ENTRY_LEN * (record as u64 + 1),
+        ))?;
+        let offset = read_u64(&mut file)?;
+        let line = read_u64(&mut file)?;
+        Ok((offset, line as usize))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sort::TempFile;
+    use std::time::{Duration, SystemTime};
+
+    // A CSV file and its index, both removed when dropped.
+    fn indexed(text: &str, dialect: Dialect) -> (TempFile, TempFile) {
+        let csv = TempFile::unique();
+        fs::write(&csv.0, text).unwrap();
+        let index = TempFile(path_for(&csv.0));
+        Index::build(&csv.0, dialect).unwrap();
+        (csv, index)
+    }
+
+    #[test]
+    fn records_are_located() {
+        let (csv, _index) = indexed("a,b\n\"x\ny\",1\n\n2,3\n\n", Dialect::default());
+        let index = Index::load(&csv.0, Dialect::default()).unwrap().unwrap();
+        assert_eq!(index.records(), 3);
+        let starts: Vec<_> = (0..4).map(|record| index.start(record).unwrap()).collect();
+        // The blank line before the last record belongs to it
+        assert_eq!(starts, [(0, 1), (4, 2), (12, 4), (17, 6)]);
+    }
+
+    #[test]
+    fn changed_files_are_not_trusted() {
+        let (csv, _index) = indexed("a,b\n1,2\n", Dialect::default());
+        let loads = || Index::load(&csv.0, Dialect::default()).unwrap().is_some();
+        assert!(loads());
+
+        // Same size, other time
+        let file = File::options().write(true).open(&csv.0).unwrap();
+        let modified = file.metadata().unwrap().modified().unwrap();
+        file.set_modified(modified - Duration::from_secs(60))
+            .unwrap();
+        assert!(!loads());
+
+        // Other size, same time
+        Index::build(&csv.0, Dialect::default()).unwrap();
+        assert!(loads());
+        let modified = fs::metadata(&csv.0).unwrap().modified().unwrap();
+        fs::write(&csv.0, "a,b\n1,2\n3,4\n").unwrap();
+        let file = File::options().write(true).open(&csv.0).unwrap();
+        file.set_modified(modified).unwrap();
+        assert!(!loads());
+
+        // Rebuilt for the new content
+        file.set_modified(SystemTime::now()).unwrap();
+        assert_eq!(Index::build(&csv.0, Dialect::default()).unwrap(), 3);
+        assert!(loads());
+    }
+
+    #[test]
+    fn other_dialects_are_not_trusted() {
+        let (csv, index) = indexed("a;b\n1;2\n", Dialect::default());
+        let loads = |dialect| Index::load(&csv.0, dialect).unwrap().is_some();
+        let dialect = Dialect::default();
+        assert!(!loads(Dialect {
+            delimiter: b';',
+            ..dialect
+        }));
+        assert!(!loads(Dialect {
+            quote: b'\'',
+            ..dialect
+        }));
+        assert!(!loads(Dialect {
+            escape: Some(b'\\'),
+            ..dialect
+        }));
+        assert!(!loads(Dialect {
+            terminator: Terminator::Byte(b'\r'),
+            ..dialect
+        }));
+        // LF and CRLF read the same records
+        assert!(loads(Dialect {
+            terminator: Terminator::Crlf,
+            ..dialect
+        }));
+
+        // A damaged index is ignored too
+        let bytes = fs::read(&index.0).unwrap();
+        fs::write(&index.0, &bytes[..HEADER_LEN as usize - 4]).unwrap();
+        assert!(!loads(dialect));
+    }
+}
@@ -0,0 +1,383 @@
+// Minimal JSON support: string quoting, a value type with a serializer, and
+// a parser reporting the line and column of syntax errors.
+
+use crate::Error;
+
+// Encodes a string as a quoted JSON string literal.
+pub fn quote(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    // The literal as written, so that no digits are lost
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    // Members in source order
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    // A number, or null when it is not finite.
+    pub fn float(n: f64) -> Json {
+        match n.is_finite() {
+            true => Json::Number(number(n)),
+            false => Json::Null,
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Json::Number(n) => n.parse().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Json::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    // Serializes the value, indenting nested arrays and objects by two spaces
+    // per level when `pretty` is set.
+    pub fn to_string(&self, pretty: bool) -> String {
+        let mut out = String::new();
+        self.write(&mut out, pretty, 0);
+        out
+    }
+
+    fn write(&self, out: &mut String, pretty: bool, depth: usize) {
+        let newline = |out: &mut String, depth: usize| {
+            if pretty {
+                out.push('\n');
+                out.push_str(&"  ".repeat(depth));
+            }
+        };
+        match self {
+            Json::Null => out.push_str("null"),
+            Json::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+            Json::Number(n) => out.push_str(n),
+            Json::String(s) => out.push_str(&quote(s)),
+            Json::Array(items) if items.is_empty() => out.push_str("[]"),
+            Json::Object(members) if members.is_empty() => out.push_str("{}"),
+            Json::Array(items) => {
+                out.push('[');
+                for (index, item) in items.iter().enumerate() {
+                    if index > 0 {
+                        out.push(',');
+                    }
+                    newline(out, depth + 1);
+                    item.write(out, pretty, depth + 1);
+                }
+                newline(out, depth);
+                out.push(']');
+            }
+            Json::Object(members) => {
+                out.push('{');
+                for (index, (key, value)) in members.iter().enumerate() {
+                    if index > 0 {
+                        out.push(',');
+                    }
+                    newline(out, depth + 1);
+                    out.push_str(&quote(key));
+                    out.push_str(if pretty { ": " } else { ":" });
+                    value.write(out, pretty, depth + 1);
+                }
+                newline(out, depth);
+                out.push('}');
+            }
+        }
+    }
+}
+
+// Formats a number the way JSON expects, whole numbers without a fraction
+// and non-finite numbers as null.
+pub fn number(n: f64) -> String {
+    if !n.is_finite() {
+        "null".to_string()
+    } else if n.fract() == 0.0 && n.abs() < 1e15 {
+        format!("{}", n as i64)
+    } else {
+        n.to_string()
+    }
+}
+
+// Whether `literal` follows the JSON grammar of numbers: an optional minus,
+// an integer without leading zeros, then an optional fraction and exponent.
+fn is_number(literal: &str) -> bool {
+    let digits =
+        |rest: &str| rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let rest = literal.strip_prefix('-').unwrap_or(literal);
+    let rest = match digits(rest) {
+        0 => return false,
+        n if n > 1 && rest.starts_with('0') => return false,
+        n => &rest[n..],
+    };
+    let rest = match rest.strip_prefix('.') {
+        Some(fraction) if digits(fraction) == 0 => return false,
+        Some(fraction) => &fraction[digits(fraction)..],
+        None => rest,
+    };
+    let rest = match rest.strip_prefix(['e', 'E']) {
+        Some(exponent) => {
+            let exponent = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+            match digits(exponent) {
+                0 => return false,
+                n => &exponent[n..],
+            }
+        }
+        None => rest,
+    };
+    rest.is_empty()
+}
+
+// Arrays and objects nested deeper are rejected rather than overflow the
+// stack of the parser and of everything walking the value.
+pub const MAX_DEPTH: usize = 512;
+
+struct Parser<'a> {
+    source: &'a str,
+    bytes: &'a [u8],
+    position: usize,
+    // Arrays and objects the position is in
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, reason: impl Into<String>) -> Error {
+        let before = &self.source[..self.position];
+        let line = before.matches('\n').count() + 1;
+        let column = before.rsplit('\n').next().map_or(0, |l| l.chars().count()) + 1;
+        Error::InvalidJson {
+            line,
+            column,
+            reason: reason.into(),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self
+            .bytes
+            .get(self.position)
+            .is_some_and(|b| b.is_ascii_whitespace())
+        {
+            self.position += 1;
+        }
+    }
+
+    fn expect(&mut self, literal: &str) -> Result<(), Error> {
+        if !self.source[self.position..].starts_with(literal) {
+            return Err(self.error(format!("expected {:?}", literal)));
+        }
+        self.position += literal.len();
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Json, Error> {
+        self.skip_whitespace();
+        match self.bytes.get(self.position) {
+            None => Err(self.error("unexpected end of input")),
+            Some(b'n') => self.expect("null").map(|_| Json::Null),
+            Some(b't') => self.expect("true").map(|_| Json::Bool(true)),
+            Some(b'f') => self.expect("false").map(|_| Json::Bool(false)),
+            Some(b'"') => self.string().map(Json::String),
+            Some(b'[' | b'{') if self.depth == MAX_DEPTH => Err(self.error(format!(
+                "arrays and objects are nested more than {} levels deep",
+                MAX_DEPTH
+            ))),
+            Some(b'[') => {
+                self.position += 1;
+                let mut items = Vec::new();
+                self.skip_whitespace();
+                if self.bytes.get(self.position) == Some(&b']') {
+                    self.position += 1;
+                    return Ok(Json::Array(items));
+                }
+                loop {
+                    items.push(self.nested()?);
+                    self.skip_whitespace();
+                    match self.bytes.get(self.position) {
+                        Some(b',') => self.position += 1,
+                        Some(b']') => {
+                            self.position += 1;
+                            return Ok(Json::Array(items));
+                        }
+                        _ => return Err(self.error("expected ',' or ']'")),
+                    }
+                }
+            }
+            Some(b'{') => {
+                self.position += 1;
+                let mut members = Vec::new();
+                self.skip_whitespace();
+                if self.bytes.get(self.position) == Some(&b'}') {
+                    self.position += 1;
+                    return Ok(Json::Object(members));
+                }
+                loop {
+                    self.skip_whitespace();
+                    if self.bytes.get(self.position) != Some(&b'"') {
+                        return Err(self.error("expected a string key"));
+                    }
+                    let key = self.string()?;
+                    self.skip_whitespace();
+                    self.expect(":")?;
+                    members.push((key, self.nested()?));
+                    self.skip_whitespace();
+                    match self.bytes.get(self.position) {
+                        Some(b',') => self.position += 1,
+                        Some(b'}') => {
+                            self.position += 1;
+                            return Ok(Json::Object(members));
+                        }
+                        _ => return Err(self.error("expected ',' or '}'")),
+                    }
+                }
+            }
+            Some(b'-' | b'0'..=b'9') => {
+                let start = self.position;
+                while self
+                    .bytes
+                    .get(self.position)
+                    .is_some_and(|b| matches!(b, b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9'))
+                {
+                    self.position += 1;
+                }
+                let literal = &self.source[start..self.position];
+                if !is_number(literal) {
+                    self.position = start;
+                    return Err(self.error(format!("invalid number {:?}", literal)));
+                }
+                Ok(Json::Number(literal.to_string()))
+            }
+            Some(_) => Err(self.error("expected a value")),
+        }
+    }
+
+    // Reads a value in an array or object.
+    fn nested(&mut self) -> Result<Json, Error> {
+        self.depth += 1;
+        let value = self.value();
+        self.depth -= 1;
+        value
+    }
+
+    // Reads a string literal, the position being on its opening quote.
+    fn string(&mut self) -> Result<String, Error> {
+        self.position += 1;
+        let mut out = String::new();
+        loop {
+            let rest = &self.source[self.position..];
+            let Some(c) = rest.chars().next() else {
+                return Err(self.error("unterminated string"));
+            };
+            self.position += c.len_utf8();
+            match c {
+                '"' => return Ok(out),
+                '\\' => {
+                    let escape = self.bytes.get(self.position).copied();
+                    self.position += 1;
+                    match escape {
+                        Some(b'"') => out.push('"'),
+                        Some(b'\\') => out.push('\\'),
+                        Some(b'/') => out.push('/'),
+                        Some(b'b') => out.push('\u{8}'),
+                        Some(b'f') => out.push('\u{c}'),
+                        Some(b'n') => out.push('\n'),
+                        Some(b'r') => out.push('\r'),
+                        Some(b't') => out.push('\t'),
+                        Some(b'u') => {
+                            let mut code = self.hex4()?;
+                            // A high surrogate must be followed by a low one
+                            if (0xD800..0xDC00).contains(&code)
+                                && self.source[self.position..].starts_with("\\u")
+                            {
+                                self.position += 2;
+                                let low = self.hex4()?;
+                                code = 0x10000
+                                    + ((code - 0xD800) << 10)
+                                    + (low.wrapping_sub(0xDC00) & 0x3FF);
+                            }
+                            out.push(char::from_u32(code).unwrap_or('\u{FFFD}'));
+                        }
+                        _ => {
+                            self.position -= 1;
+                            return Err(self.error("invalid escape"));
+                        }
+                    }
+                }
+                c if (c as u32) < 0x20 => {
+                    self.position -= 1;
+                    return Err(self.error("control character in string"));
+                }
+                c => out.push(c),
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, Error> {
+        let digits = self
+            .source
+            .get(self.position..self.position + 4)
+            .filter(|d| d.bytes().all(|b| b.is_ascii_hexdigit()))
+            .ok_or_else(|| self.error("invalid unicode escape"))?;
+        self.position += 4;
+        Ok(u32::from_str_radix(digits, 16).expect("checked hex digits"))
+    }
+}
+
+// Parses a complete JSON document.
+pub fn parse(source: &str) -> Result<Json, Error> {
+    let mut parser = Parser {
+        source,
+        bytes: source.as_bytes(),
+        position: 0,
+        depth: 0,
+    };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.position < source.len() {
+        return Err(parser.error("unexpected data after the value"));
+    }
+    Ok(value)
+}
@@ -10,6 +10,22 @@
 // Paginate display
 // cargo run -- --read-path=./testdata.csv paginate 1 3
 
+// show the second, or the last, page of 10 rows
+// cargo run -- --read-path=./testdata.csv paginate --page 2 --page-size 10
+// cargo run -- --read-path=./testdata.csv paginate --page last --page-size 10
+
+// index a large file once, then jump to a page or edit a row without reading
+// the rows before it; the index is ignored once the file changes
+// cargo run -- --read-path=./big.csv index
+// cargo run -- --read-path=./big.csv paginate 5000000 5000010
+
+// browse the file in a full-screen pager from row 100 (arrows, page up/down,
+// `:` jumps to a row, `/` searches, q quits)
+// cargo run -- --read-path=./testdata.csv view 100
+
+// edit cells in a full-screen spreadsheet, `:w` saves and `:q` quits
+// cargo run -- --read-path=./testdata.csv --has-header edit
+
 // delete row of csv
 // cargo run -- --read-path=./testdata.csv --write-path=./write.csv delete 1 && cat write.csv
 
@@ -19,14 +35,141 @@
 // modify whole row of csv
 // cargo run -- --read-path=./testdata.csv --write-path=./write.csv modify -r 1 -d yolo,this,is,replacement,values,thanks && cat write.csv
 
-use anyhow::{bail, Result};
-use clap::Parser;
+// treat the first row as a header and modify a cell by column name
+// cargo run -- --read-path=./testdata.csv --write-path=./write.csv --has-header modify -r 1 -c carry -d yolo && cat write.csv
+
+// read a semicolon separated file (the dialect is sniffed when no option is given)
+// cargo run -- --read-path=./data.csv --delimiter=';' display
+
+// load the whole file into memory instead of streaming it
+// cargo run -- --read-path=./testdata.csv --in-memory display
+
+// insert a row so it becomes row 2, padding missing cells
+// cargo run -- --read-path=./testdata.csv --write-path=./write.csv insert --at 2 --data a,b,c --fit pad && cat write.csv
+
+// append records piped in on stdin
+// printf 'a,b,c,d,e,f\n' | cargo run -- --read-path=./testdata.csv --write-path=./write.csv append --from-stdin && cat write.csv
+
+// add a computed column, then keep and reorder a few columns
+// cargo run -- --read-path=./data.csv --write-path=./write.csv --has-header add-column --name total --expr 'price * qty'
+// cargo run -- --read-path=./data.csv --write-path=./write.csv --has-header select total,name
+
+// sort by descending price, then by name ignoring case
+// cargo run -- --read-path=./data.csv --write-path=./write.csv --has-header sort --by=-price:numeric,name -i
+
+// show the rows matching a predicate, or count them
+// cargo run -- --read-path=./data.csv --has-header filter 'age > 30 && country == "DE"'
+// cargo run -- --read-path=./data.csv --has-header filter 'name ~ /^a/i' --count
+
+// infer column types, save them and sort by the inferred types
+// cargo run -- --read-path=./data.csv --has-header infer-schema --output schema.json
+// cargo run -- --read-path=./data.csv --write-path=./write.csv --has-header --schema schema.json sort --by joined
+
+// export the rows as JSON objects keyed by the header, numbers and booleans
+// typed from the inferred (or --schema) column types, `address.city` nested
+// as {"address": {"city": ...}}; ndjson writes one object per line
+// cargo run -- --read-path=./data.csv --has-header convert --to json --typed --nest
+// cargo run -- --read-path=./data.csv --has-header convert --to ndjson --output data.ndjson
+
+// read JSON or NDJSON (told by the extension or --input-format) as a table,
+// nested values as dotted columns or JSON text, and save it as CSV
+// cargo run -- --read-path=./data.ndjson display
+// cargo run -- --read-path=./export.txt --input-format=json --flatten=json --write-path=./data.csv modify -r 1 -c name -d Ann
+
+// read the second sheet of a spreadsheet, or the sheet named Sales, and save
+// the rows as a spreadsheet with a styled header and typed cells
+// cargo run -- --read-path=./report.xlsx --sheet=2 --has-header display
+// cargo run -- --read-path=./report.xlsx --sheet=Sales --has-header --write-path=./sales.csv select region,total
+// cargo run -- --read-path=./data.csv --has-header --write-path=./data.xlsx display
+
+// write the rows as Parquet (snappy compressed by default) or Arrow IPC,
+// typed by the inferred or --schema column types, and page through a Parquet
+// or Arrow file as a table, or save it as CSV
+// cargo run -- --read-path=./data.csv --has-header convert --to parquet --compression gzip --row-group-size 100000 --output data.parquet
+// cargo run -- --read-path=./data.csv --has-header --schema schema.json convert --to arrow --compression lz4 --output data.arrow
+// cargo run -- --read-path=./data.parquet paginate --page 2
+// cargo run -- --read-path=./data.arrow --write-path=./data.csv display
+
+// paste the rows into docs as a GitHub flavoured Markdown table, an HTML page
+// or a bare table with CSS classes and a sticky header, or a LaTeX tabular;
+// columns are aligned as in display, numeric ones to the right
+// cargo run -- --read-path=./data.csv --has-header convert --to markdown
+// cargo run -- --read-path=./data.csv --has-header convert --to html --fragment --class "table striped" --sticky-header
+// cargo run -- --read-path=./data.csv --has-header --align name=center convert --to latex --output table.tex
+
+// check every row against the types and constraints of a schema
+// cargo run -- --read-path=./data.csv --has-header validate --schema schema.json
+
+// pad short rows and cut long ones while reading, instead of keeping them
+// cargo run -- --read-path=./ragged.csv --ragged=pad-or-truncate display
+
+// report errors as JSON, the exit code tells which error occurred
+// cargo run -- --read-path=./testdata.csv --error-format=json delete 99; echo $?
+
+// right-align one column and center another, numeric columns are
+// right-aligned on their own
+// cargo run -- --read-path=./data.csv --has-header --align name=right,country=center display
+
+// draw the table with box-drawing characters, numbering the rows and wrapping
+// cells wider than 20 columns (also plain, ascii, rounded, markdown, compact)
+// cargo run -- --read-path=./data.csv --has-header --style=unicode --row-numbers --max-width=20 --overflow=wrap display
+
+// fit a wide table into the terminal: stacked panels repeating the id column,
+// one record per block, or scrolled three columns to the right
+// cargo run -- --read-path=./wide.csv --has-header --pin=id display
+// cargo run -- --read-path=./wide.csv --has-header --layout=vertical display
+// cargo run -- --read-path=./wide.csv --has-header --pin=id --scroll=3 display
+
+// re-quote the whole file (minimal, always, non-numeric, never)
+// cargo run -- --read-path=./testdata.csv --write-path=./write.csv --quote-style=minimal display && cat write.csv
+
+mod arrow;
+mod columns;
+mod compress;
+mod convert;
+mod datetime;
+mod dialect;
+mod display;
+mod editor;
+mod expr;
+mod flatbuffer;
+mod index;
+mod json;
+mod pager;
+mod parquet;
+mod parser;
+mod regex;
+mod render;
+mod schema;
+mod sort;
+mod stream;
+mod terminal;
+mod thrift;
+mod validate;
+mod width;
+mod writer;
+mod xlsx;
+mod zip;
+
+use anyhow::{bail, Context, Result};
+use clap::{Parser, ValueEnum};
+use columns::ColumnOp;
+use dialect::{Dialect, Terminator};
+use display::{Align, Fit, Layout, Overflow, Style};
+use expr::Filter;
+use parser::{Reader, Record};
+use schema::{ColumnType, Inference, Schema};
+use sort::{Sort, SortMode};
 use std::{
     fs::File,
-    io::{BufRead, BufReader, BufWriter, Write},
-    path::PathBuf,
+    io::{self, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
 };
+use stream::CSVStream;
 use thiserror::Error;
+use validate::Validator;
+use writer::{QuoteStyle, Writer};
 
 #[derive(Parser, Debug)]
 struct Args {
@@ -36,6 +179,86 @@ struct Args {
     // Output data to new csv file or update existing one
     #[arg(short, long)]
     write_path: Option<PathBuf>,
+    // Re-quote every record with this policy instead of preserving the input
+    #[arg(long, value_enum)]
+    quote_style: Option<QuoteStyle>,
+    // Field delimiter, e.g. `;`, `|` or `tab` (sniffed from the file when no
+    // dialect option is given)
+    #[arg(long, value_parser = dialect::parse_byte)]
+    delimiter: Option<u8>,
+    // Quote character
+    #[arg(long, value_parser = dialect::parse_byte)]
+    quote: Option<u8>,
+    // Escape character used instead of doubling quotes, e.g. `\\`
+    #[arg(long, value_parser = dialect::parse_byte)]
+    escape: Option<u8>,
+    // Record terminator: lf, crlf, cr or any single character
+    #[arg(long, value_parser = dialect::parse_terminator)]
+    terminator: Option<Terminator>,
+    // Treat the first record as a header row
+    #[arg(long, conflicts_with = "no_header")]
+    has_header: bool,
+    // Treat the first record as data (default)
+    #[arg(long)]
+    no_header: bool,
+    // What to do with records whose field count differs from the first one
+    #[arg(long, value_enum, default_value_t = RaggedPolicy::Keep)]
+    ragged: RaggedPolicy,
+    // Load the whole file up front instead of streaming it from disk on every
+    // pass, trading memory for speed on small files
+    #[arg(long)]
+    in_memory: bool,
+    // Format of the file read, told by its extension by default; files other
+    // than CSV are always loaded into memory and saved as CSV, unless the
+    // write path ends in .xlsx
+    #[arg(long, value_enum)]
+    input_format: Option<convert::InputFormat>,
+    // How nested arrays and objects of JSON input are turned into cells
+    #[arg(long, value_enum, default_value_t = convert::Flatten::Dotted)]
+    flatten: convert::Flatten,
+    // Sheet of .xlsx input, by name or 1-based number, the first by default
+    #[arg(long)]
+    sheet: Option<String>,
+    // Schema file with column types, as written by infer-schema, used by
+    // sort, filter and validate
+    #[arg(long, global = true)]
+    schema: Option<PathBuf>,
+    // Column alignment for display, e.g. `price=right,name=center`;
+    // numeric columns are right-aligned by default
+    #[arg(long, global = true, value_delimiter = ',', value_parser = parse_align)]
+    align: Vec<(String, Align)>,
+    // Table style for display, paginate, filter and infer-schema
+    #[arg(long, global = true, value_enum, default_value_t = Style::Plain)]
+    style: Style,
+    // Show the row number in a first column
+    #[arg(long, global = true)]
+    row_numbers: bool,
+    // Leave out the rule under the header (markdown tables always have it)
+    #[arg(long, global = true)]
+    no_header_rule: bool,
+    // Widest a column may get, in terminal columns
+    #[arg(long, global = true)]
+    max_width: Option<usize>,
+    // What to do with cells wider than --max-width
+    #[arg(long, global = true, value_enum, default_value_t = Overflow::Truncate)]
+    overflow: Overflow,
+    // Terminal width to fit tables into, detected when printing to a terminal
+    #[arg(long, global = true)]
+    width: Option<usize>,
+    // How tables wider than the terminal are shown. Not named --fit, which
+    // insert and append take for rows of the wrong length
+    #[arg(long, global = true, value_enum, default_value_t = Fit::Panels)]
+    layout: Fit,
+    // Key columns repeated at the left of every panel, e.g. `id,name`
+    #[arg(long, global = true, value_delimiter = ',')]
+    pin: Vec<String>,
+    // Scroll past this many columns besides the pinned ones, showing the
+    // columns that follow as far as the terminal is wide
+    #[arg(long, global = true)]
+    scroll: Option<usize>,
+    // How errors are reported on stderr
+    #[arg(long, value_enum, default_value_t = ErrorFormat::Text)]
+    error_format: ErrorFormat,
     // Sub command for handling data in csv file
     #[clap(subcommand)]
     command: Command,
@@ -45,11 +268,34 @@ struct Args {
 enum Command {
     // Display entire file
     Display,
-    // Paginate(display from row xa to xb)
+    // Paginate(display from row xa to xb), or show page N of --page-size rows
     Paginate {
+        #[arg(
+            required_unless_present = "page",
+            requires = "end",
+            conflicts_with = "page"
+        )]
+        start: Option<usize>,
+        #[arg(conflicts_with = "page")]
+        end: Option<usize>,
+        // Page number, 1-based, or `last`
+        #[arg(long, value_parser = parse_page)]
+        page: Option<Page>,
+        #[arg(long, default_value_t = 20, value_parser = parse_page_size)]
+        page_size: usize,
+    },
+    // Write a sidecar index of where every record starts, `<file>.idx`, which
+    // paginate, view, modify and delete then use to seek straight to a row
+    Index,
+    // Browse the file in a full-screen pager, starting at row `start`
+    View {
+        #[arg(default_value_t = 1)]
         start: usize,
-        end: usize,
     },
+    // Edit the file in a full-screen spreadsheet, `:w` saves to --write-path
+    // or else back to --read-path, or next to it as `.csv` for JSON and
+    // spreadsheet input
+    Edit,
     // Delete a row/field
     Delete {
         row_index: usize,
@@ -59,163 +305,978 @@ enum Command {
         #[clap(short, long)]
         row_index: usize,
 
+        // 1-based column index, or a column name when the file has a header
+        #[clap(short, long)]
+        col_index: Option<String>,
+
+        // comma seperated values from cli
+        #[clap(short, long, value_delimiter = ',')]
+        data: Vec<String>,
+    },
+    // Insert a row so that it becomes row `at`
+    Insert {
         #[clap(short, long)]
-        col_index: Option<usize>,
+        at: usize,
 
         // comma seperated values from cli
         #[clap(short, long, value_delimiter = ',')]
         data: Vec<String>,
+
+        #[clap(long, value_enum, default_value_t = FitPolicy::Error)]
+        fit: FitPolicy,
+    },
+    // Append a row, or every record read from stdin, after the last row
+    Append {
+        // comma seperated values from cli
+        #[clap(
+            short,
+            long,
+            value_delimiter = ',',
+            required_unless_present = "from_stdin"
+        )]
+        data: Vec<String>,
+
+        // records in the same dialect as the file
+        #[clap(long, conflicts_with = "data")]
+        from_stdin: bool,
+
+        #[clap(long, value_enum, default_value_t = FitPolicy::Error)]
+        fit: FitPolicy,
+    },
+    // Add a column filled with a fixed value or computed from each row,
+    // e.g. --expr '$2 * price'
+    AddColumn {
+        // header name of the new column
+        #[clap(short, long)]
+        name: Option<String>,
+
+        // column to insert before, defaults to after the last one
+        #[clap(long)]
+        at: Option<String>,
+
+        #[clap(short, long, conflicts_with = "expr")]
+        value: Option<String>,
+
+        #[clap(short, long)]
+        expr: Option<String>,
+    },
+    // Remove a column
+    DropColumn {
+        #[clap(short, long)]
+        col: String,
     },
+    // Change the header name of a column
+    RenameColumn {
+        #[clap(short, long)]
+        col: String,
+
+        #[clap(short, long)]
+        to: String,
+    },
+    // Move a column to the given 1-based position
+    MoveColumn {
+        #[clap(short, long)]
+        col: String,
+
+        #[clap(short, long)]
+        to: usize,
+    },
+    // Keep only the given columns, in the given order
+    Select {
+        #[clap(value_delimiter = ',', required = true)]
+        columns: Vec<String>,
+    },
+    // Stable sort of the data rows, the header stays first
+    Sort {
+        // comma seperated keys: `-col` sorts descending, `col:numeric`
+        // overrides the mode for one key
+        #[clap(short, long, value_delimiter = ',', required = true)]
+        by: Vec<String>,
+
+        // defaults to the key's type in the schema, else lexicographic
+        #[clap(short, long, value_enum)]
+        mode: Option<SortMode>,
+
+        #[clap(short, long)]
+        ignore_case: bool,
+
+        // MiB of rows sorted in memory before spilling to temporary files
+        #[clap(long, default_value_t = 64, value_parser = parse_memory_limit)]
+        memory_limit: usize,
+    },
+    // Keep the rows matching an expression such as
+    // 'age > 30 && country == "DE"' or 'name ~ /^A/', and display them
+    // unless a write path is given
+    Filter {
+        expr: String,
+
+        // keep the rows that do not match instead
+        #[clap(short, long)]
+        invert: bool,
+
+        // print the number of matching rows instead of the rows
+        #[clap(short, long)]
+        count: bool,
+    },
+    // Infer the type of every column, print them as a table or save them
+    // as a schema file for --schema
+    InferSchema {
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+
+        // only look at the first rows
+        #[clap(short, long)]
+        sample: Option<usize>,
+    },
+    // Check every row against the --schema file and list all violations
+    Validate,
+    // Write the rows as JSON, objects keyed by the header or arrays without
+    // one, as Parquet or Arrow IPC with a type per column, or as a Markdown,
+    // HTML or LaTeX table
+    Convert {
+        #[clap(short, long, value_enum)]
+        to: convert::Format,
+
+        // numbers, booleans and nulls as such in JSON, typed by --schema or
+        // else by the inferred column types
+        #[clap(long)]
+        typed: bool,
+
+        // nest dotted column names like `address.city` into objects
+        #[clap(long)]
+        nest: bool,
+
+        // file to write to instead of stdout
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+
+        // compression of Parquet pages or Arrow buffers, snappy for Parquet
+        // and none for Arrow by default
+        #[clap(long, value_enum)]
+        compression: Option<convert::Compression>,
+
+        // rows per Parquet row group or Arrow record batch
+        #[clap(long, default_value_t = 65_536, value_parser = parse_row_group_size)]
+        row_group_size: usize,
+
+        // write only the HTML table, to paste into a page, rather than a
+        // whole document
+        #[clap(long)]
+        fragment: bool,
+
+        // CSS classes of the HTML table, like "table striped"
+        #[clap(long)]
+        class: Option<String>,
+
+        // keep the HTML header row in view while the table scrolls
+        #[clap(long)]
+        sticky_header: bool,
+    },
+}
+
+fn parse_align(spec: &str) -> Result<(String, Align), String> {
+    let (column, align) = spec
+        .rsplit_once('=')
+        .ok_or_else(|| format!("expected <column>=<alignment>, got {:?}", spec))?;
+    let align = Align::from_str(align, true)
+        .map_err(|_| format!("unknown alignment {:?}, use left, right or center", align))?;
+    Ok((column.to_string(), align))
+}
+
+// A page of `paginate --page`.
+#[derive(Clone, Copy, Debug)]
+enum Page {
+    Number(usize),
+    Last,
+}
+
+fn parse_page(page: &str) -> Result<Page, String> {
+    match page {
+        "last" => Ok(Page::Last),
+        number => number
+            .parse()
+            .map(Page::Number)
+            .map_err(|_| format!("expected a page number or `last`, got {:?}", page)),
+    }
+}
+
+fn parse_page_size(size: &str) -> Result<usize, String> {
+    match size.parse() {
+        Ok(0) => Err("a page holds at least one row".to_string()),
+        Ok(size) => Ok(size),
+        Err(_) => Err(format!("expected a number of rows, got {:?}", size)),
+    }
+}
+
+fn parse_memory_limit(limit: &str) -> Result<usize, String> {
+    match limit.parse() {
+        Ok(0) => Err("sorting needs at least 1 MiB of memory".to_string()),
+        Ok(limit) => Ok(limit),
+        Err(_) => Err(format!("expected a number of MiB, got {:?}", limit)),
+    }
+}
+
+fn parse_row_group_size(size: &str) -> Result<usize, String> {
+    match size.parse() {
+        Ok(0) => Err("a row group holds at least one row".to_string()),
+        Ok(size) => Ok(size),
+        Err(_) => Err(format!("expected a number of rows, got {:?}", size)),
+    }
+}
+
+// What to do with new rows whose field count differs from the file's.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum FitPolicy {
+    // Reject the row
+    Error,
+    // Fill short rows with empty cells, reject long ones
+    Pad,
+    // Cut long rows down, reject short ones
+    Truncate,
+    // Pad short rows and truncate long ones
+    PadOrTruncate,
+}
+
+// What to do with records of the input whose field count differs from the
+// first record's.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum RaggedPolicy {
+    // Stop with the location of the first such record
+    Error,
+    // Fill short records with empty cells, stop at long ones
+    Pad,
+    // Cut long records down, stop at short ones
+    Truncate,
+    // Pad short records and truncate long ones
+    PadOrTruncate,
+    // Keep records as they are
+    Keep,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ErrorFormat {
+    // A human readable message
+    Text,
+    // A single JSON object with the error kind, message, exit code and details
+    Json,
 }
 
 // Trait for data manipulation in CSV.
 trait CSVManipulation {
-    fn display(&self);
-    fn paginate(&self, start: usize, end: usize);
-    fn modify(&mut self, row: usize, col: Option<usize>, value: Vec<String>) -> Result<()>;
+    fn display(&self, options: &display::Options) -> Result<()>;
+    fn paginate(&self, start: usize, end: usize, options: &display::Options) -> Result<()>;
+    fn view(&self, start: usize, options: &display::Options) -> Result<()>;
+    fn modify(&mut self, row: usize, col: Option<&str>, value: Vec<String>) -> Result<()>;
     fn delete(&mut self, row: usize) -> Result<()>;
+    fn insert(&mut self, at: usize, values: Vec<String>, fit: FitPolicy) -> Result<()>;
+    fn append(&mut self, values: Vec<String>, fit: FitPolicy) -> Result<()>;
+    fn header(&self) -> Option<&[String]>;
+    fn cols(&self) -> usize;
+    fn reshape(&mut self, op: ColumnOp) -> Result<()>;
+    fn sort(&mut self, sort: Sort) -> Result<()>;
+    fn filter(&mut self, filter: Filter) -> Result<()>;
+    // Number of data rows
+    fn count(&self) -> Result<usize>;
+    // Calls `f` with every data row and its 1-based number until it returns
+    // false
+    fn scan(&self, f: &mut dyn FnMut(usize, &[String]) -> Result<bool>) -> Result<()>;
+
+    fn add_column(
+        &mut self,
+        name: Option<String>,
+        at: Option<&str>,
+        value: Option<String>,
+        expr: Option<&str>,
+    ) -> Result<()> {
+        let op = ColumnOp::add(self.header(), self.cols(), name, at, value, expr)?;
+        self.reshape(op)
+    }
+
+    fn drop_column(&mut self, col: &str) -> Result<()> {
+        let op = ColumnOp::drop(self.header(), self.cols(), col)?;
+        self.reshape(op)
+    }
+
+    fn rename_column(&mut self, col: &str, name: String) -> Result<()> {
+        let op = ColumnOp::rename(self.header(), self.cols(), col, name)?;
+        self.reshape(op)
+    }
+
+    fn move_column(&mut self, col: &str, to: usize) -> Result<()> {
+        let op = ColumnOp::move_to(self.header(), self.cols(), col, to)?;
+        self.reshape(op)
+    }
+
+    fn select(&mut self, cols: &[String]) -> Result<()> {
+        let op = ColumnOp::select(self.header(), self.cols(), cols)?;
+        self.reshape(op)
+    }
 }
 
 //Custom errors
 #[derive(Error, Debug)]
 enum Error {
-    #[error("Row index out of bound")]
-    RowIndexOutOfBound,
-    #[error("Column index out of bound")]
-    ColumnIndexOutOfBound,
-    #[error("Provided values length mismatch")]
-    ValueLengthMismatch,
-    #[error("Replacement values length mismatch")]
-    ReplacementLengthMismatch,
+    #[error("Row index {index} out of bound, {}", valid_range("rows", *rows))]
+    RowIndexOutOfBound { index: usize, rows: usize },
+    #[error("Column index {index} out of bound, {}", valid_range("columns", *cols))]
+    ColumnIndexOutOfBound { index: usize, cols: usize },
+    #[error("Provided values length mismatch, a single cell takes 1 value but {found} were given")]
+    ValueLengthMismatch { found: usize },
+    #[error("Replacement values length mismatch, the row has {expected} fields but {found} values were given")]
+    ReplacementLengthMismatch { expected: usize, found: usize },
+    #[error("Malformed record at line {line}, byte {offset}: {reason}")]
+    MalformedRecord {
+        line: usize,
+        offset: u64,
+        reason: &'static str,
+    },
+    #[error("Field {0:?} cannot be written without quotes")]
+    UnquotableField(String),
+    #[error("Unknown column {0:?}")]
+    UnknownColumn(String),
+    #[error("Field count mismatch, rows have {expected} fields but {found} were given")]
+    FieldCountMismatch { expected: usize, found: usize },
+    #[error("Invalid expression at column {column}: {reason}")]
+    ExpressionParse { column: usize, reason: String },
+    #[error("Expression failed: {0}")]
+    ExpressionEval(String),
+    #[error("The file has no header row, pass --has-header to use column names")]
+    NoHeader,
+    #[error("Invalid JSON at line {line}, column {column}: {reason}")]
+    InvalidJson {
+        line: usize,
+        column: usize,
+        reason: String,
+    },
+    #[error("Invalid schema: {0}")]
+    InvalidSchema(String),
+    #[error("Validation failed with {violations} violations")]
+    ValidationFailed { violations: usize },
+    #[error("Ragged record at line {line}, byte {offset}: it has {found} fields but {expected} were expected")]
+    RaggedRecord {
+        line: usize,
+        offset: u64,
+        expected: usize,
+        found: usize,
+    },
+    #[error("The interactive view needs a terminal on stdout")]
+    NotATerminal,
+    #[error("Invalid row range, end row {end} comes before start row {start}")]
+    InvalidRange { start: usize, end: usize },
+    #[error("Page {page} out of bound, valid pages are 1 to {pages}")]
+    PageOutOfBound { page: usize, pages: usize },
+    #[error("Column {column:?} cannot also be the object holding nested column {nested:?}")]
+    NestingConflict { column: String, nested: String },
+    #[error("JSON record {record} {reason}")]
+    InvalidRecord { record: usize, reason: String },
+    #[error("Invalid .xlsx file: {0}")]
+    InvalidXlsx(String),
+    #[error("Unknown sheet {sheet:?}, the workbook has {sheets}")]
+    UnknownSheet { sheet: String, sheets: String },
+    #[error("Invalid Parquet file: {0}")]
+    InvalidParquet(String),
+    #[error("Invalid Arrow IPC file: {0}")]
+    InvalidArrow(String),
+    #[error("{format} files with {feature} are not supported")]
+    Unsupported {
+        format: &'static str,
+        feature: String,
+    },
+}
+
+fn valid_range(what: &str, count: usize) -> String {
+    match count {
+        0 => format!("there are no {}", what),
+        count => format!("valid {} are 1 to {}", what, count),
+    }
+}
+
+// Process exit codes. 0 is success and 2 is a command line usage error
+// (reported by clap); every `Error` variant has its own code and any other
+// failure, I/O included, has one of the last two.
+//
+//   3  RowIndexOutOfBound         7  MalformedRecord
+//   4  ColumnIndexOutOfBound      8  UnquotableField
+//   5  ValueLengthMismatch        9  UnknownColumn
+//   6  ReplacementLengthMismatch  10 I/O error
+//   11 FieldCountMismatch         12 ExpressionParse
+//   13 ExpressionEval             14 NoHeader
+//   15 InvalidJson                16 InvalidSchema
+//   17 ValidationFailed           18 RaggedRecord
+//   19 NotATerminal               20 InvalidRange
+//   21 PageOutOfBound             22 NestingConflict
+//   23 InvalidRecord              24 InvalidXlsx
+//   25 UnknownSheet               26 InvalidParquet
+//   27 InvalidArrow               28 Unsupported
+//   1  anything else
+const EXIT_FAILURE: u8 = 1;
+const EXIT_IO: u8 = 10;
+
+impl Error {
+    fn exit_code(&self) -> u8 {
+        match self {
+            Error::RowIndexOutOfBound { .. } => 3,
+            Error::ColumnIndexOutOfBound { .. } => 4,
+            Error::ValueLengthMismatch { .. } => 5,
+            Error::ReplacementLengthMismatch { .. } => 6,
+            Error::MalformedRecord { .. } => 7,
+            Error::UnquotableField(_) => 8,
+            Error::UnknownColumn(_) => 9,
+            Error::FieldCountMismatch { .. } => 11,
+            Error::ExpressionParse { .. } => 12,
+            Error::ExpressionEval(_) => 13,
+            Error::NoHeader => 14,
+            Error::InvalidJson { .. } => 15,
+            Error::InvalidSchema(_) => 16,
+            Error::ValidationFailed { .. } => 17,
+            Error::RaggedRecord { .. } => 18,
+            Error::NotATerminal => 19,
+            Error::InvalidRange { .. } => 20,
+            Error::PageOutOfBound { .. } => 21,
+            Error::NestingConflict { .. } => 22,
+            Error::InvalidRecord { .. } => 23,
+            Error::InvalidXlsx(_) => 24,
+            Error::UnknownSheet { .. } => 25,
+            Error::InvalidParquet(_) => 26,
+            Error::InvalidArrow(_) => 27,
+            Error::Unsupported { .. } => 28,
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Error::RowIndexOutOfBound { .. } => "RowIndexOutOfBound",
+            Error::ColumnIndexOutOfBound { .. } => "ColumnIndexOutOfBound",
+            Error::ValueLengthMismatch { .. } => "ValueLengthMismatch",
+            Error::ReplacementLengthMismatch { .. } => "ReplacementLengthMismatch",
+            Error::MalformedRecord { .. } => "MalformedRecord",
+            Error::UnquotableField(_) => "UnquotableField",
+            Error::UnknownColumn(_) => "UnknownColumn",
+            Error::FieldCountMismatch { .. } => "FieldCountMismatch",
+            Error::ExpressionParse { .. } => "ExpressionParse",
+            Error::ExpressionEval(_) => "ExpressionEval",
+            Error::NoHeader => "NoHeader",
+            Error::InvalidJson { .. } => "InvalidJson",
+            Error::InvalidSchema(_) => "InvalidSchema",
+            Error::ValidationFailed { .. } => "ValidationFailed",
+            Error::RaggedRecord { .. } => "RaggedRecord",
+            Error::NotATerminal => "NotATerminal",
+            Error::InvalidRange { .. } => "InvalidRange",
+            Error::PageOutOfBound { .. } => "PageOutOfBound",
+            Error::NestingConflict { .. } => "NestingConflict",
+            Error::InvalidRecord { .. } => "InvalidRecord",
+            Error::InvalidXlsx(_) => "InvalidXlsx",
+            Error::UnknownSheet { .. } => "UnknownSheet",
+            Error::InvalidParquet(_) => "InvalidParquet",
+            Error::InvalidArrow(_) => "InvalidArrow",
+            Error::Unsupported { .. } => "Unsupported",
+        }
+    }
+
+    // Numeric details of the error for machine readable output.
+    fn details(&self) -> Vec<(&'static str, u64)> {
+        match *self {
+            Error::RowIndexOutOfBound { index, rows } => {
+                vec![("index", index as u64), ("rows", rows as u64)]
+            }
+            Error::ColumnIndexOutOfBound { index, cols } => {
+                vec![("index", index as u64), ("cols", cols as u64)]
+            }
+            Error::ValueLengthMismatch { found } => vec![("found", found as u64)],
+            Error::ReplacementLengthMismatch { expected, found } => {
+                vec![("expected", expected as u64), ("found", found as u64)]
+            }
+            Error::MalformedRecord { line, offset, .. } => {
+                vec![("line", line as u64), ("offset", offset)]
+            }
+            Error::FieldCountMismatch { expected, found } => {
+                vec![("expected", expected as u64), ("found", found as u64)]
+            }
+            Error::ExpressionParse { column, .. } => vec![("column", column as u64)],
+            Error::InvalidJson { line, column, .. } => {
+                vec![("line", line as u64), ("column", column as u64)]
+            }
+            Error::ValidationFailed { violations } => vec![("violations", violations as u64)],
+            Error::InvalidRange { start, end } => {
+                vec![("start", start as u64), ("end", end as u64)]
+            }
+            Error::PageOutOfBound { page, pages } => {
+                vec![("page", page as u64), ("pages", pages as u64)]
+            }
+            Error::InvalidRecord { record, .. } => vec![("record", record as u64)],
+            Error::RaggedRecord {
+                line,
+                offset,
+                expected,
+                found,
+            } => vec![
+                ("line", line as u64),
+                ("offset", offset),
+                ("expected", expected as u64),
+                ("found", found as u64),
+            ],
+            Error::UnquotableField(_)
+            | Error::UnknownColumn(_)
+            | Error::ExpressionEval(_)
+            | Error::NoHeader
+            | Error::InvalidSchema(_)
+            | Error::NotATerminal
+            | Error::NestingConflict { .. }
+            | Error::InvalidXlsx(_)
+            | Error::UnknownSheet { .. }
+            | Error::InvalidParquet(_)
+            | Error::InvalidArrow(_)
+            | Error::Unsupported { .. } => Vec::new(),
+        }
+    }
 }
 
 #[allow(dead_code)]
 #[derive(Debug)]
 struct CSVData {
+    // Column names, when the first record is a header row
+    header: Option<Vec<String>>,
+    header_raw: Option<String>,
     data: Vec<Vec<String>>,
     rows: usize,
     cols: usize,
+    // Source text of each row in `data`, None once the row has been changed
+    raw: Vec<Option<String>>,
+    // Blank lines after the last record
+    trailer: String,
+    // Dialect and quoting used by the input, applied to changed rows
+    dialect: Dialect,
+    quote_style: QuoteStyle,
 }
 
 impl CSVData {
-    pub fn from_file(file_path: PathBuf) -> Result<Self> {
+    pub fn from_file(
+        file_path: PathBuf,
+        mut dialect: Dialect,
+        has_header: bool,
+        ragged: RaggedPolicy,
+    ) -> Result<Self> {
         let file = File::open(file_path)?;
         let reader = BufReader::new(file);
 
+        let mut header = None;
+        let mut header_raw = None;
         let mut data = Vec::new();
+        let mut raw = Vec::new();
         let mut rows = 0;
         let mut cols = 0;
+        let mut all_quoted = true;
 
-        for (index, line) in reader.lines().enumerate() {
-            let line = line?;
-            let row: Vec<String> = line.split(',').map(|s| s.trim().to_string()).collect();
+        let mut records = Reader::new(reader, dialect);
+        for (index, record) in records.by_ref().enumerate() {
+            let mut record = record?;
+            fit_record(&mut record, cols, ragged)?;
             if index == 0 {
-                cols = row.len();
+                cols = record.fields.len();
+                if dialect.terminator == Terminator::Auto {
+                    dialect.terminator = if record.raw.ends_with("\r\n") {
+                        Terminator::Crlf
+                    } else {
+                        Terminator::Lf
+                    };
+                }
+            }
+            all_quoted &= record.quoted;
+            if index == 0 && has_header {
+                header = Some(record.fields);
+                header_raw = Some(record.raw);
+                continue;
             }
-            data.push(row);
+            raw.push((!record.fitted).then_some(record.raw));
+            data.push(record.fields);
             rows += 1
         }
 
-        Ok(Self { data, rows, cols })
+        let quote_style = if all_quoted && (rows > 0 || header.is_some()) {
+            QuoteStyle::Always
+        } else {
+            QuoteStyle::Minimal
+        };
+
+        Ok(Self {
+            header,
+            header_raw,
+            data,
+            rows,
+            cols,
+            raw,
+            trailer: records.trailer(),
+            dialect,
+            quote_style,
+        })
     }
 
-    fn to_file(&self, file_path: PathBuf) -> Result<()> {
-        let file = File::create(file_path)?;
-        let mut writer = BufWriter::new(file);
+    // Table read from another format, written out as CSV in `dialect`.
+    fn from_rows(header: Option<Vec<String>>, rows: Vec<Vec<String>>, dialect: Dialect) -> Self {
+        let cols = match &header {
+            Some(header) => header.len(),
+            None => rows.first().map_or(0, Vec::len),
+        };
+        Self {
+            header_raw: None,
+            rows: rows.len(),
+            cols,
+            raw: vec![None; rows.len()],
+            data: rows,
+            header,
+            trailer: String::new(),
+            dialect,
+            quote_style: QuoteStyle::Minimal,
+        }
+    }
 
-        for row in &self.data {
-            let line = row.join(",");
-            writeln!(writer, "{}", line)?;
+    // Writes a spreadsheet when `file_path` ends in .xlsx, typing the cells
+    // by the schema or else by the inferred column types, and CSV otherwise.
+    fn save(
+        &self,
+        file_path: PathBuf,
+        quote_style: Option<QuoteStyle>,
+        schema: Option<&Schema>,
+    ) -> Result<()> {
+        if !xlsx::is_xlsx(&file_path) {
+            return self.to_file(file_path, quote_style);
         }
-        Ok(())
+        let types = match schema {
+            Some(schema) => schema.column_types(self.header(), self.cols())?,
+            None => inferred_types(self)?,
+        };
+        writer::replace_file(&file_path, |temp_path| {
+            xlsx::write(temp_path, self.header.as_deref(), &self.data, &types)
+        })
+    }
+
+    // Untouched rows are written back verbatim unless `quote_style` asks for
+    // every record to be re-encoded, so read then write of an unmodified file
+    // reproduces it byte for byte.
+    fn to_file(&self, file_path: PathBuf, quote_style: Option<QuoteStyle>) -> Result<()> {
+        writer::replace_file(&file_path, |temp_path| {
+            self.write_to(File::create(temp_path)?, quote_style)
+        })
     }
 
-    fn calculate_max_col_width(&self) -> Vec<usize> {
-        let mut columns_width = vec![0; self.cols];
-        for row in &self.data {
-            for (i, cell) in row.iter().enumerate() {
-                columns_width[i] = columns_width[i].max(cell.len());
+    fn write_to(&self, file: File, quote_style: Option<QuoteStyle>) -> Result<()> {
+        let style = quote_style.unwrap_or(self.quote_style);
+        let mut writer = Writer::new(BufWriter::new(file), style, self.dialect);
+
+        let header = self.header.as_ref().map(|h| (h, &self.header_raw));
+        for (row, raw) in header.into_iter().chain(self.data.iter().zip(&self.raw)) {
+            match raw {
+                Some(raw) if quote_style.is_none() => writer.write_raw(raw)?,
+                _ => writer.write_record(row)?,
             }
         }
-        columns_width
+        if quote_style.is_none() {
+            writer.write_raw(&self.trailer)?;
+        }
+        writer.flush()
     }
 
-    fn format_row(&self, row: &[String], columns_width: &[usize]) -> String {
-        row.iter()
-            .enumerate()
-            .map(|(index, cell)| {
-                let space_padding = columns_width[index] - cell.len();
-                format!("{}{}", cell, " ".repeat(space_padding))
-            })
-            .collect::<Vec<_>>()
-            .join("| ")
+    fn column_index(&self, column: &str) -> Result<usize> {
+        resolve_column(self.header.as_deref(), self.cols, column)
+    }
+
+    fn layout<'a>(
+        &self,
+        rows: impl Iterator<Item = &'a Vec<String>>,
+        options: &display::Options,
+    ) -> Layout {
+        let mut layout = Layout::new(self.header.as_ref(), self.cols, options);
+        for row in rows {
+            layout.measure(row);
+        }
+        layout
     }
 }
 
-impl CSVManipulation for CSVData {
-    fn display(&self) {
-        let columns_width = self.calculate_max_col_width();
-        for row in &self.data {
-            println!("{}", self.format_row(row, &columns_width))
+// Resolves a column given by header name or 1-based index to a 0-based index.
+fn resolve_column(header: Option<&[String]>, cols: usize, column: &str) -> Result<usize> {
+    if let Some(index) = header.and_then(|header| header.iter().position(|name| name == column)) {
+        return Ok(index);
+    }
+    match column.parse::<usize>() {
+        Ok(index) if index == 0 || index > cols => {
+            bail!(Error::ColumnIndexOutOfBound { index, cols })
         }
+        Ok(index) => Ok(index - 1),
+        Err(_) => bail!(Error::UnknownColumn(column.to_string())),
     }
+}
 
-    fn paginate(&self, start: usize, end: usize) {
-        let columns_width = self.calculate_max_col_width();
-        let buffer_end = end + 1;
-        for row in self.data.iter().skip(start - 1).take(buffer_end - start) {
-            println!("{}", self.format_row(row, &columns_width))
+// Replaces a single cell when `col` is given, otherwise the whole row.
+fn apply_modification(
+    row: &mut Vec<String>,
+    col: Option<usize>,
+    values: Vec<String>,
+) -> Result<()> {
+    match (col, values.len()) {
+        (Some(index), 1) => {
+            if index >= row.len() {
+                bail!(Error::ColumnIndexOutOfBound {
+                    index: index + 1,
+                    cols: row.len(),
+                });
+            }
+            row[index] = values[0].clone();
+        }
+        (None, new_values) => {
+            if new_values == row.len() {
+                *row = values;
+            } else {
+                bail!(Error::ReplacementLengthMismatch {
+                    expected: row.len(),
+                    found: new_values,
+                });
+            }
+        }
+        (Some(_), found) => {
+            bail!(Error::ValueLengthMismatch { found })
+        }
+    }
+    Ok(())
+}
+
+// Checks a new row against the file's column count, padding or truncating it
+// as the policy allows. Any row fits a file that has no columns yet.
+fn fit_fields(mut values: Vec<String>, cols: usize, fit: FitPolicy) -> Result<Vec<String>> {
+    let found = values.len();
+    if cols == 0 || found == cols {
+        return Ok(values);
+    }
+    match fit {
+        FitPolicy::Pad | FitPolicy::PadOrTruncate if found < cols => {
+            values.resize(cols, String::new())
         }
+        FitPolicy::Truncate | FitPolicy::PadOrTruncate if found > cols => values.truncate(cols),
+        _ => bail!(Error::FieldCountMismatch {
+            expected: cols,
+            found
+        }),
+    }
+    Ok(values)
+}
+
+// Applies the ragged-row policy to a record read from the input. `expected`
+// is the field count of the first record, 0 while reading that one.
+fn fit_record(record: &mut Record, expected: usize, ragged: RaggedPolicy) -> Result<()> {
+    let found = record.fields.len();
+    if expected == 0 || found == expected || ragged == RaggedPolicy::Keep {
+        return Ok(());
+    }
+    match ragged {
+        RaggedPolicy::Pad | RaggedPolicy::PadOrTruncate if found < expected => {
+            record.fields.resize(expected, String::new())
+        }
+        RaggedPolicy::Truncate | RaggedPolicy::PadOrTruncate if found > expected => {
+            record.fields.truncate(expected)
+        }
+        _ => bail!(Error::RaggedRecord {
+            line: record.line,
+            offset: record.offset,
+            expected,
+            found,
+        }),
+    }
+    record.fitted = true;
+    Ok(())
+}
+
+impl CSVManipulation for CSVData {
+    fn display(&self, options: &display::Options) -> Result<()> {
+        let table = self.layout(self.data.iter(), options).finish();
+        table.print(self.header.as_ref(), || {
+            Ok(self
+                .data
+                .iter()
+                .enumerate()
+                .map(|(index, row)| Ok((index + 1, row))))
+        })
+    }
+
+    fn paginate(&self, start: usize, end: usize, options: &display::Options) -> Result<()> {
+        let buffer_end = end + 1;
+        let page = || self.data.iter().skip(start - 1).take(buffer_end - start);
+        let table = self.layout(page(), options).number_from(start).finish();
+        table.print(self.header.as_ref(), || {
+            Ok(page()
+                .enumerate()
+                .map(|(index, row)| Ok((start + index, row))))
+        })
+    }
+
+    fn view(&self, start: usize, options: &display::Options) -> Result<()> {
+        let header = self.header.as_ref();
+        pager::run(&mut self.data.as_slice(), header, self.cols, start, options)
     }
 
     fn modify(
         &mut self,
         row_index: usize,
-        col_index: Option<usize>,
+        col_index: Option<&str>,
         values: Vec<String>,
     ) -> Result<()> {
         if row_index == 0 || row_index > self.data.len() {
-            bail!(Error::RowIndexOutOfBound);
+            bail!(Error::RowIndexOutOfBound {
+                index: row_index,
+                rows: self.data.len(),
+            });
         }
 
-        match (col_index, values.len()) {
-            (Some(index), 1) => {
-                if index == 0 || index > self.data[row_index - 1].len() {
-                    bail!(Error::ColumnIndexOutOfBound);
-                }
-                self.data[row_index - 1][index - 1] = format!("\"{}\"", values[0]);
-            }
-            (None, new_values) => {
-                if new_values == self.data[row_index - 1].len() {
-                    self.data[row_index - 1] =
-                        values.iter().map(|d| format!("\"{}\"", d)).collect();
-                } else {
-                    bail!(Error::ReplacementLengthMismatch);
-                }
-            }
-            _ => {
-                bail!(Error::ValueLengthMismatch)
-            }
-        }
+        let col = col_index
+            .map(|column| self.column_index(column))
+            .transpose()?;
+        apply_modification(&mut self.data[row_index - 1], col, values)?;
+        self.raw[row_index - 1] = None;
         Ok(())
     }
 
     fn delete(&mut self, row_index: usize) -> Result<()> {
         if row_index == 0 || row_index > self.data.len() {
-            bail!(Error::RowIndexOutOfBound)
+            bail!(Error::RowIndexOutOfBound {
+                index: row_index,
+                rows: self.data.len(),
+            })
         }
         self.data.remove(row_index - 1);
+        self.raw.remove(row_index - 1);
+        Ok(())
+    }
+
+    fn insert(&mut self, at: usize, values: Vec<String>, fit: FitPolicy) -> Result<()> {
+        if at == 0 || at > self.data.len() + 1 {
+            bail!(Error::RowIndexOutOfBound {
+                index: at,
+                rows: self.data.len() + 1,
+            })
+        }
+        let row = fit_fields(values, self.cols, fit)?;
+        if self.cols == 0 {
+            self.cols = row.len();
+        }
+        self.data.insert(at - 1, row);
+        self.raw.insert(at - 1, None);
+        Ok(())
+    }
+
+    fn append(&mut self, values: Vec<String>, fit: FitPolicy) -> Result<()> {
+        self.insert(self.data.len() + 1, values, fit)
+    }
+
+    fn header(&self) -> Option<&[String]> {
+        self.header.as_deref()
+    }
+
+    fn cols(&self) -> usize {
+        self.cols
+    }
+
+    fn reshape(&mut self, op: ColumnOp) -> Result<()> {
+        if let Some(header) = &mut self.header {
+            op.apply(header, true)?;
+            self.header_raw = None;
+        }
+        if op.changes_rows() {
+            for (index, (row, raw)) in self.data.iter_mut().zip(&mut self.raw).enumerate() {
+                op.apply(row, false)
+                    .with_context(|| format!("In row {}", index + 1))?;
+                *raw = None;
+            }
+        }
+        self.cols = op.cols_after(self.cols);
+        Ok(())
+    }
+
+    // Rows move together with their source text, so they are written back
+    // verbatim in the new order
+    fn sort(&mut self, sort: Sort) -> Result<()> {
+        let mut rows: Vec<_> = self.data.drain(..).zip(self.raw.drain(..)).collect();
+        sort.sort_by_fields(&mut rows, |(row, _)| row);
+        (self.data, self.raw) = rows.into_iter().unzip();
+        Ok(())
+    }
+
+    fn filter(&mut self, filter: Filter) -> Result<()> {
+        let mut rows = Vec::with_capacity(self.data.len());
+        for (index, row) in self.data.drain(..).zip(self.raw.drain(..)).enumerate() {
+            if filter
+                .keeps(&row.0)
+                .with_context(|| format!("In row {}", index + 1))?
+            {
+                rows.push(row);
+            }
+        }
+        (self.data, self.raw) = rows.into_iter().unzip();
+        Ok(())
+    }
+
+    fn count(&self) -> Result<usize> {
+        Ok(self.data.len())
+    }
+
+    fn scan(&self, f: &mut dyn FnMut(usize, &[String]) -> Result<bool>) -> Result<()> {
+        for (index, row) in self.data.iter().enumerate() {
+            if !f(index + 1, row)? {
+                break;
+            }
+        }
         Ok(())
     }
 }
 
 //Example usage of trait bounds
-fn display_data<T: CSVManipulation>(data: &T) {
-    data.display();
+fn display_data<T: CSVManipulation>(data: &T, options: &display::Options) -> Result<()> {
+    data.display(options)
 }
 
-fn paginate_data<T: CSVManipulation>(data: &T, start: usize, end: usize) {
-    data.paginate(start, end);
+fn paginate_data<T: CSVManipulation>(
+    data: &T,
+    start: usize,
+    end: usize,
+    options: &display::Options,
+) -> Result<()> {
+    if end < start {
+        bail!(Error::InvalidRange { start, end });
+    }
+    let rows = data.count()?;
+    if start == 0 || start > rows {
+        bail!(Error::RowIndexOutOfBound { index: start, rows });
+    }
+    data.paginate(start, end, options)
+}
+
+// Shows page `page` of `page_size` rows with a footer locating it in the file.
+fn paginate_page<T: CSVManipulation>(
+    data: &T,
+    page: Page,
+    page_size: usize,
+    options: &display::Options,
+) -> Result<()> {
+    let rows = data.count()?;
+    let pages = rows.div_ceil(page_size).max(1);
+    let page = match page {
+        Page::Number(page) if page == 0 || page > pages => {
+            bail!(Error::PageOutOfBound { page, pages })
+        }
+        Page::Number(page) => page,
+        Page::Last => pages,
+    };
+    let start = (page - 1) * page_size + 1;
+    let end = (page * page_size).min(rows);
+    data.paginate(start, end, options)?;
+    let mut out = io::stdout().lock();
+    match rows {
+        0 => writeln!(out, "page 1 of 1, no rows")?,
+        rows => writeln!(
+            out,
+            "page {} of {}, rows {}–{} of {}",
+            page, pages, start, end, rows
+        )?,
+    }
+    Ok(())
 }
 
 fn delete_row<T: CSVManipulation>(data: &mut T, row_index: usize) -> Result<()> {
@@ -226,39 +1287,779 @@ fn delete_row<T: CSVManipulation>(data: &mut T, row_index: usize) -> Result<()>
 fn modify_row<T: CSVManipulation>(
     data: &mut T,
     row_index: usize,
-    col_index: Option<usize>,
+    col_index: Option<&str>,
     values: Vec<String>,
 ) -> Result<()> {
     data.modify(row_index, col_index, values)?;
     Ok(())
 }
 
-fn main() {
-    let args = Args::parse();
-    let mut csv_data = CSVData::from_file(args.read_path).unwrap();
-    match args.command {
-        Command::Display => display_data(&csv_data),
-        Command::Paginate { start, end } => paginate_data(&csv_data, start, end),
+fn insert_row<T: CSVManipulation>(
+    data: &mut T,
+    at: usize,
+    values: Vec<String>,
+    fit: FitPolicy,
+) -> Result<()> {
+    data.insert(at, values, fit)?;
+    Ok(())
+}
+
+fn append_rows<T: CSVManipulation>(
+    data: &mut T,
+    rows: impl IntoIterator<Item = Result<Vec<String>>>,
+    fit: FitPolicy,
+) -> Result<()> {
+    for row in rows {
+        data.append(row?, fit)?;
+    }
+    Ok(())
+}
+
+// Declared type of every column, empty without a schema.
+fn column_types<T: CSVManipulation>(
+    data: &T,
+    schema: Option<&Schema>,
+) -> Result<Vec<Option<schema::ColumnType>>> {
+    match schema {
+        Some(schema) => schema.column_types(data.header(), data.cols()),
+        None => Ok(Vec::new()),
+    }
+}
+
+// Resolves the column names of the display settings.
+fn display_options<T: CSVManipulation>(data: &T, settings: &Settings) -> Result<display::Options> {
+    let mut align = vec![None; data.cols()];
+    for (column, alignment) in &settings.align {
+        let index = resolve_column(data.header(), data.cols(), column)?;
+        align[index] = Some(*alignment);
+    }
+    let pin = settings
+        .pin
+        .iter()
+        .map(|column| resolve_column(data.header(), data.cols(), column))
+        .collect::<Result<_>>()?;
+    Ok(display::Options {
+        align,
+        pin,
+        ..settings.display.clone()
+    })
+}
+
+fn filter_rows<T: CSVManipulation>(
+    data: &mut T,
+    expr: &str,
+    invert: bool,
+    count: bool,
+    settings: &Settings,
+) -> Result<()> {
+    let types = column_types(data, settings.schema.as_ref())?;
+    let (header, cols) = (data.header(), data.cols());
+    let resolve = |column: &str| resolve_column(header, cols, column);
+    let filter = Filter::new(expr, &resolve, &types, invert)?;
+    data.filter(filter)?;
+    if count {
+        writeln!(io::stdout(), "{}", data.count()?)?;
+    } else if !settings.writes {
+        data.display(&display_options(data, settings)?)?;
+    }
+    Ok(())
+}
+
+fn build_index(settings: &Settings) -> Result<()> {
+    let records = index::Index::build(&settings.read_path, settings.dialect)?;
+    let path = index::path_for(&settings.read_path);
+    writeln!(
+        io::stdout(),
+        "Indexed {} records into {}",
+        records,
+        path.display()
+    )?;
+    Ok(())
+}
+
+// Prints the inferred schema as a table, or saves it to `output`.
+fn infer_schema<T: CSVManipulation>(
+    data: &T,
+    output: Option<&Path>,
+    sample: Option<usize>,
+    settings: &Settings,
+) -> Result<()> {
+    let mut inference = Inference::new(data.cols());
+    data.scan(&mut |row_index, row| {
+        inference.add(row);
+        Ok(sample.is_none_or(|sample| row_index < sample))
+    })?;
+    let schema = inference.finish(data.header());
+
+    if let Some(path) = output {
+        let mut json = schema.to_json().to_string(true);
+        json.push('\n');
+        std::fs::write(path, json)?;
+        return Ok(());
+    }
+    let header = ["column", "type", "nullable", "confidence"].map(String::from);
+    let rows: Vec<Vec<String>> = schema
+        .columns
+        .iter()
+        .map(|column| {
+            vec![
+                column.name.clone(),
+                column.ty.name().to_string(),
+                if column.nullable { "yes" } else { "no" }.to_string(),
+                format!("{:.1}%", column.confidence.unwrap_or(0.0) * 100.0),
+            ]
+        })
+        .collect();
+    let header = header.to_vec();
+    let mut layout = Layout::new(Some(&header), header.len(), &settings.display);
+    for row in &rows {
+        layout.measure(row);
+    }
+    layout.finish().print(Some(&header), || {
+        Ok(rows
+            .iter()
+            .enumerate()
+            .map(|(index, row)| Ok((index + 1, row))))
+    })
+}
+
+// Type of every column, inferred from all rows.
+fn inferred_types<T: CSVManipulation>(data: &T) -> Result<Vec<Option<schema::ColumnType>>> {
+    let mut inference = Inference::new(data.cols());
+    data.scan(&mut |_, row| {
+        inference.add(row);
+        Ok(true)
+    })?;
+    let schema = inference.finish(data.header());
+    Ok(schema
+        .columns
+        .iter()
+        .map(|column| Some(column.ty))
+        .collect())
+}
+
+// Writes the rows as JSON to `output`, or stdout.
+fn convert_rows<T: CSVManipulation>(
+    data: &T,
+    format: convert::Format,
+    typed: bool,
+    nest: bool,
+    output: Option<&Path>,
+    schema: Option<&Schema>,
+) -> Result<()> {
+    let types = match (typed, schema) {
+        (false, _) => Vec::new(),
+        (true, Some(schema)) => schema.column_types(data.header(), data.cols())?,
+        (true, None) => inferred_types(data)?,
+    };
+    let out: Box<dyn Write> = match output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+    let mut writer = convert::JsonWriter::new(out, format, data.header(), nest, types)?;
+    data.scan(&mut |_, row| {
+        writer.write(row)?;
+        Ok(true)
+    })?;
+    writer.finish()?;
+    Ok(())
+}
+
+// Writes the rows as a Markdown, HTML or LaTeX table, measuring them first.
+fn render_rows<T: CSVManipulation>(
+    data: &T,
+    format: convert::Format,
+    html: render::Html,
+    output: Option<&Path>,
+    settings: &Settings,
+) -> Result<()> {
+    let options = display_options(data, settings)?;
+    let mut measure = render::Measure::new(format, data.header(), data.cols(), &options);
+    data.scan(&mut |number, row| {
+        measure.add(number, row);
+        Ok(true)
+    })?;
+    let out: Box<dyn Write> = match output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+    let mut renderer = measure.finish(out, html);
+    renderer.start()?;
+    data.scan(&mut |number, row| {
+        renderer.write(number, row)?;
+        Ok(true)
+    })?;
+    renderer.finish()
+}
+
+// Rows as typed columns for Parquet and Arrow. Declared types are enforced,
+// inferred ones are only taken when every value parses as them. Either way a
+// column whose values would not read back as the same text is stored as
+// strings, so that no value changes on the way.
+fn typed_table<T: CSVManipulation>(data: &T, schema: Option<&Schema>) -> Result<convert::Table> {
+    let cols = data.cols();
+    let mut types: Vec<ColumnType> = match schema {
+        Some(schema) => schema
+            .column_types(data.header(), cols)?
+            .into_iter()
+            .map(|ty| ty.unwrap_or(ColumnType::String))
+            .collect(),
+        None => {
+            let mut inference = Inference::new(cols);
+            data.scan(&mut |_, row| {
+                inference.add(row);
+                Ok(true)
+            })?;
+            let schema = inference.finish(data.header());
+            schema
+                .columns
+                .iter()
+                .map(|column| match column.ty {
+                    ty if column.confidence == Some(1.0) => ty,
+                    _ => ColumnType::String,
+                })
+                .collect()
+        }
+    };
+    let mut changed = vec![false; cols];
+    data.scan(&mut |_, row| {
+        for ((changed, ty), cell) in changed.iter_mut().zip(&types).zip(row) {
+            *changed |= convert::changes(*ty, cell);
+        }
+        Ok(true)
+    })?;
+    for (ty, changed) in types.iter_mut().zip(changed) {
+        if changed {
+            *ty = ColumnType::String;
+        }
+    }
+    let names = match data.header() {
+        Some(header) => header.to_vec(),
+        None => (1..=cols).map(|index| index.to_string()).collect(),
+    };
+    let mut table = convert::Table::new(names, &types);
+    data.scan(&mut |row_index, row| {
+        table.add(row_index, row)?;
+        Ok(true)
+    })?;
+    Ok(table)
+}
+
+// Writes the rows as Parquet or Arrow IPC to `output`, or stdout.
+fn convert_table<T: CSVManipulation>(
+    data: &T,
+    format: convert::Format,
+    output: Option<&Path>,
+    compression: Option<convert::Compression>,
+    row_group_size: usize,
+    schema: Option<&Schema>,
+) -> Result<()> {
+    let compression = compression.unwrap_or(match format {
+        convert::Format::Parquet => convert::Compression::Snappy,
+        _ => convert::Compression::None,
+    });
+    // Fail before the output file is created
+    if format == convert::Format::Arrow {
+        arrow::compressed(compression)?;
+    }
+    let table = typed_table(data, schema)?;
+    let out: Box<dyn Write> = match output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+    match format {
+        convert::Format::Parquet => parquet::write(out, &table, compression, row_group_size),
+        _ => arrow::write(out, &table, compression, row_group_size),
+    }
+}
+
+// Prints every violation on stdout, failing if there is any.
+fn validate_rows<T: CSVManipulation>(data: &T, schema: Option<&Schema>) -> Result<()> {
+    let Some(schema) = schema else {
+        bail!(Error::InvalidSchema(
+            "validate needs a schema file, pass --schema".to_string()
+        ));
+    };
+    let mut validator = Validator::new(schema, data.header(), data.cols());
+    let mut rows = 0;
+    data.scan(&mut |row_index, row| {
+        validator.check(row_index, row);
+        rows = row_index;
+        Ok(true)
+    })?;
+    let mut out = io::stdout().lock();
+    for violation in &validator.violations {
+        writeln!(out, "{}", violation)?;
+    }
+    match validator.violations.len() {
+        0 => {
+            writeln!(out, "All {} rows are valid", rows)?;
+            Ok(())
+        }
+        violations => bail!(Error::ValidationFailed { violations }),
+    }
+}
+
+// Settings from the command line that commands use besides their own
+// arguments.
+struct Settings {
+    read_path: PathBuf,
+    dialect: Dialect,
+    // Whether the result goes to a file, in which case commands that would
+    // otherwise print their result stay quiet
+    writes: bool,
+    schema: Option<Schema>,
+    align: Vec<(String, Align)>,
+    pin: Vec<String>,
+    // Rendering settings besides those naming columns, which need the header
+    display: display::Options,
+}
+
+fn run<T: CSVManipulation>(data: &mut T, command: Command, settings: &Settings) -> Result<()> {
+    let schema = settings.schema.as_ref();
+    match command {
+        Command::Display => display_options(data, settings)
+            .and_then(|options| display_data(data, &options))
+            .context("Error occured while displaying file"),
+        Command::Paginate {
+            start,
+            end,
+            page,
+            page_size,
+        } => display_options(data, settings)
+            .and_then(|options| match (page, start, end) {
+                (Some(page), _, _) => paginate_page(data, page, page_size, &options),
+                (None, Some(start), Some(end)) => paginate_data(data, start, end, &options),
+                // clap requires either a page or both rows
+                (None, _, _) => unreachable!("paginate without rows or page"),
+            })
+            .context("Error occured while paginating file"),
+        Command::Index => build_index(settings).context("Error occured while indexing file"),
+        Command::Edit => unreachable!("edit is run on the file in memory by try_main"),
+        Command::View { start } => display_options(data, settings)
+            .and_then(|options| data.view(start, &options))
+            .context("Error occured while viewing file"),
         Command::Delete { row_index } => {
-            if let Err(e) = delete_row(&mut csv_data, row_index) {
-                panic!("Error occured while deleting row: {}", e)
-            }
+            delete_row(data, row_index).context("Error occured while deleting row")
         }
         Command::Modify {
             row_index,
             col_index,
+            data: values,
+        } => modify_row(data, row_index, col_index.as_deref(), values)
+            .context("Error occured while modifying row"),
+        Command::Insert {
+            at,
+            data: values,
+            fit,
+        } => insert_row(data, at, values, fit).context("Error occured while inserting row"),
+        Command::Append {
+            data: values,
+            from_stdin: false,
+            fit,
+        } => append_rows(data, [Ok(values)], fit).context("Error occured while appending row"),
+        Command::Append {
+            from_stdin: true,
+            fit,
+            ..
+        } => {
+            let records =
+                Reader::new(io::stdin().lock(), settings.dialect).map(|r| r.map(|r| r.fields));
+            append_rows(data, records, fit).context("Error occured while appending rows from stdin")
+        }
+        Command::AddColumn {
+            name,
+            at,
+            value,
+            expr,
+        } => data
+            .add_column(name, at.as_deref(), value, expr.as_deref())
+            .context("Error occured while adding column"),
+        Command::DropColumn { col } => data
+            .drop_column(&col)
+            .context("Error occured while dropping column"),
+        Command::RenameColumn { col, to } => data
+            .rename_column(&col, to)
+            .context("Error occured while renaming column"),
+        Command::MoveColumn { col, to } => data
+            .move_column(&col, to)
+            .context("Error occured while moving column"),
+        Command::Select { columns } => data
+            .select(&columns)
+            .context("Error occured while selecting columns"),
+        Command::Sort {
+            by,
+            mode,
+            ignore_case,
+            memory_limit,
+        } => column_types(data, schema)
+            .and_then(|types| {
+                Sort::new(
+                    &by,
+                    data.header(),
+                    data.cols(),
+                    mode,
+                    &types,
+                    ignore_case,
+                    memory_limit * 1024 * 1024,
+                )
+            })
+            .and_then(|sort| data.sort(sort))
+            .context("Error occured while sorting rows"),
+        Command::Filter {
+            expr,
+            invert,
+            count,
+        } => filter_rows(data, &expr, invert, count, settings)
+            .context("Error occured while filtering rows"),
+        Command::InferSchema { output, sample } => {
+            infer_schema(data, output.as_deref(), sample, settings)
+                .context("Error occured while inferring schema")
+        }
+        Command::Validate => {
+            validate_rows(data, schema).context("Error occured while validating rows")
+        }
+        Command::Convert {
+            to: to @ (convert::Format::Parquet | convert::Format::Arrow),
+            output,
+            compression,
+            row_group_size,
+            ..
+        } => convert_table(
             data,
+            to,
+            output.as_deref(),
+            compression,
+            row_group_size,
+            schema,
+        )
+        .context("Error occured while converting rows"),
+        Command::Convert {
+            to: to @ (convert::Format::Markdown | convert::Format::Html | convert::Format::Latex),
+            output,
+            fragment,
+            class,
+            sticky_header,
+            ..
         } => {
-            if let Err(e) = modify_row(&mut csv_data, row_index, col_index, data) {
-                panic!("Error occured while modifying row: {}", e)
-            }
+            let html = render::Html {
+                fragment,
+                classes: class,
+                sticky_header,
+                title: (settings.read_path.file_name().unwrap_or_default())
+                    .to_string_lossy()
+                    .into_owned(),
+            };
+            render_rows(data, to, html, output.as_deref(), settings)
+                .context("Error occured while converting rows")
         }
+        Command::Convert {
+            to,
+            typed,
+            nest,
+            output,
+            ..
+        } => convert_rows(data, to, typed, nest, output.as_deref(), schema)
+            .context("Error occured while converting rows"),
     }
+}
+
+fn try_main(args: Args) -> Result<()> {
+    let input_format = args
+        .input_format
+        .unwrap_or_else(|| convert::InputFormat::from_path(&args.read_path));
+    let is_csv = input_format == convert::InputFormat::Csv;
+    let dialect = match (args.delimiter, args.quote, args.escape, args.terminator) {
+        (None, None, None, None) if is_csv => {
+            Dialect::sniff_file(&args.read_path).unwrap_or_default()
+        }
+        (None, None, None, None) => Dialect::default(),
+        (delimiter, quote, escape, terminator) => {
+            let default = Dialect::default();
+            Dialect {
+                delimiter: delimiter.unwrap_or(default.delimiter),
+                quote: quote.unwrap_or(default.quote),
+                escape,
+                terminator: terminator.unwrap_or(default.terminator),
+            }
+        }
+    };
+    let read_context = || format!("Error occured while reading {}", args.read_path.display());
+    let schema = match &args.schema {
+        Some(path) => Some(
+            Schema::load(path)
+                .with_context(|| format!("Error occured while reading {}", path.display()))?,
+        ),
+        None => None,
+    };
+    let settings = Settings {
+        read_path: args.read_path.clone(),
+        dialect,
+        writes: args.write_path.is_some(),
+        schema,
+        align: args.align,
+        pin: args.pin,
+        display: display::Options {
+            style: args.style,
+            row_numbers: args.row_numbers,
+            no_header_rule: args.no_header_rule,
+            max_width: args.max_width,
+            overflow: args.overflow,
+            width: args.width.or_else(terminal::width),
+            fit: args.layout,
+            scroll: args.scroll,
+            ..display::Options::default()
+        },
+    };
 
-    let Some(path) = args.write_path else {
-        return;
+    let load = || match input_format {
+        convert::InputFormat::Csv => CSVData::from_file(
+            args.read_path.clone(),
+            dialect,
+            args.has_header,
+            args.ragged,
+        ),
+        convert::InputFormat::Xlsx => {
+            let mut rows = xlsx::read(&args.read_path, args.sheet.as_deref())?;
+            let header = (args.has_header && !rows.is_empty()).then(|| rows.remove(0));
+            Ok(CSVData::from_rows(header, rows, dialect))
+        }
+        format => {
+            let convert::Rows { header, rows } = match format {
+                convert::InputFormat::Parquet => parquet::read(&args.read_path)?,
+                convert::InputFormat::Arrow => arrow::read(&args.read_path)?,
+                format => convert::read(&args.read_path, format, args.flatten)?,
+            };
+            Ok(CSVData::from_rows(header, rows, dialect))
+        }
     };
-    if let Err(e) = csv_data.to_file(path) {
-        println!("Error occured while wriing to file {}", e);
+
+    // The editor works on the whole file in memory
+    if let Command::Edit = args.command {
+        let mut csv_data = load().with_context(read_context)?;
+        let options = display_options(&csv_data, &settings)?;
+        // Other formats are never overwritten with CSV
+        let path = match args.write_path {
+            Some(path) => path,
+            None if is_csv => args.read_path,
+            None => args.read_path.with_extension("csv"),
+        };
+        return editor::run(
+            &mut csv_data,
+            path,
+            args.quote_style,
+            settings.schema.as_ref(),
+            &options,
+        )
+        .context("Error occured while editing file");
+    }
+
+    // Other formats are read and written as a whole anyway
+    let writes_xlsx = args.write_path.as_deref().is_some_and(xlsx::is_xlsx);
+    if args.in_memory || !is_csv || writes_xlsx {
+        let mut csv_data = load().with_context(read_context)?;
+        run(&mut csv_data, args.command, &settings)?;
+        let Some(path) = args.write_path else {
+            return Ok(());
+        };
+        return csv_data
+            .save(path, args.quote_style, settings.schema.as_ref())
+            .context("Error occured while writing to file");
+    }
+
+    let mut csv_stream = CSVStream::open(
+        args.read_path.clone(),
+        dialect,
+        args.has_header,
+        args.ragged,
+    )
+    .with_context(read_context)?;
+    run(&mut csv_stream, args.command, &settings)?;
+    match args.write_path {
+        Some(path) => csv_stream.to_file(path, args.quote_style),
+        // Nothing to write, but pending edits are still checked against the file
+        None if csv_stream.has_edits() => csv_stream.write_to(io::sink(), args.quote_style),
+        None => Ok(()),
+    }
+    .context("Error occured while writing to file")
+}
+
+// Prints the error on stderr and picks the exit code of its root cause.
+fn report_error(error: &anyhow::Error, format: ErrorFormat) -> u8 {
+    let cause = error.chain().find_map(|e| e.downcast_ref::<Error>());
+    let exit_code = match cause {
+        Some(cause) => cause.exit_code(),
+        None if error.chain().any(|e| e.is::<io::Error>()) => EXIT_IO,
+        None => EXIT_FAILURE,
     };
+
+    match format {
+        ErrorFormat::Text => eprintln!("{:#}", error),
+        ErrorFormat::Json => {
+            let kind = match cause {
+                Some(cause) => cause.kind(),
+                None if exit_code == EXIT_IO => "Io",
+                None => "Other",
+            };
+            let mut fields = vec![
+                format!("\"error\":{}", json::quote(kind)),
+                format!("\"message\":{}", json::quote(&format!("{:#}", error))),
+                format!("\"exit_code\":{}", exit_code),
+            ];
+            for (name, value) in cause.map(Error::details).unwrap_or_default() {
+                fields.push(format!("{}:{}", json::quote(name), value));
+            }
+            eprintln!("{{{}}}", fields.join(","));
+        }
+    }
+    exit_code
+}
+
+fn is_broken_pipe(error: &anyhow::Error) -> bool {
+    error.chain().any(|e| {
+        e.downcast_ref::<io::Error>()
+            .is_some_and(|e| e.kind() == io::ErrorKind::BrokenPipe)
+    })
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    let error_format = args.error_format;
+    match try_main(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        // Whatever read the output, like `head`, has all it wanted
+        Err(e) if is_broken_pipe(&e) => ExitCode::SUCCESS,
+        Err(e) => ExitCode::from(report_error(&e, error_format)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sort::TempFile;
+    use std::fs;
+
+    fn load(text: &str) -> (TempFile, CSVData) {
+        let file = TempFile::unique();
+        fs::write(&file.0, text).unwrap();
+        let data = CSVData::from_file(
+            file.0.clone(),
+            Dialect::default(),
+            true,
+            RaggedPolicy::Error,
+        )
+        .unwrap();
+        (file, data)
+    }
+
+    fn saved(data: &CSVData, quote_style: Option<QuoteStyle>) -> String {
+        let file = TempFile::unique();
+        data.to_file(file.0.clone(), quote_style).unwrap();
+        fs::read_to_string(&file.0).unwrap()
+    }
+
+    #[test]
+    fn unchanged_files_round_trip() {
+        for text in [
+            "a,b\n1,2\n",
+            "a,b\r\n1,2\r\n",
+            "a,b\n1,2",
+            "\"a\",\"b\"\n\"1\",\"x,y\"\n",
+            "a,b\n\"one\ntwo\",2\n\n\n",
+        ] {
+            let (_file, data) = load(text);
+            assert_eq!(saved(&data, None), text);
+        }
+    }
+
+    #[test]
+    fn changed_rows_follow_the_file() {
+        let (_file, mut data) = load("a,b\r\n1,2\r\n");
+        data.append(vec!["3".to_string(), "4".to_string()], FitPolicy::Error)
+            .unwrap();
+        assert_eq!(saved(&data, None), "a,b\r\n1,2\r\n3,4\r\n");
+
+        // After a missing final newline
+        let (_file, mut data) = load("a,b\n1,2");
+        data.append(vec!["3".to_string(), "4".to_string()], FitPolicy::Error)
+            .unwrap();
+        assert_eq!(saved(&data, None), "a,b\n1,2\n3,4\n");
+
+        let (_file, mut data) = load("\"a\",\"b\"\n\"1\",\"2\"\n");
+        data.append(vec!["3".to_string(), "4".to_string()], FitPolicy::Error)
+            .unwrap();
+        assert_eq!(
+            saved(&data, None),
+            "\"a\",\"b\"\n\"1\",\"2\"\n\"3\",\"4\"\n"
+        );
+    }
+
+    // Fields of a record with `found` fields after fitting it to three, or
+    // the field count the error reports.
+    fn fitted(found: usize, ragged: RaggedPolicy) -> Result<Vec<String>, (usize, usize)> {
+        let text = (1..=found)
+            .map(|n| n.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        let mut record = Reader::new(format!("x\n{}\n", text).as_bytes(), Dialect::default())
+            .nth(1)
+            .unwrap()
+            .unwrap();
+        match fit_record(&mut record, 3, ragged) {
+            Ok(()) => {
+                // Only changed records lose their source text
+                assert_eq!(record.fitted, found != 3 && ragged != RaggedPolicy::Keep);
+                Ok(record.fields)
+            }
+            Err(e) => match e.downcast::<Error>() {
+                Ok(Error::RaggedRecord {
+                    line: 2,
+                    offset: 2,
+                    expected,
+                    found,
+                }) => Err((expected, found)),
+                other => panic!("unexpected error {:?}", other),
+            },
+        }
+    }
+
+    #[test]
+    fn ragged_policies() {
+        use RaggedPolicy::*;
+        let short = Ok(vec!["1".to_string(), "2".to_string(), String::new()]);
+        let long = Ok(vec!["1".to_string(), "2".to_string(), "3".to_string()]);
+        for ragged in [Error, Pad, Truncate, PadOrTruncate, Keep] {
+            assert_eq!(fitted(3, ragged), long, "{:?}", ragged);
+        }
+        assert_eq!(fitted(2, Error), Err((3, 2)));
+        assert_eq!(fitted(4, Error), Err((3, 4)));
+        assert_eq!(fitted(2, Pad), short);
+        assert_eq!(fitted(4, Pad), Err((3, 4)));
+        assert_eq!(fitted(2, Truncate), Err((3, 2)));
+        assert_eq!(fitted(4, Truncate), long);
+        assert_eq!(fitted(2, PadOrTruncate), short);
+        assert_eq!(fitted(4, PadOrTruncate), long);
+        assert_eq!(fitted(2, Keep).unwrap().len(), 2);
+        assert_eq!(fitted(4, Keep).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn first_record_sets_the_field_count() {
+        let mut record = Reader::new(&b"a,b\n"[..], Dialect::default())
+            .next()
+            .unwrap()
+            .unwrap();
+        fit_record(&mut record, 0, RaggedPolicy::Error).unwrap();
+        assert!(!record.fitted);
+    }
+
+    #[test]
+    fn quote_style_re_encodes_every_row() {
+        let (_file, data) = load("\"a\",b\n1,\"x,y\"\n");
+        assert_eq!(
+            saved(&data, Some(QuoteStyle::Always)),
+            "\"a\",\"b\"\n\"1\",\"x,y\"\n"
+        );
+        assert_eq!(saved(&data, Some(QuoteStyle::Minimal)), "a,b\n1,\"x,y\"\n");
+    }
 }
@@ -0,0 +1,322 @@
+// Full-screen pager over the rows of a file.
+//
+// Only the rows on screen are read, so the pager opens huge files at once.
+// The header stays at the top while the rows scroll under it, and columns
+// besides the pinned ones scroll sideways.
+//
+// Keys: up/down or j/k move a row, page up/down, space or b move a page,
+// home/end or g/G go to the first or last row, left/right or h/l scroll the
+// columns, `:` jumps to a row number, `/` searches as you type and `n`
+// finds the next match, q or Esc quits, and so does Ctrl-C even at the
+// prompt.
+
+use crate::{
+    display::{self, single_line, truncate, Layout, Overflow},
+    terminal::{self, Key, Screen},
+    width::display_width,
+    Error,
+};
+use anyhow::{bail, Result};
+use std::io::IsTerminal;
+
+// Rows searched per read.
+const SEARCH_CHUNK: usize = 1024;
+
+// Where the pager gets its rows from.
+pub trait Source {
+    // Up to `count` rows from the 0-based row `start`, fewer at the end.
+    fn rows(&mut self, start: usize, count: usize) -> Result<Vec<Vec<String>>>;
+    // Number of rows, None while the end has not been reached.
+    fn len(&self) -> Option<usize>;
+    // Number of rows, reading up to the end if need be.
+    fn count(&mut self) -> Result<usize>;
+}
+
+impl Source for &[Vec<String>] {
+    fn rows(&mut self, start: usize, count: usize) -> Result<Vec<Vec<String>>> {
+        let start = start.min(<[_]>::len(self));
+        let end = start.saturating_add(count).min(<[_]>::len(self));
+        Ok(self[start..end].to_vec())
+    }
+
+    fn len(&self) -> Option<usize> {
+        Some(<[_]>::len(self))
+    }
+
+    fn count(&mut self) -> Result<usize> {
+        Ok(<[_]>::len(self))
+    }
+}
+
+// A line being typed at the bottom of the screen.
+enum Prompt {
+    // `:` followed by a row number
+    Jump(String),
+    // `/` followed by the search text, and the row shown when it began
+    Search(String, usize),
+}
+
+struct Pager<'a> {
+    source: &'a mut dyn Source,
+    header: Option<&'a Vec<String>>,
+    cols: usize,
+    options: display::Options,
+    // 0-based row shown first
+    top: usize,
+    // Unpinned columns scrolled past
+    scroll: usize,
+    // Terminal rows and columns
+    size: (usize, usize),
+    prompt: Option<Prompt>,
+    // Last search, for `n`
+    search: Option<String>,
+    message: Option<String>,
+}
+
+// Cells on one screen line each.
+fn flatten(row: Vec<String>) -> Vec<String> {
+    row.iter().map(|cell| single_line(cell)).collect()
+}
+
+impl Pager<'_> {
+    fn layout(&self) -> Layout {
+        Layout::new(self.header, self.cols, &self.options).number_from(self.top + 1)
+    }
+
+    // Rows that fit under the header, above the status line.
+    fn height(&self) -> usize {
+        let table = self.layout().finish();
+        let chrome =
+            table.header_lines(self.header).len() + usize::from(table.footer_line().is_some()) + 1;
+        self.size.0.saturating_sub(chrome).max(1)
+    }
+
+    fn draw(&mut self, screen: &Screen) -> Result<()> {
+        let (rows, cols) = self.size;
+        let height = self.height();
+        let page: Vec<Vec<String>> = self
+            .source
+            .rows(self.top, height)?
+            .into_iter()
+            .map(flatten)
+            .collect();
+        let mut layout = self.layout();
+        for row in &page {
+            layout.measure(row);
+        }
+        let table = layout.finish();
+        let scrollable = table.scrollable();
+        self.scroll = self.scroll.min(scrollable.saturating_sub(1));
+        let table = table.scrolled(self.scroll, Some(cols));
+
+        let mut lines = table.header_lines(self.header);
+        for (index, row) in page.iter().enumerate() {
+            lines.push(table.format_row(self.top + index + 1, row));
+        }
+        lines.extend(table.footer_line());
+        lines.resize(rows.saturating_sub(1), "~".to_string());
+
+        let status = match &self.prompt {
+            Some(Prompt::Jump(text)) => format!(":{}", text),
+            Some(Prompt::Search(text, _)) => format!("/{}", text),
+            None => {
+                let total = match self.source.len() {
+                    Some(len) => len.to_string(),
+                    None => "?".to_string(),
+                };
+                let shown = match page.len() {
+                    0 => "no rows".to_string(),
+                    len => format!("rows {}-{}", self.top + 1, self.top + len),
+                };
+                let mut status = format!(" {} of {}", shown, total);
+                if self.scroll > 0 {
+                    status.push_str(&format!(
+                        ", scrolled past {} of {} columns",
+                        self.scroll, scrollable
+                    ));
+                }
+                if let Some(message) = &self.message {
+                    status.push_str(&format!(" | {}", message));
+                }
+                status
+            }
+        };
+
+        let mut frame = String::from("\x1b[H");
+        for line in &lines {
+            frame.push_str(&clip(line, cols));
+            frame.push_str("\x1b[K\r\n");
+        }
+        frame.push_str(&format!(
+            "\x1b[7m{}\x1b[0m\x1b[K",
+            clip(&single_line(&status), cols)
+        ));
+        screen.draw(&frame)?;
+        Ok(())
+    }
+
+    // First row at or after `from` with a cell containing `text`, ignoring case.
+    fn find(&mut self, from: usize, text: &str) -> Result<Option<usize>> {
+        let text = text.to_lowercase();
+        let mut start = from;
+        loop {
+            let rows = self.source.rows(start, SEARCH_CHUNK)?;
+            if rows.is_empty() {
+                return Ok(None);
+            }
+            let found = rows
+                .iter()
+                .position(|row| row.iter().any(|cell| cell.to_lowercase().contains(&text)));
+            if let Some(index) = found {
+                return Ok(Some(start + index));
+            }
+            start += rows.len();
+        }
+    }
+
+    // Shows `row` first, keeping a full page on screen near the end.
+    fn go_to(&mut self, row: usize) -> Result<()> {
+        let height = self.height();
+        let last = match self.source.rows(row, height)?.len() {
+            len if len == height => row,
+            _ => self.source.count()?.saturating_sub(height),
+        };
+        self.top = row.min(last);
+        Ok(())
+    }
+
+    // Handles a key typed at the prompt.
+    fn type_key(&mut self, key: Key) -> Result<()> {
+        let Some(prompt) = self.prompt.take() else {
+            return Ok(());
+        };
+        match (prompt, key) {
+            (Prompt::Jump(_), Key::Escape) => {}
+            (Prompt::Search(_, origin), Key::Escape) => self.top = origin,
+            (Prompt::Jump(text), Key::Enter) => match text.parse::<usize>() {
+                Ok(row) if row > 0 => self.go_to(row - 1)?,
+                _ => self.message = Some(format!("not a row number: {:?}", text)),
+            },
+            (Prompt::Search(text, _), Key::Enter) => {
+                if !text.is_empty() {
+                    self.search = Some(text);
+                }
+            }
+            (Prompt::Jump(mut text), Key::Backspace) => {
+                text.pop();
+                self.prompt = Some(Prompt::Jump(text));
+            }
+            (Prompt::Jump(mut text), Key::Char(c)) if c.is_ascii_digit() => {
+                text.push(c);
+                self.prompt = Some(Prompt::Jump(text));
+            }
+            (Prompt::Search(mut text, origin), Key::Char(_) | Key::Backspace) => {
+                match key {
+                    Key::Char(c) => text.push(c),
+                    _ => {
+                        text.pop();
+                    }
+                }
+                // Incremental: every change searches again from where it began
+                self.top = origin;
+                if !text.is_empty() {
+                    match self.find(origin, &text)? {
+                        Some(row) => self.top = row,
+                        None => self.message = Some("not found".to_string()),
+                    }
+                }
+                self.prompt = Some(Prompt::Search(text, origin));
+            }
+            (prompt, _) => self.prompt = Some(prompt),
+        }
+        Ok(())
+    }
+
+    // Handles a key, returning false to quit.
+    fn key(&mut self, key: Key) -> Result<bool> {
+        // Quits from the prompt too
+        if key == Key::Interrupt {
+            return Ok(false);
+        }
+        if self.prompt.is_some() {
+            self.message = None;
+            self.type_key(key)?;
+            return Ok(true);
+        }
+        self.message = None;
+        let height = self.height();
+        match key {
+            Key::Char('q') | Key::Escape => return Ok(false),
+            Key::Down | Key::Char('j') => self.go_to(self.top + 1)?,
+            Key::Up | Key::Char('k') => self.top = self.top.saturating_sub(1),
+            Key::PageDown | Key::Char(' ') | Key::Char('f') => self.go_to(self.top + height)?,
+            Key::PageUp | Key::Char('b') => self.top = self.top.saturating_sub(height),
+            Key::Home | Key::Char('g') => self.top = 0,
+            Key::End | Key::Char('G') => self.go_to(usize::MAX / 2)?,
+            Key::Right | Key::Char('l') => self.scroll += 1,
+            Key::Left | Key::Char('h') => self.scroll = self.scroll.saturating_sub(1),
+            Key::Char(':') => self.prompt = Some(Prompt::Jump(String::new())),
+            Key::Char('/') => self.prompt = Some(Prompt::Search(String::new(), self.top)),
+            Key::Char('n') => match self.search.clone() {
+                Some(text) => match self.find(self.top + 1, &text)? {
+                    Some(row) => self.top = row,
+                    None => self.message = Some("no more matches".to_string()),
+                },
+                None => self.message = Some("nothing searched yet".to_string()),
+            },
+            _ => {}
+        }
+        Ok(true)
+    }
+}
+
+// Cuts a line down to the terminal width.
+fn clip(line: &str, width: usize) -> String {
+    match display_width(line) > width {
+        true => truncate(line, width),
+        false => line.to_string(),
+    }
+}
+
+// Runs the pager until the user quits, starting at the 1-based row `start`.
+pub fn run(
+    source: &mut dyn Source,
+    header: Option<&Vec<String>>,
+    cols: usize,
+    start: usize,
+    options: &display::Options,
+) -> Result<()> {
+    let size = match terminal::size() {
+        Some(size) if std::io::stdout().is_terminal() => size,
+        _ => bail!(Error::NotATerminal),
+    };
+    let mut pager = Pager {
+        source,
+        header,
+        cols,
+        options: display::Options {
+            // Every row takes a single screen line
+            overflow: Overflow::Truncate,
+            ..options.clone()
+        },
+        top: 0,
+        scroll: 0,
+        size,
+        prompt: None,
+        search: None,
+        message: None,
+    };
+    pager.go_to(start.saturating_sub(1))?;
+    let mut screen = Screen::enter()?;
+    loop {
+        pager.draw(&screen)?;
+        let key = screen.read_key()?;
+        if !pager.key(key)? {
+            return Ok(());
+        }
+        // The terminal may have been resized meanwhile
+        if let Some(size) = terminal::size() {
+            pager.size = size;
+        }
+    }
+}
@@ -0,0 +1,1051 @@
+// Apache Parquet files, with flat schemas only.
+//
+// Files are written with a row group per `row_group_size` rows and a single
+// data page per column chunk, every column optional and its values PLAIN
+// encoded, with null counts and min/max statistics. Reading takes what common
+// writers produce for flat schemas: version 1 and 2 data pages, dictionary,
+// RLE, delta and byte stream split encodings, and pages stored as they are or
+// compressed with snappy, gzip or LZ4. Nested columns and the other codecs,
+// zstd among them, are not supported.
+
+use crate::{
+    compress,
+    convert::{self, Compression, Counted, Rows, Table, Values},
+    schema::ColumnType,
+    thrift::{self, Struct, Value},
+    Error,
+};
+use anyhow::{bail, Result};
+use std::{fs, io::Write, ops::Range, path::Path};
+
+const MAGIC: &[u8] = b"PAR1";
+
+// Physical types
+const BOOLEAN: i64 = 0;
+const INT32: i64 = 1;
+const INT64: i64 = 2;
+const INT96: i64 = 3;
+const FLOAT: i64 = 4;
+const DOUBLE: i64 = 5;
+const BYTE_ARRAY: i64 = 6;
+const FIXED_LEN_BYTE_ARRAY: i64 = 7;
+
+// Converted types, the annotations older writers use instead of logical types
+const UTF8: i64 = 0;
+const ENUM: i64 = 4;
+const DECIMAL: i64 = 5;
+const DATE: i64 = 6;
+const TIME_MILLIS: i64 = 7;
+const TIME_MICROS: i64 = 8;
+const TIMESTAMP_MILLIS: i64 = 9;
+const TIMESTAMP_MICROS: i64 = 10;
+const UINT_8: i64 = 11;
+const UINT_64: i64 = 14;
+const JSON: i64 = 19;
+
+// Encodings
+const PLAIN: i64 = 0;
+const PLAIN_DICTIONARY: i64 = 2;
+const RLE: i64 = 3;
+const DELTA_BINARY_PACKED: i64 = 5;
+const DELTA_LENGTH_BYTE_ARRAY: i64 = 6;
+const DELTA_BYTE_ARRAY: i64 = 7;
+const RLE_DICTIONARY: i64 = 8;
+const BYTE_STREAM_SPLIT: i64 = 9;
+
+// Page types
+const DATA_PAGE: i64 = 0;
+const DICTIONARY_PAGE: i64 = 2;
+const DATA_PAGE_V2: i64 = 3;
+
+// Compression codecs
+const UNCOMPRESSED: i64 = 0;
+const SNAPPY: i64 = 1;
+const GZIP: i64 = 2;
+const LZ4: i64 = 5;
+const LZ4_RAW: i64 = 7;
+const CODECS: [&str; 8] = [
+    "uncompressed",
+    "snappy",
+    "gzip",
+    "LZO",
+    "brotli",
+    "LZ4",
+    "zstd",
+    "LZ4",
+];
+
+// Repetitions
+const OPTIONAL: i64 = 1;
+const REPEATED: i64 = 2;
+
+// Days between the julian day 0 of INT96 timestamps and 1970-01-01
+const JULIAN_EPOCH: i64 = 2_440_588;
+
+fn invalid(reason: impl Into<String>) -> anyhow::Error {
+    Error::InvalidParquet(reason.into()).into()
+}
+
+fn unsupported(feature: impl Into<String>) -> anyhow::Error {
+    Error::Unsupported {
+        format: "Parquet",
+        feature: feature.into(),
+    }
+    .into()
+}
+
+// Writing.
+
+// A column chunk of a row group: its PLAIN values, which rows have one, and
+// statistics.
+struct Chunk {
+    values: Vec<u8>,
+    defined: Vec<bool>,
+    nulls: usize,
+    // Smallest and largest value, as PLAIN but without length prefixes
+    bounds: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+fn chunk<T: PartialOrd>(
+    values: &[Option<T>],
+    bytes: impl Fn(&T) -> Vec<u8>,
+    prefixed: bool,
+) -> Chunk {
+    let mut out = Vec::new();
+    let (mut min, mut max): (Option<&T>, Option<&T>) = (None, None);
+    for value in values.iter().flatten() {
+        let encoded = bytes(value);
+        if prefixed {
+            out.extend((encoded.len() as u32).to_le_bytes());
+        }
+        out.extend(encoded);
+        if min.is_none_or(|min| value < min) {
+            min = Some(value);
+        }
+        if max.is_none_or(|max| value > max) {
+            max = Some(value);
+        }
+    }
+    let defined: Vec<bool> = values.iter().map(Option::is_some).collect();
+    Chunk {
+        values: out,
+        nulls: defined.iter().filter(|defined| !**defined).count(),
+        defined,
+        bounds: min.zip(max).map(|(min, max)| (bytes(min), bytes(max))),
+    }
+}
+
+fn column_chunk(values: &Values, rows: Range<usize>) -> Chunk {
+    match values {
+        Values::Boolean(values) => {
+            let values = &values[rows];
+            let mut chunk = chunk(values, |value| vec![*value as u8], false);
+            // Booleans are packed a bit each
+            let bits: Vec<bool> = values.iter().flatten().copied().collect();
+            chunk.values = pack_bits(&bits);
+            chunk
+        }
+        Values::Integer(values) => chunk(&values[rows], |v| v.to_le_bytes().to_vec(), false),
+        Values::Float(values) => chunk(&values[rows], |v| v.to_le_bytes().to_vec(), false),
+        Values::Date(values) => chunk(&values[rows], |v| v.to_le_bytes().to_vec(), false),
+        Values::DateTime(values) => chunk(&values[rows], |v| v.to_le_bytes().to_vec(), false),
+        Values::String(values) => chunk(&values[rows], |v| v.as_bytes().to_vec(), true),
+    }
+}
+
+// Bits from the least significant one of every byte on.
+fn pack_bits(bits: &[bool]) -> Vec<u8> {
+    let mut out = vec![0u8; bits.len().div_ceil(8)];
+    for (index, _) in bits.iter().enumerate().filter(|(_, bit)| **bit) {
+        out[index / 8] |= 1 << (index % 8);
+    }
+    out
+}
+
+fn varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+// Definition levels in the RLE/bit-packed hybrid encoding, a single run when
+// there is no null.
+fn definition_levels(defined: &[bool]) -> Vec<u8> {
+    let mut out = Vec::new();
+    if defined.iter().all(|defined| *defined) {
+        varint(&mut out, (defined.len() as u64) << 1);
+        out.push(1);
+    } else {
+        varint(&mut out, (defined.len().div_ceil(8) as u64) << 1 | 1);
+        out.extend(pack_bits(defined));
+    }
+    out
+}
+
+fn physical_type(ty: ColumnType) -> i64 {
+    match ty {
+        ColumnType::Boolean => BOOLEAN,
+        ColumnType::Integer | ColumnType::DateTime => INT64,
+        ColumnType::Float => DOUBLE,
+        ColumnType::Date => INT32,
+        ColumnType::String => BYTE_ARRAY,
+    }
+}
+
+fn schema_element(name: &str, ty: ColumnType) -> Struct {
+    let empty = || Value::Struct(Struct::default());
+    // Logical types are a union, keyed by field
+    let (converted, logical) = match ty {
+        ColumnType::Boolean | ColumnType::Integer | ColumnType::Float => (None, None),
+        ColumnType::Date => (Some(DATE), Some((6, empty()))),
+        // The converted type would claim the values are UTC
+        ColumnType::DateTime => {
+            let millis = Struct::default().with(1, empty());
+            let timestamp = Struct::default()
+                .with(1, Value::Bool(false))
+                .with(2, Value::Struct(millis));
+            (None, Some((8, Value::Struct(timestamp))))
+        }
+        ColumnType::String => (Some(UTF8), Some((1, empty()))),
+    };
+    let mut element = Struct::default()
+        .with(1, Value::I32(physical_type(ty) as i32))
+        .with(3, Value::I32(OPTIONAL as i32))
+        .with(4, Value::Binary(name.as_bytes().to_vec()));
+    if let Some(converted) = converted {
+        element = element.with(6, Value::I32(converted as i32));
+    }
+    if let Some((field, logical)) = logical {
+        element = element.with(10, Value::Struct(Struct::default().with(field, logical)));
+    }
+    element
+}
+
+fn page_size(len: usize) -> Result<Value> {
+    match i32::try_from(len) {
+        Ok(len) => Ok(Value::I32(len)),
+        Err(_) => bail!(unsupported("pages over 2 GiB")),
+    }
+}
+
+// Writes `table` with a row group per `row_group_size` rows.
+pub fn write<W: Write>(
+    out: W,
+    table: &Table,
+    compression: Compression,
+    row_group_size: usize,
+) -> Result<()> {
+    let mut out = Counted::new(out);
+    out.write(MAGIC)?;
+    let codec = match compression {
+        Compression::None => UNCOMPRESSED,
+        Compression::Snappy => SNAPPY,
+        Compression::Gzip => GZIP,
+        Compression::Lz4 => LZ4_RAW,
+    };
+    let mut row_groups = Vec::new();
+    for start in (0..table.rows).step_by(row_group_size) {
+        let rows = start..(start + row_group_size).min(table.rows);
+        let mut columns = Vec::new();
+        let mut total_size = 0;
+        for (name, values) in table.names.iter().zip(&table.columns) {
+            let chunk = column_chunk(values, rows.clone());
+            let levels = definition_levels(&chunk.defined);
+            let mut page = (levels.len() as u32).to_le_bytes().to_vec();
+            page.extend(levels);
+            page.extend(&chunk.values);
+            let compressed = match compression {
+                Compression::None => page.clone(),
+                Compression::Snappy => compress::snappy(&page),
+                Compression::Gzip => compress::gzip(&page),
+                Compression::Lz4 => compress::lz4_block(&page),
+            };
+            let data_page = Struct::default()
+                .with(1, Value::I32(rows.len() as i32))
+                .with(2, Value::I32(PLAIN as i32))
+                .with(3, Value::I32(RLE as i32))
+                .with(4, Value::I32(RLE as i32));
+            let header = Struct::default()
+                .with(1, Value::I32(DATA_PAGE as i32))
+                .with(2, page_size(page.len())?)
+                .with(3, page_size(compressed.len())?)
+                .with(5, Value::Struct(data_page));
+            let mut header_bytes = Vec::new();
+            thrift::write(&mut header_bytes, &header);
+
+            let offset = out.position as i64;
+            out.write(&header_bytes)?;
+            out.write(&compressed)?;
+            let uncompressed_size = (header_bytes.len() + page.len()) as i64;
+            let compressed_size = (header_bytes.len() + compressed.len()) as i64;
+            total_size += uncompressed_size;
+
+            let mut statistics = Struct::default().with(3, Value::I64(chunk.nulls as i64));
+            if let Some((min, max)) = chunk.bounds {
+                statistics = statistics
+                    .with(5, Value::Binary(max))
+                    .with(6, Value::Binary(min));
+            }
+            let metadata = Struct::default()
+                .with(1, Value::I32(physical_type(values.ty()) as i32))
+                .with(
+                    2,
+                    Value::List(vec![Value::I32(PLAIN as i32), Value::I32(RLE as i32)]),
+                )
+                .with(
+                    3,
+                    Value::List(vec![Value::Binary(name.as_bytes().to_vec())]),
+                )
+                .with(4, Value::I32(codec as i32))
+                .with(5, Value::I64(rows.len() as i64))
+                .with(6, Value::I64(uncompressed_size))
+                .with(7, Value::I64(compressed_size))
+                .with(9, Value::I64(offset))
+                .with(12, Value::Struct(statistics));
+            columns.push(Value::Struct(
+                Struct::default()
+                    .with(2, Value::I64(offset))
+                    .with(3, Value::Struct(metadata)),
+            ));
+        }
+        row_groups.push(Value::Struct(
+            Struct::default()
+                .with(1, Value::List(columns))
+                .with(2, Value::I64(total_size))
+                .with(3, Value::I64(rows.len() as i64)),
+        ));
+    }
+
+    let root = Struct::default()
+        .with(4, Value::Binary(b"schema".to_vec()))
+        .with(5, Value::I32(table.names.len() as i32));
+    let elements = table
+        .names
+        .iter()
+        .zip(&table.columns)
+        .map(|(name, values)| Value::Struct(schema_element(name, values.ty())));
+    let schema = std::iter::once(Value::Struct(root))
+        .chain(elements)
+        .collect();
+    // Statistics are ordered by the logical type of their column
+    let type_defined = Struct::default().with(1, Value::Struct(Struct::default()));
+    let column_orders = vec![Value::Struct(type_defined); table.names.len()];
+    let created_by = format!(
+        "{} version {}",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION")
+    );
+    let metadata = Struct::default()
+        .with(1, Value::I32(1))
+        .with(2, Value::List(schema))
+        .with(3, Value::I64(table.rows as i64))
+        .with(4, Value::List(row_groups))
+        .with(6, Value::Binary(created_by.into_bytes()))
+        .with(7, Value::List(column_orders));
+    let mut footer = Vec::new();
+    thrift::write(&mut footer, &metadata);
+    out.write(&footer)?;
+    out.write(&(footer.len() as u32).to_le_bytes())?;
+    out.write(MAGIC)?;
+    out.finish()
+}
+
+// Reading.
+
+// How the values of a column are turned into cells.
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Plain,
+    Unsigned,
+    Date,
+    // Units per second
+    Timestamp(i64),
+    Time(i64),
+    // Digits after the decimal point
+    Decimal(i32),
+    Text,
+    Uuid,
+}
+
+struct Column {
+    name: String,
+    physical: i64,
+    // Bytes per value of FIXED_LEN_BYTE_ARRAY columns
+    width: usize,
+    optional: bool,
+    kind: Kind,
+}
+
+fn time_unit(unit: Option<&Struct>) -> Option<i64> {
+    let unit = unit?;
+    [(1, 1_000), (2, 1_000_000), (3, 1_000_000_000)]
+        .into_iter()
+        .find(|(field, _)| unit.get(*field).is_some())
+        .map(|(_, units)| units)
+}
+
+impl Column {
+    fn new(element: &Struct) -> Result<Self> {
+        let name = String::from_utf8_lossy(element.binary(4).unwrap_or_default()).into_owned();
+        if element.int(5).is_some_and(|children| children > 0) {
+            bail!(unsupported(format!("nested column {:?}", name)));
+        }
+        if element.int(3) == Some(REPEATED) {
+            bail!(unsupported(format!("repeated column {:?}", name)));
+        }
+        let physical = element
+            .int(1)
+            .ok_or_else(|| invalid(format!("column {:?} has no type", name)))?;
+        let scale = element.int(7).unwrap_or(0) as i32;
+        // Logical types first, the converted type of older writers otherwise
+        let logical = element.child(10);
+        let kind = match logical.and_then(|logical| logical.0.first()) {
+            Some((1 | 4 | 12, _)) => Kind::Text,
+            Some((5, Value::Struct(decimal))) => Kind::Decimal(decimal.int(1).unwrap_or(0) as i32),
+            Some((6, _)) => Kind::Date,
+            Some((7, Value::Struct(time))) => Kind::Time(time_unit(time.child(2)).unwrap_or(1)),
+            Some((8, Value::Struct(timestamp))) => {
+                Kind::Timestamp(time_unit(timestamp.child(2)).unwrap_or(1))
+            }
+            Some((10, Value::Struct(integer))) if integer.bool(2) == Some(false) => Kind::Unsigned,
+            Some((14, _)) => Kind::Uuid,
+            Some(_) => Kind::Plain,
+            None => match element.int(6) {
+                Some(UTF8 | ENUM | JSON) => Kind::Text,
+                Some(DECIMAL) => Kind::Decimal(scale),
+                Some(DATE) => Kind::Date,
+                Some(TIME_MILLIS) => Kind::Time(1_000),
+                Some(TIME_MICROS) => Kind::Time(1_000_000),
+                Some(TIMESTAMP_MILLIS) => Kind::Timestamp(1_000),
+                Some(TIMESTAMP_MICROS) => Kind::Timestamp(1_000_000),
+                Some(UINT_8..=UINT_64) => Kind::Unsigned,
+                _ => Kind::Plain,
+            },
+        };
+        Ok(Self {
+            name,
+            physical,
+            width: element.int(2).unwrap_or(0).max(0) as usize,
+            optional: element.int(3) != Some(0),
+            kind,
+        })
+    }
+
+    fn int(&self, value: i64) -> String {
+        match self.kind {
+            Kind::Date => convert::date(value),
+            Kind::Timestamp(units) => convert::timestamp(value, units),
+            Kind::Time(units) => convert::time_of_day(value, units),
+            Kind::Decimal(scale) => convert::decimal(value as i128, scale),
+            Kind::Unsigned if self.physical == INT32 => (value as u32).to_string(),
+            Kind::Unsigned => (value as u64).to_string(),
+            _ => value.to_string(),
+        }
+    }
+
+    fn bytes(&self, bytes: &[u8]) -> String {
+        match self.kind {
+            Kind::Text => String::from_utf8_lossy(bytes).into_owned(),
+            Kind::Decimal(scale) if !bytes.is_empty() && bytes.len() <= 16 => {
+                // Big-endian two's complement
+                let fill = if bytes[0] & 0x80 != 0 { 0xff } else { 0 };
+                let mut value = [fill; 16];
+                value[16 - bytes.len()..].copy_from_slice(bytes);
+                convert::decimal(i128::from_be_bytes(value), scale)
+            }
+            Kind::Uuid if bytes.len() == 16 => {
+                let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+                format!(
+                    "{}-{}-{}-{}-{}",
+                    &hex[..8],
+                    &hex[8..12],
+                    &hex[12..16],
+                    &hex[16..20],
+                    &hex[20..]
+                )
+            }
+            _ => convert::binary(bytes),
+        }
+    }
+
+    // Legacy timestamps: nanoseconds in the day, then the julian day.
+    fn int96(&self, bytes: &[u8]) -> String {
+        let nanos = i64::from_le_bytes(bytes[..8].try_into().unwrap_or_default());
+        let day = u32::from_le_bytes(bytes[8..12].try_into().unwrap_or_default());
+        format!(
+            "{} {}",
+            convert::date(day as i64 - JULIAN_EPOCH),
+            convert::time_of_day(nanos, 1_000_000_000)
+        )
+    }
+
+    // Bytes a PLAIN value takes, None for length-prefixed ones.
+    fn fixed_width(&self) -> Option<usize> {
+        match self.physical {
+            INT32 | FLOAT => Some(4),
+            INT64 | DOUBLE => Some(8),
+            INT96 => Some(12),
+            FIXED_LEN_BYTE_ARRAY => Some(self.width),
+            _ => None,
+        }
+    }
+
+    // A PLAIN value of fixed width.
+    fn fixed(&self, bytes: &[u8]) -> String {
+        match self.physical {
+            INT32 => self.int(i32::from_le_bytes(bytes.try_into().unwrap_or_default()) as i64),
+            INT64 => self.int(i64::from_le_bytes(bytes.try_into().unwrap_or_default())),
+            FLOAT => f32::from_le_bytes(bytes.try_into().unwrap_or_default()).to_string(),
+            DOUBLE => f64::from_le_bytes(bytes.try_into().unwrap_or_default()).to_string(),
+            INT96 => self.int96(bytes),
+            _ => self.bytes(bytes),
+        }
+    }
+}
+
+// Reads `count` values of `width` bits each, packed from the least
+// significant bit of every byte on.
+fn unpack(data: &[u8], width: u32, count: usize) -> Option<Vec<u64>> {
+    let bytes = count
+        .checked_mul(width as usize)
+        .map(|bits| bits.div_ceil(8));
+    if width > 64 || bytes.is_none_or(|bytes| bytes > data.len()) {
+        return None;
+    }
+    let mut values = Vec::with_capacity(count);
+    let (mut buffer, mut bits, mut position) = (0u128, 0u32, 0);
+    for _ in 0..count {
+        while bits < width {
+            buffer |= (data[position] as u128) << bits;
+            position += 1;
+            bits += 8;
+        }
+        values.push((buffer & ((1u128 << width) - 1)) as u64);
+        buffer >>= width;
+        bits -= width;
+    }
+    Some(values)
+}
+
+fn read_varint(data: &[u8], position: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *data.get(*position)?;
+        *position += 1;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte < 0x80 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn read_zigzag(data: &[u8], position: &mut usize) -> Option<i64> {
+    let value = read_varint(data, position)?;
+    Some((value >> 1) as i64 ^ -((value & 1) as i64))
+}
+
+// `count` values of the RLE/bit-packed hybrid encoding.
+fn rle_hybrid(data: &[u8], width: u32, count: usize) -> Option<Vec<u64>> {
+    let mut values = Vec::with_capacity(count.min(data.len() * 8));
+    let mut position = 0;
+    while values.len() < count {
+        let header = read_varint(data, &mut position)?;
+        let remaining = count - values.len();
+        if header & 1 == 1 {
+            // Groups of eight bit-packed values
+            let len = (header >> 1) as usize * 8;
+            let bytes = len.checked_mul(width as usize)? / 8;
+            let packed = data.get(position..position.checked_add(bytes)?)?;
+            position += bytes;
+            let unpacked = unpack(packed, width, len)?;
+            values.extend(unpacked.into_iter().take(remaining));
+        } else {
+            let len = (header >> 1) as usize;
+            let bytes = width.div_ceil(8) as usize;
+            let mut value = [0u8; 8];
+            value[..bytes].copy_from_slice(data.get(position..position + bytes)?);
+            position += bytes;
+            values.extend(std::iter::repeat_n(
+                u64::from_le_bytes(value),
+                len.min(remaining),
+            ));
+        }
+    }
+    Some(values)
+}
+
+// Integers of the DELTA_BINARY_PACKED encoding, with the bytes they took.
+fn delta_binary(data: &[u8]) -> Option<(Vec<i64>, usize)> {
+    let mut position = 0;
+    let block_size = read_varint(data, &mut position)? as usize;
+    let miniblocks = read_varint(data, &mut position)? as usize;
+    let count = read_varint(data, &mut position)? as usize;
+    let mut last = read_zigzag(data, &mut position)?;
+    if miniblocks == 0 || !block_size.is_multiple_of(miniblocks) || count > data.len() * 8 + 1 {
+        return None;
+    }
+    let per_miniblock = block_size / miniblocks;
+    let mut values = Vec::with_capacity(count.min(data.len() * 8));
+    if count > 0 {
+        values.push(last);
+    }
+    while values.len() < count {
+        let min_delta = read_zigzag(data, &mut position)?;
+        let widths = data.get(position..position + miniblocks)?.to_vec();
+        position += miniblocks;
+        // Miniblocks past the last value are left out
+        for width in widths {
+            if values.len() >= count {
+                break;
+            }
+            let bytes = per_miniblock * width as usize / 8;
+            let packed = data.get(position..position + bytes)?;
+            position += bytes;
+            for delta in unpack(packed, width as u32, per_miniblock)? {
+                if values.len() < count {
+                    last = last.wrapping_add(min_delta).wrapping_add(delta as i64);
+                    values.push(last);
+                }
+            }
+        }
+    }
+    Some((values, position))
+}
+
+// Byte arrays of the DELTA_LENGTH_BYTE_ARRAY encoding.
+fn delta_length(data: &[u8]) -> Option<Vec<&[u8]>> {
+    let (lengths, mut position) = delta_binary(data)?;
+    let mut values = Vec::with_capacity(lengths.len());
+    for len in lengths {
+        let len = usize::try_from(len).ok()?;
+        values.push(data.get(position..position.checked_add(len)?)?);
+        position += len;
+    }
+    Some(values)
+}
+
+// Byte arrays of the DELTA_BYTE_ARRAY encoding, each sharing a prefix with
+// the one before it.
+fn delta_strings(data: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let (prefixes, position) = delta_binary(data)?;
+    let suffixes = delta_length(&data[position..])?;
+    let mut values: Vec<Vec<u8>> = Vec::with_capacity(prefixes.len());
+    for (prefix, suffix) in prefixes.into_iter().zip(suffixes) {
+        let previous = values.last().map_or(&[][..], Vec::as_slice);
+        let mut value = previous.get(..usize::try_from(prefix).ok()?)?.to_vec();
+        value.extend(suffix);
+        values.push(value);
+    }
+    Some(values)
+}
+
+// `count` PLAIN values.
+fn plain(data: &[u8], column: &Column, count: usize) -> Option<Vec<String>> {
+    let mut values = Vec::with_capacity(count.min(data.len() * 8));
+    match (column.physical, column.fixed_width()) {
+        (BOOLEAN, _) => {
+            for bit in unpack(data, 1, count)? {
+                values.push((bit == 1).to_string());
+            }
+        }
+        (_, Some(width)) => {
+            if width == 0 || data.len() < count.checked_mul(width)? {
+                return None;
+            }
+            for bytes in data.chunks_exact(width).take(count) {
+                values.push(column.fixed(bytes));
+            }
+        }
+        (_, None) => {
+            let mut position = 0;
+            for _ in 0..count {
+                let len = data.get(position..position + 4)?;
+                let len = u32::from_le_bytes(len.try_into().ok()?) as usize;
+                position += 4;
+                values.push(column.bytes(data.get(position..position.checked_add(len)?)?));
+                position += len;
+            }
+        }
+    }
+    Some(values)
+}
+
+// `count` values of a data page in `encoding`.
+fn values(
+    data: &[u8],
+    encoding: i64,
+    column: &Column,
+    count: usize,
+    dictionary: Option<&[String]>,
+) -> Result<Vec<String>> {
+    let corrupt = || invalid(format!("column {:?} has a corrupt page", column.name));
+    let values = match encoding {
+        PLAIN => plain(data, column, count),
+        PLAIN_DICTIONARY | RLE_DICTIONARY => {
+            let Some(dictionary) = dictionary else {
+                bail!(invalid(format!(
+                    "column {:?} has a dictionary encoded page without a dictionary",
+                    column.name
+                )));
+            };
+            let width = *data.first().ok_or_else(corrupt)? as u32;
+            let indices = rle_hybrid(&data[1..], width, count).ok_or_else(corrupt)?;
+            indices
+                .into_iter()
+                .map(|index| dictionary.get(index as usize).cloned())
+                .collect()
+        }
+        RLE if column.physical == BOOLEAN => data
+            .get(4..)
+            .and_then(|levels| rle_hybrid(levels, 1, count))
+            .map(|bits| bits.iter().map(|bit| (*bit == 1).to_string()).collect()),
+        DELTA_BINARY_PACKED if matches!(column.physical, INT32 | INT64) => delta_binary(data)
+            .map(|(values, _)| values.into_iter().map(|v| column.int(v)).collect()),
+        DELTA_LENGTH_BYTE_ARRAY if column.physical == BYTE_ARRAY => {
+            delta_length(data).map(|values| values.into_iter().map(|v| column.bytes(v)).collect())
+        }
+        DELTA_BYTE_ARRAY if matches!(column.physical, BYTE_ARRAY | FIXED_LEN_BYTE_ARRAY) => {
+            delta_strings(data).map(|values| values.iter().map(|v| column.bytes(v)).collect())
+        }
+        BYTE_STREAM_SPLIT if column.fixed_width().is_some() => {
+            // Byte i of every value, then byte i + 1
+            let width = column.fixed_width().unwrap_or(0);
+            if count.checked_mul(width).is_none_or(|len| data.len() < len) {
+                bail!(corrupt());
+            }
+            let mut joined = vec![0u8; count * width];
+            for (index, byte) in joined.iter_mut().enumerate() {
+                *byte = data[(index % width) * count + index / width];
+            }
+            plain(&joined, column, count)
+        }
+        encoding => bail!(unsupported(format!(
+            "encoding {} of column {:?}",
+            encoding, column.name
+        ))),
+    };
+    let values = values.ok_or_else(corrupt)?;
+    if values.len() != count {
+        bail!(corrupt());
+    }
+    Ok(values)
+}
+
+fn decompress(data: &[u8], codec: i64, size: usize) -> Result<Vec<u8>> {
+    let out = match codec {
+        UNCOMPRESSED => Some(data.to_vec()),
+        SNAPPY => compress::unsnappy(data),
+        GZIP => compress::gunzip(data, size),
+        LZ4_RAW => compress::unlz4_block(data, size),
+        LZ4 => hadoop_lz4(data, size).or_else(|| compress::unlz4_block(data, size)),
+        codec => bail!(unsupported(format!(
+            "{} compression",
+            CODECS.get(codec as usize).unwrap_or(&"unknown")
+        ))),
+    };
+    out.filter(|out| out.len() == size)
+        .ok_or_else(|| invalid("a compressed page is corrupt"))
+}
+
+// LZ4 blocks of the deprecated LZ4 codec, each after its decompressed and
+// compressed size as Hadoop writes them.
+fn hadoop_lz4(data: &[u8], size: usize) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(size);
+    let mut position = 0;
+    while position < data.len() {
+        let sizes = data.get(position..position + 8)?;
+        let expected = u32::from_be_bytes(sizes[..4].try_into().ok()?) as usize;
+        let len = u32::from_be_bytes(sizes[4..].try_into().ok()?) as usize;
+        position += 8;
+        let block = data.get(position..position.checked_add(len)?)?;
+        position += len;
+        out.extend(compress::unlz4_block(block, expected)?);
+    }
+    Some(out)
+}
+
+// Reads the cells of a column chunk.
+fn read_chunk(data: &[u8], chunk: &Struct, column: &Column) -> Result<Vec<String>> {
+    if chunk.get(1).is_some() {
+        bail!(unsupported("columns stored in other files"));
+    }
+    let metadata = chunk
+        .child(3)
+        .ok_or_else(|| invalid(format!("column {:?} has no metadata", column.name)))?;
+    let codec = metadata.int(4).unwrap_or(UNCOMPRESSED);
+    let count = metadata.int(5).unwrap_or(0).max(0) as usize;
+    let data_offset = metadata.int(9).unwrap_or(0);
+    let start = match metadata.int(11) {
+        Some(offset) if offset > 0 && offset < data_offset => offset,
+        _ => data_offset,
+    };
+    let size = metadata.int(7).unwrap_or(0);
+    let end = start.saturating_add(size).clamp(0, data.len() as i64) as usize;
+    let chunk_data = data
+        .get(start.max(0) as usize..end)
+        .ok_or_else(|| invalid(format!("column {:?} is out of the file", column.name)))?;
+
+    let truncated = || invalid(format!("column {:?} is truncated", column.name));
+    let mut cells = Vec::with_capacity(count.min(data.len()));
+    let mut dictionary = None;
+    let mut position = 0;
+    while cells.len() < count {
+        let (header, len) = thrift::read(chunk_data.get(position..).unwrap_or_default())
+            .ok_or_else(|| {
+                invalid(format!(
+                    "column {:?} has a corrupt page header",
+                    column.name
+                ))
+            })?;
+        position += len;
+        let compressed = header.int(3).unwrap_or(0).max(0) as usize;
+        let size = header.int(2).unwrap_or(0).max(0) as usize;
+        let body = chunk_data
+            .get(position..position + compressed)
+            .ok_or_else(truncated)?;
+        position += compressed;
+        let (values_count, encoding, defined, data) = match header.int(1) {
+            Some(DICTIONARY_PAGE) => {
+                let page = header.child(7).ok_or_else(truncated)?;
+                let count = page.int(1).unwrap_or(0).max(0) as usize;
+                let page = decompress(body, codec, size)?;
+                let values = plain(&page, column, count).ok_or_else(truncated)?;
+                dictionary = Some(values);
+                continue;
+            }
+            Some(DATA_PAGE) => {
+                let page = header.child(5).ok_or_else(truncated)?;
+                let count = page.int(1).unwrap_or(0).max(0) as usize;
+                let mut data = decompress(body, codec, size)?;
+                let defined = match column.optional {
+                    true => {
+                        let len = data.get(..4).ok_or_else(truncated)?;
+                        let len = u32::from_le_bytes(len.try_into()?) as usize;
+                        let levels = data.get(4..4 + len).ok_or_else(truncated)?;
+                        let levels = rle_hybrid(levels, 1, count).ok_or_else(truncated)?;
+                        data.drain(..4 + len);
+                        levels
+                    }
+                    false => vec![1; count],
+                };
+                (count, page.int(2).unwrap_or(PLAIN), defined, data)
+            }
+            Some(DATA_PAGE_V2) => {
+                let page = header.child(8).ok_or_else(truncated)?;
+                let count = page.int(1).unwrap_or(0).max(0) as usize;
+                let definition = page.int(5).unwrap_or(0).max(0) as usize;
+                let repetition = page.int(6).unwrap_or(0).max(0) as usize;
+                // Levels are never compressed in these pages
+                let levels = body
+                    .get(repetition..repetition + definition)
+                    .ok_or_else(truncated)?;
+                let rest = &body[repetition + definition..];
+                let data = match page.bool(7).unwrap_or(true) {
+                    true => decompress(rest, codec, size.saturating_sub(repetition + definition))?,
+                    false => rest.to_vec(),
+                };
+                let defined = match column.optional {
+                    true => rle_hybrid(levels, 1, count).ok_or_else(truncated)?,
+                    false => vec![1; count],
+                };
+                (count, page.int(4).unwrap_or(PLAIN), defined, data)
+            }
+            // Index pages and any other kind
+            _ => continue,
+        };
+        let present = defined.iter().filter(|level| **level == 1).count();
+        let mut present =
+            values(&data, encoding, column, present, dictionary.as_deref())?.into_iter();
+        for level in defined.iter().take(values_count) {
+            cells.push(match level {
+                1 => present.next().unwrap_or_default(),
+                _ => String::new(),
+            });
+        }
+    }
+    cells.truncate(count);
+    Ok(cells)
+}
+
+// Reads every row of a file, the column names as header.
+pub fn read(path: &Path) -> Result<Rows> {
+    let data = fs::read(path)?;
+    if data.len() < 12 || !data.starts_with(MAGIC) || !data.ends_with(MAGIC) {
+        bail!(invalid("it does not start and end with PAR1"));
+    }
+    let footer_len = u32::from_le_bytes(data[data.len() - 8..data.len() - 4].try_into()?);
+    let footer_start = (data.len() - 8)
+        .checked_sub(footer_len as usize)
+        .filter(|start| *start >= 4)
+        .ok_or_else(|| invalid("the footer is truncated"))?;
+    let (metadata, _) = thrift::read(&data[footer_start..data.len() - 8])
+        .ok_or_else(|| invalid("the file metadata is corrupt"))?;
+
+    let elements = metadata.list(2).unwrap_or_default();
+    let columns = elements
+        .iter()
+        .skip(1)
+        .map(|element| {
+            let element = element
+                .as_struct()
+                .ok_or_else(|| invalid("the schema is corrupt"))?;
+            Column::new(element)
+        })
+        .collect::<Result<Vec<Column>>>()?;
+
+    let mut rows: Vec<Vec<String>> = Vec::new();
+    for row_group in metadata.list(4).unwrap_or_default() {
+        let row_group = row_group
+            .as_struct()
+            .ok_or_else(|| invalid("a row group is corrupt"))?;
+        let count = row_group.int(3).unwrap_or(0).max(0) as usize;
+        let chunks = row_group.list(1).unwrap_or_default();
+        if chunks.len() != columns.len() {
+            bail!(invalid("a row group does not have every column"));
+        }
+        let start = rows.len();
+        rows.resize_with(start + count, || Vec::with_capacity(columns.len()));
+        for (chunk, column) in chunks.iter().zip(&columns) {
+            let chunk = chunk
+                .as_struct()
+                .ok_or_else(|| invalid("a column chunk is corrupt"))?;
+            let cells = read_chunk(&data, chunk, column)?;
+            if cells.len() != count {
+                bail!(invalid(format!(
+                    "column {:?} has {} values in a row group of {} rows",
+                    column.name,
+                    cells.len(),
+                    count
+                )));
+            }
+            for (row, cell) in rows[start..].iter_mut().zip(cells) {
+                row.push(cell);
+            }
+        }
+    }
+    Ok(Rows {
+        header: Some(columns.into_iter().map(|column| column.name).collect()),
+        rows,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{schema::ColumnType, sort::TempFile};
+    use std::fs;
+
+    fn scratch(name: &str) -> TempFile {
+        TempFile(std::env::temp_dir().join(format!(
+            "csv_handler-{}-parquet-{}",
+            std::process::id(),
+            name
+        )))
+    }
+
+    // Rows with a column of every type, nulls and text that needs escaping
+    // in other formats.
+    fn rows() -> (Vec<String>, Vec<Vec<String>>) {
+        let names = ["flag", "count", "price", "day", "seen", "note"];
+        let mut rows = vec![
+            [
+                "true",
+                "-7",
+                "3.5",
+                "2023-01-05",
+                "2023-01-05 10:20:30",
+                "héllo, \"world\"",
+            ],
+            ["false", "", "", "", "", ""],
+            [
+                "",
+                "9007199254740993",
+                "-0.25",
+                "1969-12-31",
+                "1900-01-01 00:00:00",
+                "x",
+            ],
+        ];
+        for _ in 0..40 {
+            rows.push([
+                "true",
+                "1",
+                "1000",
+                "2024-02-29",
+                "2038-01-19 03:14:08",
+                "repeated",
+            ]);
+        }
+        let strings = |row: &[&str]| row.iter().map(|cell| cell.to_string()).collect();
+        (
+            strings(&names),
+            rows.iter().map(|row| strings(row)).collect(),
+        )
+    }
+
+    fn table() -> Table {
+        let (names, rows) = rows();
+        let types = [
+            ColumnType::Boolean,
+            ColumnType::Integer,
+            ColumnType::Float,
+            ColumnType::Date,
+            ColumnType::DateTime,
+            ColumnType::String,
+        ];
+        let mut table = Table::new(names, &types);
+        for (index, row) in rows.iter().enumerate() {
+            table.add(index + 1, row).unwrap();
+        }
+        table
+    }
+
+    fn written(compression: Compression, group: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+        write(&mut out, &table(), compression, group).unwrap();
+        out
+    }
+
+    fn read_bytes(name: &str, data: &[u8]) -> Result<Rows> {
+        let file = scratch(name);
+        fs::write(&file.0, data).unwrap();
+        read(&file.0)
+    }
+
+    #[test]
+    fn round_trip() {
+        let (names, rows) = rows();
+        for compression in [
+            Compression::None,
+            Compression::Snappy,
+            Compression::Gzip,
+            Compression::Lz4,
+        ] {
+            for group in [1, 7, 65_536] {
+                let read = read_bytes("round_trip", &written(compression, group)).unwrap();
+                assert_eq!(read.header.as_ref(), Some(&names));
+                assert_eq!(read.rows, rows, "{:?} in groups of {}", compression, group);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_corrupt_input() {
+        let data = written(Compression::None, 16);
+        for len in 0..data.len() {
+            assert!(
+                read_bytes("truncated", &data[..len]).is_err(),
+                "{} bytes",
+                len
+            );
+        }
+        for position in 0..data.len() {
+            let mut data = data.clone();
+            data[position] ^= 0x5a;
+            let _ = read_bytes("flipped", &data);
+        }
+    }
+}
@@ -0,0 +1,388 @@
+// RFC 4180 record reader.
+//
+// Tokenizes a byte stream into records with a small state machine so that
+// quoted fields may contain delimiters, doubled quotes ("") and line breaks.
+// Records end at LF or CRLF unless the dialect names another terminator, and
+// every error carries the physical line and byte offset at which the input
+// stopped making sense.
+
+use crate::{
+    dialect::{Dialect, Terminator},
+    Error,
+};
+use anyhow::{bail, Result};
+use std::io::{BufRead, Seek, SeekFrom};
+
+// A single parsed record.
+#[derive(Debug)]
+pub struct Record {
+    pub fields: Vec<String>,
+    // Exact source text of the record, including its terminator and any
+    // blank lines that preceded it
+    pub raw: String,
+    // Whether every field of the record was enclosed in quotes
+    pub quoted: bool,
+    // Physical line and byte offset at which the record starts
+    pub line: usize,
+    pub offset: u64,
+    // Fields were padded or truncated after reading, so `raw` is stale
+    pub fitted: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    // At the beginning of a field, nothing consumed yet
+    FieldStart,
+    // Inside a field that did not start with a quote
+    Unquoted,
+    // Inside a quoted field
+    Quoted,
+    // Just saw a quote while inside a quoted field, either an escape or the end
+    QuoteInQuoted,
+    // Just saw the escape character, the next byte is taken literally
+    EscapeInUnquoted,
+    EscapeInQuoted,
+}
+
+pub struct Reader<R> {
+    inner: R,
+    dialect: Dialect,
+    line: usize,
+    offset: u64,
+    // Bytes consumed since the end of the previous record
+    raw: Vec<u8>,
+    done: bool,
+}
+
+impl<R: BufRead> Reader<R> {
+    pub fn new(inner: R, dialect: Dialect) -> Self {
+        Self {
+            inner,
+            dialect,
+            line: 1,
+            offset: 0,
+            raw: Vec::new(),
+            done: false,
+        }
+    }
+
+    // Byte offset and physical line the next record is read from.
+    pub fn position(&self) -> (u64, usize) {
+        (self.offset, self.line)
+    }
+
+    // Source text left over after the last record (trailing blank lines).
+    pub fn trailer(&self) -> String {
+        String::from_utf8_lossy(&self.raw).into_owned()
+    }
+
+    fn peek_byte(&mut self) -> Result<Option<u8>> {
+        Ok(self.inner.fill_buf()?.first().copied())
+    }
+
+    fn next_byte(&mut self) -> Result<Option<u8>> {
+        let byte = self.peek_byte()?;
+        if let Some(b) = byte {
+            self.inner.consume(1);
+            self.offset += 1;
+            self.raw.push(b);
+            if b == b'\n' {
+                self.line += 1;
+            }
+        }
+        Ok(byte)
+    }
+
+    // Whether the byte just read ends a record, consuming the LF of a CRLF pair.
+    fn is_terminator(&mut self, byte: u8) -> Result<bool> {
+        if let Terminator::Byte(t) = self.dialect.terminator {
+            return Ok(byte == t);
+        }
+        if byte == b'\r' && self.peek_byte()? == Some(b'\n') {
+            self.next_byte()?;
+            return Ok(true);
+        }
+        Ok(byte == b'\n')
+    }
+
+    fn malformed(&self, reason: &'static str) -> Error {
+        // `offset` already points past the offending byte
+        Error::MalformedRecord {
+            line: self.line,
+            offset: self.offset.saturating_sub(1),
+            reason,
+        }
+    }
+
+    fn read_record(&mut self) -> Result<Option<Record>> {
+        // Blank lines between records carry no data and are skipped.
+        loop {
+            let Some(byte) = self.peek_byte()? else {
+                return Ok(None);
+            };
+            let blank = match self.dialect.terminator {
+                Terminator::Byte(t) => byte == t,
+                _ => byte == b'\n' || byte == b'\r',
+            };
+            if !blank {
+                return self.read_fields(Vec::new()).map(Some);
+            }
+            self.next_byte()?;
+            if !self.is_terminator(byte)? {
+                // A lone CR that is part of the data
+                return self.read_fields(vec![byte]).map(Some);
+            }
+        }
+    }
+
+    fn read_fields(&mut self, mut field: Vec<u8>) -> Result<Record> {
+        let Dialect {
+            delimiter,
+            quote,
+            escape,
+            ..
+        } = self.dialect;
+        let line = self.line;
+        let offset = self.offset - field.len() as u64;
+        let mut raw_fields: Vec<Vec<u8>> = Vec::new();
+        let mut quoted = true;
+        let mut state = if field.is_empty() {
+            State::FieldStart
+        } else {
+            State::Unquoted
+        };
+
+        loop {
+            let Some(byte) = self.next_byte()? else {
+                if matches!(state, State::Quoted | State::EscapeInQuoted) {
+                    bail!(Error::MalformedRecord {
+                        line,
+                        offset,
+                        reason: "unterminated quoted field",
+                    });
+                }
+                quoted &= state == State::QuoteInQuoted;
+                raw_fields.push(field);
+                break;
+            };
+
+            match state {
+                State::FieldStart | State::Unquoted => {
+                    if state == State::FieldStart && byte == quote {
+                        state = State::Quoted;
+                    } else if byte == delimiter {
+                        quoted = false;
+                        raw_fields.push(std::mem::take(&mut field));
+                        state = State::FieldStart;
+                    } else if self.is_terminator(byte)? {
+                        quoted = false;
+                        raw_fields.push(field);
+                        break;
+                    } else if Some(byte) == escape {
+                        state = State::EscapeInUnquoted;
+                    } else if byte == quote {
+                        bail!(self.malformed("quote inside unquoted field"));
+                    } else {
+                        field.push(byte);
+                        state = State::Unquoted;
+                    }
+                }
+                State::Quoted => {
+                    if Some(byte) == escape {
+                        state = State::EscapeInQuoted;
+                    } else if byte == quote {
+                        state = State::QuoteInQuoted;
+                    } else {
+                        field.push(byte);
+                    }
+                }
+                State::QuoteInQuoted => {
+                    if byte == quote && escape.is_none() {
+                        field.push(quote);
+                        state = State::Quoted;
+                    } else if byte == delimiter {
+                        raw_fields.push(std::mem::take(&mut field));
+                        state = State::FieldStart;
+                    } else if self.is_terminator(byte)? {
+                        raw_fields.push(field);
+                        break;
+                    } else {
+                        bail!(self.malformed("unexpected character after closing quote"));
+                    }
+                }
+                State::EscapeInUnquoted => {
+                    field.push(byte);
+                    state = State::Unquoted;
+                }
+                State::EscapeInQuoted => {
+                    field.push(byte);
+                    state = State::Quoted;
+                }
+            }
+        }
+
+        let fields = raw_fields
+            .into_iter()
+            .map(|bytes| {
+                String::from_utf8(bytes).map_err(|_| Error::MalformedRecord {
+                    line,
+                    offset,
+                    reason: "record is not valid UTF-8",
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let raw = std::mem::take(&mut self.raw);
+        // Fields are valid UTF-8 and everything else consumed is ASCII.
+        let raw = String::from_utf8(raw).expect("record bytes are valid UTF-8");
+
+        Ok(Record {
+            fields,
+            raw,
+            quoted,
+            line,
+            offset,
+            fitted: false,
+        })
+    }
+}
+
+impl<R: BufRead + Seek> Reader<R> {
+    // Continues reading at the record that starts at byte `offset` on the
+    // physical line `line`, as remembered from an earlier `Record`.
+    pub fn seek(&mut self, offset: u64, line: usize) -> Result<()> {
+        self.inner.seek(SeekFrom::Start(offset))?;
+        self.offset = offset;
+        self.line = line;
+        self.raw.clear();
+        self.done = false;
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Iterator for Reader<R> {
+    type Item = Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.read_record() {
+            Ok(Some(record)) => Some(Ok(record)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                // The stream position is unreliable after a malformed record.
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(text: &str, dialect: Dialect) -> Result<Vec<Record>> {
+        Reader::new(text.as_bytes(), dialect).collect()
+    }
+
+    fn fields(text: &str, dialect: Dialect) -> Vec<Vec<String>> {
+        read(text, dialect)
+            .unwrap()
+            .into_iter()
+            .map(|record| record.fields)
+            .collect()
+    }
+
+    // Line and offset of the error a malformed input ends in.
+    fn malformed(text: &str) -> (usize, u64, &'static str) {
+        match read(text, Dialect::default()).map_err(|e| e.downcast::<Error>()) {
+            Err(Ok(Error::MalformedRecord {
+                line,
+                offset,
+                reason,
+            })) => (line, offset, reason),
+            other => panic!("{:?} is not malformed: {:?}", text, other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn quoted_fields() {
+        assert_eq!(
+            fields("\"a,b\",c\n\"say \"\"hi\"\"\",\"\"\n", Dialect::default()),
+            [vec!["a,b", "c"], vec!["say \"hi\"", ""]]
+        );
+        let records = read("\"x\",\"y\"\n\"x\",y\n", Dialect::default()).unwrap();
+        assert!(records[0].quoted);
+        assert!(!records[1].quoted);
+    }
+
+    #[test]
+    fn line_breaks_in_quotes() {
+        let records = read("a,\"one\ntwo\"\r\nb,\"x\r\ny\"\nc,d", Dialect::default()).unwrap();
+        let fields: Vec<_> = records.iter().map(|r| r.fields.clone()).collect();
+        assert_eq!(
+            fields,
+            [vec!["a", "one\ntwo"], vec!["b", "x\r\ny"], vec!["c", "d"]]
+        );
+        // Records start on the physical line after the breaks inside quotes
+        let lines: Vec<_> = records.iter().map(|r| (r.line, r.offset)).collect();
+        assert_eq!(lines, [(1, 0), (3, 13), (5, 22)]);
+        assert_eq!(records[1].raw, "b,\"x\r\ny\"\n");
+    }
+
+    #[test]
+    fn blank_lines_are_skipped() {
+        let records = read("\na,b\n\r\n\nc,d\n\n", Dialect::default()).unwrap();
+        let fields: Vec<_> = records.iter().map(|r| r.fields.clone()).collect();
+        assert_eq!(fields, [vec!["a", "b"], vec!["c", "d"]]);
+        // Skipped lines stay part of the raw text of the following record
+        assert_eq!(records[1].raw, "\r\n\nc,d\n");
+        assert_eq!(records[1].line, 5);
+    }
+
+    #[test]
+    fn escape_character() {
+        let dialect = Dialect {
+            escape: Some(b'\\'),
+            ..Dialect::default()
+        };
+        assert_eq!(
+            fields("a\\,b,\"say \\\"hi\\\"\",c\\\\\n", dialect),
+            [vec!["a,b", "say \"hi\"", "c\\"]]
+        );
+    }
+
+    #[test]
+    fn other_terminators() {
+        let dialect = Dialect {
+            delimiter: b';',
+            terminator: Terminator::Byte(b'\r'),
+            ..Dialect::default()
+        };
+        assert_eq!(
+            fields("a;b\rc;\"d\re\"\r", dialect),
+            [vec!["a", "b"], vec!["c", "d\re"]]
+        );
+    }
+
+    #[test]
+    fn malformed_records_are_located() {
+        assert_eq!(
+            malformed("a,b\nc,d\"e\n"),
+            (2, 7, "quote inside unquoted field")
+        );
+        assert_eq!(
+            malformed("a\n\"b\nc\"x,d\n"),
+            (3, 7, "unexpected character after closing quote")
+        );
+        assert_eq!(
+            malformed("a,b\nc,\"d\n"),
+            (2, 4, "unterminated quoted field")
+        );
+    }
+}
@@ -0,0 +1,548 @@
+// A small regular expression engine.
+//
+// Supports literals, `.`, classes such as `[a-z0-9_]` and `[^,]`, the
+// escapes `\d \w \s` and their negations, anchors `^` and `$`, groups with
+// alternation `(a|b)`, and the quantifiers `* + ? {n} {n,} {n,m}` with a
+// trailing `?` for lazy matching. Matching is unanchored unless the pattern
+// says otherwise, like most command line tools.
+//
+// Patterns are compiled into a program that runs as a Pike VM: every way the
+// pattern can match is followed at once, a character at a time, so matching
+// takes time linear in the text whatever the pattern, and never recurses.
+
+#[derive(Debug, Clone)]
+enum Node {
+    Char(char),
+    Any,
+    Class {
+        ranges: Vec<(char, char)>,
+        negated: bool,
+    },
+    Start,
+    End,
+    Concat(Vec<Node>),
+    Alternation(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: usize,
+        max: Option<usize>,
+    },
+}
+
+// An instruction of a compiled pattern.
+#[derive(Debug, Clone)]
+enum Inst {
+    // Consume a character matching the node, which is a Char, Any or Class
+    Char(Node),
+    Start,
+    End,
+    // Go on at both targets
+    Split(usize, usize),
+    Jump(usize),
+    Match,
+}
+
+// Most instructions a pattern may compile to, as counted repetitions copy
+// what they repeat
+const MAX_PROGRAM: usize = 100_000;
+
+#[derive(Debug, Clone)]
+pub struct Regex {
+    program: Vec<Inst>,
+    case_insensitive: bool,
+}
+
+// Deepest groups may nest. Parsing and compiling recurse into groups, so
+// this bounds both.
+const MAX_DEPTH: usize = 512;
+
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+    // Groups around the position
+    depth: usize,
+}
+
+const DIGIT: &[(char, char)] = &[('0', '9')];
+const WORD: &[(char, char)] = &[('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')];
+const SPACE: &[(char, char)] = &[('\t', '\r'), (' ', ' ')];
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn error(&self, reason: &str) -> String {
+        format!("{} at position {}", reason, self.position + 1)
+    }
+
+    // Letters and digits only escape to the classes and characters known, so
+    // that `\b` or `\x41` fail instead of quietly matching `b` or `x41`.
+    // Called just after the escaped character.
+    fn unknown_escape(&self, c: char) -> String {
+        format!("unknown escape \\{} at position {}", c, self.position - 1)
+    }
+
+    fn alternation(&mut self) -> Result<Node, String> {
+        let mut branches = vec![self.concat()?];
+        while self.peek() == Some('|') {
+            self.position += 1;
+            branches.push(self.concat()?);
+        }
+        Ok(match branches.len() {
+            1 => branches.pop().expect("one branch"),
+            _ => Node::Alternation(branches),
+        })
+    }
+
+    fn concat(&mut self) -> Result<Node, String> {
+        let mut nodes = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.atom()?;
+            nodes.push(self.quantifier(atom)?);
+        }
+        Ok(Node::Concat(nodes))
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        let c = self.peek().ok_or_else(|| self.error("unexpected end"))?;
+        self.position += 1;
+        Ok(match c {
+            '.' => Node::Any,
+            '^' => Node::Start,
+            '$' => Node::End,
+            '(' => {
+                // Non-capturing group syntax is accepted as a plain group
+                if self.chars[self.position..].starts_with(&['?', ':']) {
+                    self.position += 2;
+                }
+                if self.depth == MAX_DEPTH {
+                    return Err(self.error("groups nested too deeply"));
+                }
+                self.depth += 1;
+                let inner = self.alternation()?;
+                self.depth -= 1;
+                if self.peek() != Some(')') {
+                    return Err(self.error("missing ')'"));
+                }
+                self.position += 1;
+                inner
+            }
+            '[' => self.class()?,
+            '\\' => self.escape()?,
+            '*' | '+' | '?' | '{' => return Err(self.error("nothing to repeat")),
+            ')' => return Err(self.error("unmatched ')'")),
+            c => Node::Char(c),
+        })
+    }
+
+    fn escape(&mut self) -> Result<Node, String> {
+        let c = self
+            .peek()
+            .ok_or_else(|| self.error("trailing backslash"))?;
+        self.position += 1;
+        let class = |ranges: &[(char, char)], negated| Node::Class {
+            ranges: ranges.to_vec(),
+            negated,
+        };
+        Ok(match c {
+            'd' => class(DIGIT, false),
+            'D' => class(DIGIT, true),
+            'w' => class(WORD, false),
+            'W' => class(WORD, true),
+            's' => class(SPACE, false),
+            'S' => class(SPACE, true),
+            'n' => Node::Char('\n'),
+            't' => Node::Char('\t'),
+            'r' => Node::Char('\r'),
+            c if c.is_ascii_alphanumeric() => return Err(self.unknown_escape(c)),
+            c => Node::Char(c),
+        })
+    }
+
+    fn class(&mut self) -> Result<Node, String> {
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.position += 1;
+        }
+        let mut ranges = Vec::new();
+        let mut first = true;
+        loop {
+            let c = self.peek().ok_or_else(|| self.error("missing ']'"))?;
+            self.position += 1;
+            if c == ']' && !first {
+                break;
+            }
+            first = false;
+            let start = match c {
+                '\\' => {
+                    let escaped = self.peek().ok_or_else(|| self.error("missing ']'"))?;
+                    self.position += 1;
+                    match escaped {
+                        'd' => {
+                            ranges.extend_from_slice(DIGIT);
+                            continue;
+                        }
+                        'w' => {
+                            ranges.extend_from_slice(WORD);
+                            continue;
+                        }
+                        's' => {
+                            ranges.extend_from_slice(SPACE);
+                            continue;
+                        }
+                        'n' => '\n',
+                        't' => '\t',
+                        'r' => '\r',
+                        c if c.is_ascii_alphanumeric() => return Err(self.unknown_escape(c)),
+                        c => c,
+                    }
+                }
+                c => c,
+            };
+            let is_range =
+                self.peek() == Some('-') && self.chars.get(self.position + 1) != Some(&']');
+            if is_range {
+                let end = *self
+                    .chars
+                    .get(self.position + 1)
+                    .ok_or_else(|| self.error("missing ']'"))?;
+                self.position += 2;
+                if end < start {
+                    return Err(self.error("invalid class range"));
+                }
+                ranges.push((start, end));
+            } else {
+                ranges.push((start, start));
+            }
+        }
+        Ok(Node::Class { ranges, negated })
+    }
+
+    fn number(&mut self) -> Option<usize> {
+        let start = self.position;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.position += 1;
+        }
+        self.chars[start..self.position]
+            .iter()
+            .collect::<String>()
+            .parse()
+            .ok()
+    }
+
+    fn quantifier(&mut self, atom: Node) -> Result<Node, String> {
+        let (min, max) = match self.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => {
+                let start = self.position;
+                self.position += 1;
+                let Some(min) = self.number() else {
+                    // Not a repetition, `{` is taken literally
+                    self.position = start;
+                    return Ok(atom);
+                };
+                let max = match self.peek() {
+                    Some(',') => {
+                        self.position += 1;
+                        self.number()
+                    }
+                    _ => Some(min),
+                };
+                if self.peek() != Some('}') {
+                    return Err(self.error("missing '}'"));
+                }
+                if max.is_some_and(|max| max < min) {
+                    return Err(self.error("invalid repetition range"));
+                }
+                (min, max)
+            }
+            _ => return Ok(atom),
+        };
+        self.position += 1;
+        if matches!(atom, Node::Start | Node::End) {
+            return Err(self.error("nothing to repeat"));
+        }
+        // Laziness changes what a match covers, not whether the text matches
+        if self.peek() == Some('?') {
+            self.position += 1;
+        }
+        Ok(Node::Repeat {
+            node: Box::new(atom),
+            min,
+            max,
+        })
+    }
+}
+
+struct Compiler {
+    program: Vec<Inst>,
+}
+
+impl Compiler {
+    fn push(&mut self, inst: Inst) -> Result<usize, String> {
+        if self.program.len() >= MAX_PROGRAM {
+            return Err("the pattern is too large".to_string());
+        }
+        self.program.push(inst);
+        Ok(self.program.len() - 1)
+    }
+
+    // Points the open target of a split or jump at the next instruction.
+    fn patch(&mut self, at: usize) {
+        let target = self.program.len();
+        match &mut self.program[at] {
+            Inst::Split(_, second) => *second = target,
+            Inst::Jump(to) => *to = target,
+            _ => unreachable!("only splits and jumps are patched"),
+        }
+    }
+
+    fn node(&mut self, node: &Node) -> Result<(), String> {
+        match node {
+            Node::Char(_) | Node::Any | Node::Class { .. } => {
+                self.push(Inst::Char(node.clone()))?;
+            }
+            Node::Start => {
+                self.push(Inst::Start)?;
+            }
+            Node::End => {
+                self.push(Inst::End)?;
+            }
+            Node::Concat(nodes) => {
+                for node in nodes {
+                    self.node(node)?;
+                }
+            }
+            Node::Alternation(branches) => {
+                let mut jumps = Vec::new();
+                for (index, branch) in branches.iter().enumerate() {
+                    if index + 1 == branches.len() {
+                        self.node(branch)?;
+                        break;
+                    }
+                    let split = self.push(Inst::Split(self.program.len() + 1, 0))?;
+                    self.node(branch)?;
+                    jumps.push(self.push(Inst::Jump(0))?);
+                    self.patch(split);
+                }
+                for jump in jumps {
+                    self.patch(jump);
+                }
+            }
+            Node::Repeat { node, min, max } => {
+                for _ in 0..*min {
+                    self.node(node)?;
+                }
+                match max {
+                    None => {
+                        let split = self.push(Inst::Split(self.program.len() + 1, 0))?;
+                        self.node(node)?;
+                        self.push(Inst::Jump(split))?;
+                        self.patch(split);
+                    }
+                    Some(max) => {
+                        for _ in *min..*max {
+                            let split = self.push(Inst::Split(self.program.len() + 1, 0))?;
+                            self.node(node)?;
+                            self.patch(split);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Instructions waiting for the next character, each listed once.
+struct Threads {
+    list: Vec<usize>,
+}
+
+impl Regex {
+    pub fn new(pattern: &str, case_insensitive: bool) -> Result<Self, String> {
+        let mut parser = Parser {
+            chars: pattern.chars().collect(),
+            position: 0,
+            depth: 0,
+        };
+        let root = parser.alternation()?;
+        if parser.position < parser.chars.len() {
+            return Err(parser.error("unmatched ')'"));
+        }
+        let mut compiler = Compiler {
+            program: Vec::new(),
+        };
+        compiler.node(&root)?;
+        compiler.push(Inst::Match)?;
+        Ok(Self {
+            program: compiler.program,
+            case_insensitive,
+        })
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        let chars: Vec<char> = text.chars().collect();
+        // Position + 1 at which each instruction was last added, so that no
+        // instruction is followed twice for the same position
+        let mut added = vec![0; self.program.len()];
+        let mut stack = Vec::new();
+        let mut current = Threads { list: Vec::new() };
+        let mut next = Threads { list: Vec::new() };
+        for position in 0..=chars.len() {
+            // A match may start anywhere
+            if self.add(&mut current, &mut added, &mut stack, 0, position, &chars) {
+                return true;
+            }
+            let Some(&c) = chars.get(position) else {
+                break;
+            };
+            next.list.clear();
+            for &pc in &current.list {
+                if let Inst::Char(node) = &self.program[pc] {
+                    if self.char_matches(node, c)
+                        && self.add(
+                            &mut next,
+                            &mut added,
+                            &mut stack,
+                            pc + 1,
+                            position + 1,
+                            &chars,
+                        )
+                    {
+                        return true;
+                    }
+                }
+            }
+            std::mem::swap(&mut current, &mut next);
+        }
+        false
+    }
+
+    // Adds the thread at `pc` to `threads`, following splits, jumps and
+    // anchors to the instructions that consume a character. Returns whether
+    // the pattern matched.
+    fn add(
+        &self,
+        threads: &mut Threads,
+        added: &mut [usize],
+        stack: &mut Vec<usize>,
+        pc: usize,
+        position: usize,
+        text: &[char],
+    ) -> bool {
+        stack.push(pc);
+        while let Some(pc) = stack.pop() {
+            if added[pc] == position + 1 {
+                continue;
+            }
+            added[pc] = position + 1;
+            match self.program[pc] {
+                Inst::Char(_) => threads.list.push(pc),
+                Inst::Start if position == 0 => stack.push(pc + 1),
+                Inst::End if position == text.len() => stack.push(pc + 1),
+                Inst::Start | Inst::End => {}
+                Inst::Split(first, second) => {
+                    // The first target is followed first
+                    stack.push(second);
+                    stack.push(first);
+                }
+                Inst::Jump(to) => stack.push(to),
+                Inst::Match => {
+                    stack.clear();
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    fn char_matches(&self, node: &Node, c: char) -> bool {
+        let test = |c: char| match node {
+            Node::Char(expected) => *expected == c,
+            Node::Any => c != '\n',
+            Node::Class { ranges, negated } => {
+                ranges.iter().any(|(lo, hi)| (*lo..=*hi).contains(&c)) != *negated
+            }
+            _ => false,
+        };
+        if !self.case_insensitive {
+            return test(c);
+        }
+        test(c)
+            || c.to_lowercase().any(|l| l != c && test(l))
+            || c.to_uppercase().any(|u| u != c && test(u))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Regex;
+
+    fn matches(pattern: &str, text: &str) -> bool {
+        Regex::new(pattern, false).unwrap().is_match(text)
+    }
+
+    #[test]
+    fn syntax() {
+        assert!(matches("b.d", "abcde"));
+        assert!(!matches("^b", "abc"));
+        assert!(matches("^a[b-d]+e$", "abcde"));
+        assert!(matches("[^,]+,", "x,y"));
+        assert!(matches(r"^\d{4}-\d{2}$", "2023-01"));
+        assert!(!matches(r"^\d{4}-\d{2}$", "2023-1"));
+        assert!(matches("^(ab|cd){2,3}$", "abcdab"));
+        assert!(!matches("^(ab|cd){2,3}$", "ab"));
+        assert!(matches("^a.*?z$", "abcz"));
+        assert!(matches("^(a*)*$", ""));
+        assert!(matches("^x{2,}$", "xxxx"));
+        assert!(Regex::new("^abc$", true).unwrap().is_match("AbC"));
+        assert!(Regex::new("(", false).is_err());
+        assert!(Regex::new("a{3,1}", false).is_err());
+    }
+
+    #[test]
+    fn nested_repetition_is_linear() {
+        let text = format!("{}b", "a".repeat(40));
+        assert!(!matches("^(a+)+$", &text));
+        assert!(!matches("^(a|aa)*$", &text));
+    }
+
+    #[test]
+    fn long_text_does_not_overflow_the_stack() {
+        let text = "a".repeat(200_000);
+        assert!(!matches("a*b", &text));
+        assert!(matches("a*$", &text));
+    }
+
+    #[test]
+    fn deep_groups_are_rejected() {
+        let nested = |depth: usize| format!("{}a{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(matches(&nested(500), "a"));
+        assert!(Regex::new(&nested(100_000), false).is_err());
+    }
+
+    #[test]
+    fn unknown_escapes_are_rejected() {
+        for pattern in [r"\b", r"a\B", r"\x41", r"\p{L}", r"\1", r"[\x41]"] {
+            assert!(Regex::new(pattern, false).is_err(), "{}", pattern);
+        }
+        assert_eq!(
+            Regex::new(r"ab\q", false).err().as_deref(),
+            Some(r"unknown escape \q at position 3")
+        );
+        assert!(matches(r"\.\$\(\t", ".$(\t"));
+        assert!(matches(r"[\]\-\r]", "-"));
+    }
+
+    #[test]
+    fn huge_repetitions_are_rejected() {
+        assert!(Regex::new("((a{100}){100}){100}", false).is_err());
+    }
+}
@@ -0,0 +1,335 @@
+// Tables for pasting into documents: GitHub flavoured Markdown, HTML and
+// LaTeX `tabular`.
+//
+// The rows are measured first with the display layout, so the columns are
+// aligned as on screen: numeric ones to the right unless --align says
+// otherwise. Markdown is the markdown display style without fitting into the
+// terminal. HTML is escaped and written as a whole document or as a lone
+// table to paste into one. LaTeX source is padded to the column widths so it
+// reads as a table too. Cells are always written in full.
+
+use crate::{
+    convert::Format,
+    display::{self, Align, Layout, Options, Style, Table},
+};
+use anyhow::Result;
+use std::io::Write;
+
+// How HTML tables are written.
+#[derive(Debug, Clone, Default)]
+pub struct Html {
+    // Only the table rather than a whole document
+    pub fragment: bool,
+    // CSS classes of the table element
+    pub classes: Option<String>,
+    // Keep the header row in view while the table scrolls
+    pub sticky_header: bool,
+    // Title of the document
+    pub title: String,
+}
+
+// Measures the rows before any is written.
+pub struct Measure {
+    format: Format,
+    row_numbers: bool,
+    // Column names, numbers when the file has no header
+    names: Vec<String>,
+    has_header: bool,
+    layout: Layout,
+    cols: usize,
+}
+
+impl Measure {
+    pub fn new(format: Format, header: Option<&[String]>, cols: usize, options: &Options) -> Self {
+        let row_numbers = options.row_numbers;
+        let names: Vec<String> = match header {
+            Some(header) => header.to_vec(),
+            None => (1..=cols).map(|n| n.to_string()).collect(),
+        };
+        let names: Vec<String> = row_numbers
+            .then(|| "#".to_string())
+            .into_iter()
+            .chain(names)
+            .collect();
+        // Row numbers are written as a column of their own, right-aligned
+        let align = row_numbers
+            .then_some(Some(Align::Right))
+            .into_iter()
+            .chain(options.align.iter().copied())
+            .collect();
+        let options = Options {
+            align,
+            style: match format {
+                Format::Markdown => Style::Markdown,
+                _ => Style::Plain,
+            },
+            row_numbers: false,
+            max_width: None,
+            width: None,
+            ..options.clone()
+        };
+        // Markdown tables always have a header, other formats only with one
+        let has_header = header.is_some();
+        let measured = (has_header || format == Format::Markdown).then(|| {
+            names
+                .iter()
+                .map(|name| written(format, name))
+                .collect::<Vec<String>>()
+        });
+        Self {
+            format,
+            row_numbers,
+            cols: names.len(),
+            layout: Layout::new(measured.as_ref(), names.len(), &options),
+            names,
+            has_header,
+        }
+    }
+
+    pub fn add(&mut self, number: usize, row: &[String]) {
+        let number = self.row_numbers.then(|| number.to_string());
+        let cells: Vec<String> = number
+            .into_iter()
+            .chain(row.iter().map(|cell| written(self.format, cell)))
+            .collect();
+        self.cols = self.cols.max(cells.len());
+        self.layout.measure(&cells);
+    }
+
+    pub fn finish<W: Write>(self, out: W, html: Html) -> Renderer<W> {
+        Renderer {
+            out,
+            format: self.format,
+            html,
+            row_numbers: self.row_numbers,
+            names: self.names,
+            has_header: self.has_header,
+            table: self.layout.finish(),
+            cols: self.cols,
+        }
+    }
+}
+
+// Writes the measured rows.
+pub struct Renderer<W: Write> {
+    out: W,
+    format: Format,
+    html: Html,
+    row_numbers: bool,
+    names: Vec<String>,
+    has_header: bool,
+    table: Table,
+    cols: usize,
+}
+
+impl<W: Write> Renderer<W> {
+    // Writes everything up to the first row.
+    pub fn start(&mut self) -> Result<()> {
+        match self.format {
+            Format::Markdown => {
+                for line in self.table.header_lines(Some(&self.names)) {
+                    writeln!(self.out, "{}", line)?;
+                }
+            }
+            Format::Html => self.html_start()?,
+            _ => self.latex_start()?,
+        }
+        Ok(())
+    }
+
+    pub fn write(&mut self, number: usize, row: &[String]) -> Result<()> {
+        let row: Vec<String> = self
+            .row_numbers
+            .then(|| number.to_string())
+            .into_iter()
+            .chain(row.iter().cloned())
+            .collect();
+        match self.format {
+            Format::Markdown => writeln!(self.out, "{}", self.table.format_row(number, &row))?,
+            Format::Html => self.html_row("td", &row)?,
+            _ => self.latex_row(&row)?,
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        match self.format {
+            Format::Markdown => {}
+            Format::Html => {
+                writeln!(self.out, "</tbody>")?;
+                writeln!(self.out, "</table>")?;
+                if !self.html.fragment {
+                    writeln!(self.out, "</body>")?;
+                    writeln!(self.out, "</html>")?;
+                }
+            }
+            _ => {
+                writeln!(self.out, "\\hline")?;
+                writeln!(self.out, "\\end{{tabular}}")?;
+            }
+        }
+        self.out.flush()?;
+        Ok(())
+    }
+
+    fn html_start(&mut self) -> Result<()> {
+        let html = &self.html;
+        if !html.fragment {
+            writeln!(self.out, "<!DOCTYPE html>")?;
+            writeln!(self.out, "<html>")?;
+            writeln!(self.out, "<head>")?;
+            writeln!(self.out, "<meta charset=\"utf-8\">")?;
+            writeln!(self.out, "<title>{}</title>", html_escape(&html.title))?;
+            writeln!(self.out, "<style>")?;
+            writeln!(self.out, "table {{ border-collapse: collapse; }}")?;
+            writeln!(
+                self.out,
+                "th, td {{ border: 1px solid #ccc; padding: 0.25em 0.5em; vertical-align: top; }}"
+            )?;
+            writeln!(self.out, "th {{ background: #f4f4f4; }}")?;
+            if html.sticky_header {
+                writeln!(self.out, "thead th {{ position: sticky; top: 0; }}")?;
+            }
+            writeln!(self.out, "</style>")?;
+            writeln!(self.out, "</head>")?;
+            writeln!(self.out, "<body>")?;
+        }
+        match &html.classes {
+            Some(classes) => writeln!(self.out, "<table class=\"{}\">", html_escape(classes))?,
+            None => writeln!(self.out, "<table>")?,
+        }
+        if self.has_header {
+            writeln!(self.out, "<thead>")?;
+            let names = self.names.clone();
+            self.html_row("th", &names)?;
+            writeln!(self.out, "</thead>")?;
+        }
+        writeln!(self.out, "<tbody>")?;
+        Ok(())
+    }
+
+    // Writes a row of `th` or `td` cells. A fragment cannot style its header
+    // from a style sheet, so a sticky one is styled on every cell.
+    fn html_row(&mut self, tag: &str, row: &[String]) -> Result<()> {
+        let sticky = tag == "th" && self.html.sticky_header && self.html.fragment;
+        let mut line = String::from("<tr>");
+        for col in 0..self.cols {
+            let mut styles = Vec::new();
+            match self.table.column_align(col) {
+                Align::Left => {}
+                Align::Right => styles.push("text-align: right"),
+                Align::Center => styles.push("text-align: center"),
+            }
+            if sticky {
+                styles.push("position: sticky; top: 0");
+            }
+            let cell = row.get(col).map_or("", String::as_str);
+            let cell = html_escape(cell)
+                .replace("\r\n", "<br>")
+                .replace('\n', "<br>");
+            match styles.is_empty() {
+                true => line.push_str(&format!("<{}>{}</{}>", tag, cell, tag)),
+                false => line.push_str(&format!(
+                    "<{} style=\"{}\">{}</{}>",
+                    tag,
+                    styles.join("; "),
+                    cell,
+                    tag
+                )),
+            }
+        }
+        line.push_str("</tr>");
+        writeln!(self.out, "{}", line)?;
+        Ok(())
+    }
+
+    fn latex_start(&mut self) -> Result<()> {
+        let spec: String = (0..self.cols)
+            .map(|col| match self.table.column_align(col) {
+                Align::Left => 'l',
+                Align::Right => 'r',
+                Align::Center => 'c',
+            })
+            .collect();
+        writeln!(self.out, "\\begin{{tabular}}{{{}}}", spec)?;
+        writeln!(self.out, "\\hline")?;
+        if self.has_header {
+            let names = self.names.clone();
+            self.latex_row(&names)?;
+            writeln!(self.out, "\\hline")?;
+        }
+        Ok(())
+    }
+
+    fn latex_row(&mut self, row: &[String]) -> Result<()> {
+        let cells: Vec<String> = (0..self.cols)
+            .map(|col| {
+                let align = self.table.column_align(col);
+                let cell = latex_cell(row.get(col).map_or("", String::as_str), align);
+                display::pad(&cell, self.table.column_width(col), align)
+            })
+            .collect();
+        writeln!(self.out, "{} \\\\", cells.join(" & "))?;
+        Ok(())
+    }
+}
+
+// A cell as measured. The alignment of a LaTeX column is not known yet, but
+// only changes a letter of a multi-line cell.
+fn written(format: Format, cell: &str) -> String {
+    match format {
+        Format::Latex => latex_cell(cell, Align::Left),
+        _ => cell.to_string(),
+    }
+}
+
+fn html_escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// Escapes the characters LaTeX gives a meaning to. A cell of several lines
+// becomes a stack of them aligned like the column.
+fn latex_cell(cell: &str, align: Align) -> String {
+    let lines: Vec<String> = cell
+        .lines()
+        .map(|line| {
+            let mut out = String::with_capacity(line.len());
+            for c in line.trim_end_matches('\r').chars() {
+                match c {
+                    '\\' => out.push_str("\\textbackslash{}"),
+                    '~' => out.push_str("\\textasciitilde{}"),
+                    '^' => out.push_str("\\textasciicircum{}"),
+                    '<' => out.push_str("\\textless{}"),
+                    '>' => out.push_str("\\textgreater{}"),
+                    '|' => out.push_str("\\textbar{}"),
+                    '&' | '%' | '$' | '#' | '_' | '{' | '}' => {
+                        out.push('\\');
+                        out.push(c);
+                    }
+                    c => out.push(c),
+                }
+            }
+            out
+        })
+        .collect();
+    if lines.len() < 2 {
+        return lines.into_iter().next().unwrap_or_default();
+    }
+    let position = match align {
+        Align::Left => 'l',
+        Align::Right => 'r',
+        Align::Center => 'c',
+    };
+    format!("\\shortstack[{}]{{{}}}", position, lines.join(" \\\\ "))
+}
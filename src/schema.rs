@@ -0,0 +1,555 @@
+// Column types inferred from the data or declared in a schema file.
+//
+// A schema file is a JSON object with a `columns` array; every column has a
+// `name` (a header name, or a 1-based number for files without a header), a
+// `type` and a `nullable` flag. Inferred schemas also carry the share of
+// non-null values that parse as the inferred type as `confidence`.
+//
+// Columns may add constraints checked by the validate command: `required`
+// (the column must be in the file, the default), a regular expression
+// `pattern`, an `enum` of allowed values, `minimum` and `maximum` for
+// numeric and date columns, and `unique`.
+
+use crate::{
+    datetime::{self, DateTime},
+    json::{self, Json},
+    regex::Regex,
+    resolve_column, Error,
+};
+use anyhow::{Context, Result};
+use std::{cmp::Ordering, fmt, fs, path::Path};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnType {
+    Boolean,
+    Integer,
+    Float,
+    Date,
+    DateTime,
+    String,
+}
+
+// Types tried during inference, most specific first.
+const CANDIDATES: [ColumnType; 5] = [
+    ColumnType::Boolean,
+    ColumnType::Integer,
+    ColumnType::Float,
+    ColumnType::Date,
+    ColumnType::DateTime,
+];
+
+// Share of values a type must parse to be inferred despite a few outliers.
+const MIN_CONFIDENCE: f64 = 0.9;
+
+impl ColumnType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ColumnType::Boolean => "boolean",
+            ColumnType::Integer => "integer",
+            ColumnType::Float => "float",
+            ColumnType::Date => "date",
+            ColumnType::DateTime => "datetime",
+            ColumnType::String => "string",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        CANDIDATES
+            .into_iter()
+            .chain([ColumnType::String])
+            .find(|ty| ty.name().eq_ignore_ascii_case(name))
+    }
+
+    // Whether a non-null value parses as this type. Integers are floats too,
+    // and dates are datetimes without a time.
+    pub fn accepts(&self, value: &str) -> bool {
+        let value = value.trim();
+        match self {
+            ColumnType::Boolean => parse_bool(value).is_some(),
+            ColumnType::Integer => value.parse::<i64>().is_ok(),
+            ColumnType::Float => parse_float(value).is_some(),
+            ColumnType::Date => datetime::parse(value).is_some_and(|d| d.time.is_none()),
+            ColumnType::DateTime => datetime::parse(value).is_some(),
+            ColumnType::String => true,
+        }
+    }
+}
+
+pub fn parse_bool(value: &str) -> Option<bool> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "true" | "yes" | "y" | "t" => Some(true),
+        "false" | "no" | "n" | "f" => Some(false),
+        _ => None,
+    }
+}
+
+// Like `str::parse`, without the words `inf` and `nan`.
+pub fn parse_float(value: &str) -> Option<f64> {
+    value
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|n| n.is_finite() && value.bytes().any(|b| b.is_ascii_digit()))
+}
+
+// Empty cells and the usual null markers.
+pub fn is_null(value: &str) -> bool {
+    matches!(
+        value.trim().to_ascii_lowercase().as_str(),
+        "" | "null" | "na" | "n/a"
+    )
+}
+
+// A bound of a numeric or date column.
+#[derive(Debug, Clone)]
+pub enum Limit {
+    Number(f64),
+    // Parsed date and the text it was given as
+    Date(DateTime, String),
+}
+
+impl Limit {
+    // How a value compares to the limit, None when it does not parse.
+    pub fn compare(&self, value: &str) -> Option<Ordering> {
+        match self {
+            Limit::Number(limit) => parse_float(value).map(|n| n.total_cmp(limit)),
+            Limit::Date(limit, _) => datetime::parse(value).map(|d| d.cmp(limit)),
+        }
+    }
+
+    fn to_json(&self) -> Json {
+        match self {
+            Limit::Number(n) => Json::float(*n),
+            Limit::Date(_, s) => Json::String(s.clone()),
+        }
+    }
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Limit::Number(n) => write!(f, "{}", json::number(*n)),
+            Limit::Date(_, s) => write!(f, "{}", s),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ColumnSchema {
+    pub name: String,
+    pub ty: ColumnType,
+    pub nullable: bool,
+    pub confidence: Option<f64>,
+    pub required: bool,
+    // Source of the pattern along with the compiled expression
+    pub pattern: Option<(String, Regex)>,
+    pub allowed: Option<Vec<String>>,
+    pub minimum: Option<Limit>,
+    pub maximum: Option<Limit>,
+    pub unique: bool,
+}
+
+impl ColumnSchema {
+    fn new(name: String, ty: ColumnType, nullable: bool) -> Self {
+        Self {
+            name,
+            ty,
+            nullable,
+            confidence: None,
+            required: true,
+            pattern: None,
+            allowed: None,
+            minimum: None,
+            maximum: None,
+            unique: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Schema {
+    pub columns: Vec<ColumnSchema>,
+}
+
+impl Schema {
+    pub fn load(path: &Path) -> Result<Self> {
+        let source = fs::read_to_string(path)?;
+        Self::from_json(&json::parse(&source)?)
+    }
+
+    pub fn from_json(json: &Json) -> Result<Self> {
+        let invalid = |reason: String| Error::InvalidSchema(reason);
+        let columns = json
+            .get("columns")
+            .and_then(Json::as_array)
+            .ok_or_else(|| invalid("expected an object with a \"columns\" array".to_string()))?;
+        let columns = columns
+            .iter()
+            .enumerate()
+            .map(|(index, column)| {
+                let at = |reason: &str| invalid(format!("column {}: {}", index + 1, reason));
+                let name = column
+                    .get("name")
+                    .and_then(Json::as_str)
+                    .ok_or_else(|| at("\"name\" must be a string"))?;
+                let ty = match column.get("type") {
+                    None => ColumnType::String,
+                    Some(ty) => ty
+                        .as_str()
+                        .and_then(ColumnType::from_name)
+                        .ok_or_else(|| at("unknown \"type\""))?,
+                };
+                let flag = |key: &str, default: bool| match column.get(key) {
+                    None => Ok(default),
+                    Some(value) => value
+                        .as_bool()
+                        .ok_or_else(|| at(&format!("{:?} must be a boolean", key))),
+                };
+                let limit = |key: &str| {
+                    let Some(value) = column.get(key) else {
+                        return Ok(None);
+                    };
+                    let limit = match ty {
+                        ColumnType::Integer | ColumnType::Float => {
+                            value.as_f64().map(Limit::Number)
+                        }
+                        ColumnType::Date | ColumnType::DateTime => value.as_str().and_then(|s| {
+                            datetime::parse(s).map(|date| Limit::Date(date, s.to_string()))
+                        }),
+                        _ => {
+                            return Err(at(&format!(
+                                "{:?} only applies to numeric and date columns",
+                                key
+                            )))
+                        }
+                    };
+                    limit
+                        .map(Some)
+                        .ok_or_else(|| at(&format!("{:?} must be a {}", key, ty.name())))
+                };
+                let pattern = match column.get("pattern") {
+                    None => None,
+                    Some(pattern) => {
+                        let source = pattern
+                            .as_str()
+                            .ok_or_else(|| at("\"pattern\" must be a string"))?;
+                        let regex = Regex::new(source, false)
+                            .map_err(|reason| at(&format!("invalid \"pattern\": {}", reason)))?;
+                        Some((source.to_string(), regex))
+                    }
+                };
+                let allowed = match column.get("enum") {
+                    None => None,
+                    Some(values) => Some(
+                        values
+                            .as_array()
+                            .and_then(|values| {
+                                values
+                                    .iter()
+                                    .map(|v| v.as_str().map(String::from))
+                                    .collect::<Option<Vec<_>>>()
+                            })
+                            .ok_or_else(|| at("\"enum\" must be an array of strings"))?,
+                    ),
+                };
+                Ok(ColumnSchema {
+                    confidence: column.get("confidence").and_then(Json::as_f64),
+                    required: flag("required", true)?,
+                    pattern,
+                    allowed,
+                    minimum: limit("minimum")?,
+                    maximum: limit("maximum")?,
+                    unique: flag("unique", false)?,
+                    ..ColumnSchema::new(name.to_string(), ty, flag("nullable", true)?)
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self { columns })
+    }
+
+    pub fn to_json(&self) -> Json {
+        let columns = self
+            .columns
+            .iter()
+            .map(|column| {
+                let mut members = vec![
+                    ("name".to_string(), Json::String(column.name.clone())),
+                    (
+                        "type".to_string(),
+                        Json::String(column.ty.name().to_string()),
+                    ),
+                    ("nullable".to_string(), Json::Bool(column.nullable)),
+                ];
+                if let Some(confidence) = column.confidence {
+                    let rounded = (confidence * 1000.0).round() / 1000.0;
+                    members.push(("confidence".to_string(), Json::float(rounded)));
+                }
+                if !column.required {
+                    members.push(("required".to_string(), Json::Bool(false)));
+                }
+                if let Some((pattern, _)) = &column.pattern {
+                    members.push(("pattern".to_string(), Json::String(pattern.clone())));
+                }
+                if let Some(allowed) = &column.allowed {
+                    let values = allowed.iter().cloned().map(Json::String).collect();
+                    members.push(("enum".to_string(), Json::Array(values)));
+                }
+                for (key, limit) in [("minimum", &column.minimum), ("maximum", &column.maximum)] {
+                    if let Some(limit) = limit {
+                        members.push((key.to_string(), limit.to_json()));
+                    }
+                }
+                if column.unique {
+                    members.push(("unique".to_string(), Json::Bool(true)));
+                }
+                Json::Object(members)
+            })
+            .collect();
+        Json::Object(vec![("columns".to_string(), Json::Array(columns))])
+    }
+
+    // Pairs every schema column with its 0-based index in a file, None for
+    // columns the file does not have.
+    pub fn locate(
+        &self,
+        header: Option<&[String]>,
+        cols: usize,
+    ) -> Vec<(&ColumnSchema, Option<usize>)> {
+        self.columns
+            .iter()
+            .map(|column| (column, resolve_column(header, cols, &column.name).ok()))
+            .collect()
+    }
+
+    // Declared type of every column of a file, by 0-based index.
+    pub fn column_types(
+        &self,
+        header: Option<&[String]>,
+        cols: usize,
+    ) -> Result<Vec<Option<ColumnType>>> {
+        let mut types = vec![None; cols];
+        for (column, index) in self.locate(header, cols) {
+            match index {
+                Some(index) => types[index] = Some(column.ty),
+                None if column.required => {
+                    return Err(anyhow::Error::from(Error::UnknownColumn(
+                        column.name.clone(),
+                    )))
+                    .context("A required schema column is not in the file")
+                }
+                None => {}
+            }
+        }
+        Ok(types)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct ColumnStats {
+    values: usize,
+    nulls: usize,
+    // Values accepted by each of the candidate types
+    matches: [usize; CANDIDATES.len()],
+}
+
+// Accumulates what the cells of every column look like.
+pub struct Inference {
+    stats: Vec<ColumnStats>,
+}
+
+impl Inference {
+    pub fn new(cols: usize) -> Self {
+        Self {
+            stats: vec![ColumnStats::default(); cols],
+        }
+    }
+
+    pub fn add(&mut self, row: &[String]) {
+        if row.len() > self.stats.len() {
+            self.stats.resize(row.len(), ColumnStats::default());
+        }
+        // Cells missing from short rows count as nulls
+        for (index, stats) in self.stats.iter_mut().enumerate() {
+            let value = row.get(index).map_or("", String::as_str);
+            if is_null(value) {
+                stats.nulls += 1;
+                continue;
+            }
+            stats.values += 1;
+            for (count, ty) in stats.matches.iter_mut().zip(CANDIDATES) {
+                if ty.accepts(value) {
+                    *count += 1;
+                }
+            }
+        }
+    }
+
+    // Picks the most specific type every value parses as, or failing that
+    // the one most values parse as if it is nearly all of them. Columns are
+    // named after the header, or numbered from 1.
+    pub fn finish(self, header: Option<&[String]>) -> Schema {
+        let columns = self
+            .stats
+            .into_iter()
+            .enumerate()
+            .map(|(index, stats)| {
+                let name = header
+                    .and_then(|header| header.get(index).cloned())
+                    .unwrap_or_else(|| (index + 1).to_string());
+                let (ty, confidence) = if stats.values == 0 {
+                    (ColumnType::String, 0.0)
+                } else {
+                    let shares = stats
+                        .matches
+                        .iter()
+                        .zip(CANDIDATES)
+                        .map(|(&count, ty)| (ty, count as f64 / stats.values as f64));
+                    let best = shares
+                        .clone()
+                        .find(|&(_, share)| share == 1.0)
+                        .or_else(|| {
+                            shares.reduce(|best, candidate| {
+                                if candidate.1 > best.1 {
+                                    candidate
+                                } else {
+                                    best
+                                }
+                            })
+                        })
+                        .filter(|&(_, share)| share >= MIN_CONFIDENCE);
+                    best.unwrap_or((ColumnType::String, 1.0))
+                };
+                ColumnSchema {
+                    confidence: Some(confidence),
+                    ..ColumnSchema::new(name, ty, stats.nulls > 0)
+                }
+            })
+            .collect();
+        Schema { columns }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn infer(header: &[&str], rows: &[&[&str]]) -> Schema {
+        let header: Vec<String> = header.iter().map(|name| name.to_string()).collect();
+        let mut inference = Inference::new(header.len());
+        for row in rows {
+            let row: Vec<String> = row.iter().map(|cell| cell.to_string()).collect();
+            inference.add(&row);
+        }
+        inference.finish(Some(&header))
+    }
+
+    fn types(schema: &Schema) -> Vec<ColumnType> {
+        schema.columns.iter().map(|column| column.ty).collect()
+    }
+
+    #[test]
+    fn most_specific_type() {
+        let schema = infer(
+            &["flag", "count", "price", "day", "at", "name"],
+            &[
+                &["yes", "1", "1.5", "2024-01-31", "2024-01-31", "x"],
+                &["false", "-20", "3", "31.12.2023", "2024-02-01 10:30", "1"],
+                &[
+                    "T",
+                    "300",
+                    "1e3",
+                    "2024/02/29",
+                    "2024-02-01T10:30:59",
+                    "2024-01-01",
+                ],
+            ],
+        );
+        use ColumnType::*;
+        assert_eq!(
+            types(&schema),
+            [Boolean, Integer, Float, Date, DateTime, String]
+        );
+        assert!(schema
+            .columns
+            .iter()
+            .all(|column| column.confidence == Some(1.0) && !column.nullable));
+    }
+
+    #[test]
+    fn outliers_below_min_confidence() {
+        let mostly = |numbers: usize, others: usize| {
+            let mut rows = vec![&["7"][..]; numbers];
+            rows.extend(vec![&["seven"][..]; others]);
+            let column = infer(&["n"], &rows).columns.remove(0);
+            (column.ty, column.confidence.unwrap())
+        };
+        assert_eq!(mostly(19, 1), (ColumnType::Integer, 0.95));
+        assert_eq!(mostly(9, 1), (ColumnType::Integer, 0.9));
+        assert_eq!(mostly(8, 2), (ColumnType::String, 1.0));
+    }
+
+    #[test]
+    fn nulls_make_columns_nullable() {
+        let schema = infer(
+            &["a", "b", "c", "d"],
+            &[
+                &["1", "x", "", "1"],
+                &["NA", "y", "null"],
+                &["3", "z", "n/a", "2"],
+            ],
+        );
+        let nullable: Vec<bool> = schema.columns.iter().map(|c| c.nullable).collect();
+        // Short rows count as nulls in the missing cells
+        assert_eq!(nullable, [true, false, true, true]);
+        // Nulls do not count against the type
+        assert_eq!(schema.columns[0].ty, ColumnType::Integer);
+        assert_eq!(schema.columns[0].confidence, Some(1.0));
+        assert_eq!(schema.columns[2].ty, ColumnType::String);
+        assert_eq!(schema.columns[2].confidence, Some(0.0));
+    }
+
+    #[test]
+    fn columns_without_header_are_numbered() {
+        let mut inference = Inference::new(1);
+        inference.add(&["1".to_string(), "a".to_string()]);
+        let names: Vec<String> = inference
+            .finish(None)
+            .columns
+            .into_iter()
+            .map(|c| c.name)
+            .collect();
+        assert_eq!(names, ["1", "2"]);
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let inferred = infer(&["id", "when"], &[&["1", "2024-01-01"], &["2", ""]]);
+        let json = inferred.to_json().to_string(false);
+        let loaded = Schema::from_json(&json::parse(&json).unwrap()).unwrap();
+        assert_eq!(loaded.to_json().to_string(false), json);
+        assert_eq!(types(&loaded), types(&inferred));
+
+        let source = r#"{"columns":[{"name":"id","type":"integer","nullable":false,"minimum":1,"maximum":99,"unique":true},{"name":"code","type":"string","nullable":true,"required":false,"pattern":"^[A-Z]{2}$","enum":["DE","FR"]},{"name":"day","type":"date","nullable":true,"minimum":"2024-01-01"}]}"#;
+        let schema = Schema::from_json(&json::parse(source).unwrap()).unwrap();
+        assert_eq!(schema.to_json().to_string(false), source);
+        assert!(schema.columns[0].unique && !schema.columns[1].required);
+    }
+
+    #[test]
+    fn invalid_schemas_are_rejected() {
+        for source in [
+            r#"[]"#,
+            r#"{"columns":[{"type":"integer"}]}"#,
+            r#"{"columns":[{"name":"a","type":"decimal"}]}"#,
+            r#"{"columns":[{"name":"a","type":"string","minimum":1}]}"#,
+            r#"{"columns":[{"name":"a","pattern":"\\q"}]}"#,
+        ] {
+            let error = Schema::from_json(&json::parse(source).unwrap()).unwrap_err();
+            assert!(
+                matches!(error.downcast::<Error>(), Ok(Error::InvalidSchema(_))),
+                "{}",
+                source
+            );
+        }
+    }
+}
@@ -0,0 +1,491 @@
+// Multi-key stable sorting, in memory or as an external merge sort.
+//
+// Keys are given as `col`, `-col` for descending order, and may override the
+// comparison mode with a suffix such as `price:numeric`. Without a suffix or
+// a mode for all keys, a key's mode follows its type in the schema, if any.
+// Values that do not parse in a numeric or date key sort after those that do.
+
+use crate::{
+    datetime,
+    dialect::{Dialect, Terminator},
+    parser::{Reader, Record},
+    resolve_column,
+    schema::ColumnType,
+    writer::{QuoteStyle, Writer},
+};
+use anyhow::Result;
+use clap::ValueEnum;
+use std::{
+    cmp::Ordering,
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::PathBuf,
+    process,
+    sync::atomic::{AtomicUsize, Ordering as AtomicOrdering},
+    vec,
+};
+
+// Most sorted runs merged at once. Beyond that, runs are merged in passes so
+// the number of open files stays bounded.
+const MAX_MERGE_RUNS: usize = 64;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum SortMode {
+    // Byte-wise string order
+    Lexicographic,
+    Numeric,
+    // Digit runs compare as numbers, so `file2` sorts before `file10`
+    Natural,
+    Date,
+}
+
+#[derive(Debug, Clone)]
+struct SortKey {
+    col: usize,
+    descending: bool,
+    mode: SortMode,
+}
+
+#[derive(Debug, Clone)]
+pub struct Sort {
+    keys: Vec<SortKey>,
+    ignore_case: bool,
+    // Bytes of records held in memory before spilling a sorted run to disk
+    memory_limit: usize,
+}
+
+impl Sort {
+    pub fn new(
+        specs: &[String],
+        header: Option<&[String]>,
+        cols: usize,
+        mode: Option<SortMode>,
+        types: &[Option<ColumnType>],
+        ignore_case: bool,
+        memory_limit: usize,
+    ) -> Result<Self> {
+        let keys = specs
+            .iter()
+            .map(|spec| {
+                let (descending, spec) = match spec.strip_prefix('-') {
+                    Some(rest) => (true, rest),
+                    None => (false, spec.as_str()),
+                };
+                // A suffix only counts if it names a mode, so names may contain ':'
+                let (spec, suffix) = match spec.rsplit_once(':') {
+                    Some((col, suffix)) => match SortMode::from_str(suffix, true) {
+                        Ok(suffix) => (col, Some(suffix)),
+                        Err(_) => (spec, None),
+                    },
+                    None => (spec, None),
+                };
+                let col = resolve_column(header, cols, spec)?;
+                let declared = types.get(col).copied().flatten().map(|ty| match ty {
+                    ColumnType::Integer | ColumnType::Float => SortMode::Numeric,
+                    ColumnType::Date | ColumnType::DateTime => SortMode::Date,
+                    ColumnType::Boolean | ColumnType::String => SortMode::Lexicographic,
+                });
+                Ok(SortKey {
+                    col,
+                    descending,
+                    mode: suffix
+                        .or(mode)
+                        .or(declared)
+                        .unwrap_or(SortMode::Lexicographic),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            keys,
+            ignore_case,
+            memory_limit,
+        })
+    }
+
+    pub fn compare(&self, a: &[String], b: &[String]) -> Ordering {
+        for key in &self.keys {
+            let left = a.get(key.col).map_or("", String::as_str);
+            let right = b.get(key.col).map_or("", String::as_str);
+            let ordering = self.compare_values(left, right, key);
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        Ordering::Equal
+    }
+
+    fn compare_values(&self, a: &str, b: &str, key: &SortKey) -> Ordering {
+        let direction = |ordering: Ordering| {
+            if key.descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        };
+        let text = || match key.mode {
+            SortMode::Natural if self.ignore_case => {
+                natural_cmp(&a.to_lowercase(), &b.to_lowercase())
+            }
+            SortMode::Natural => natural_cmp(a, b),
+            _ if self.ignore_case => a.to_lowercase().cmp(&b.to_lowercase()),
+            _ => a.cmp(b),
+        };
+        match key.mode {
+            SortMode::Lexicographic | SortMode::Natural => direction(text()),
+            SortMode::Numeric => {
+                let parse = |s: &str| s.trim().parse::<f64>().ok().filter(|n| !n.is_nan());
+                parsed_first(parse(a), parse(b), |x, y| direction(x.total_cmp(y)))
+                    .then_with(|| direction(text()))
+            }
+            SortMode::Date => {
+                let (x, y) = (datetime::parse(a), datetime::parse(b));
+                parsed_first(x, y, |x, y| direction(x.cmp(y))).then_with(|| direction(text()))
+            }
+        }
+    }
+
+    // Stable, so rows with equal keys keep their input order.
+    pub fn sort_by_fields<T>(&self, rows: &mut [T], fields: impl Fn(&T) -> &[String]) {
+        rows.sort_by(|a, b| self.compare(fields(a), fields(b)));
+    }
+
+    // Consumes `records` and yields them in order. Records are gathered up to
+    // the memory limit; if the input does not fit, every batch is sorted and
+    // spilled to a temporary file and the files are merged on the way out.
+    pub fn sorted(
+        &self,
+        records: impl Iterator<Item = Result<Record>>,
+        dialect: Dialect,
+    ) -> Result<Sorted> {
+        let mut runs = Vec::new();
+        let mut batch = Vec::new();
+        let mut batch_size = 0;
+
+        for record in records {
+            let record = record?;
+            batch_size += record_size(&record);
+            batch.push(record);
+            if batch_size >= self.memory_limit {
+                runs.push(self.spill(&mut batch, dialect)?);
+                batch_size = 0;
+            }
+        }
+
+        self.sort_by_fields(&mut batch, |r| &r.fields);
+        if runs.is_empty() {
+            return Ok(Sorted::InMemory(batch.into_iter()));
+        }
+        if !batch.is_empty() {
+            runs.push(self.spill(&mut batch, dialect)?);
+        }
+
+        while runs.len() > MAX_MERGE_RUNS {
+            let mut merged = Vec::new();
+            let mut rest = runs.into_iter();
+            loop {
+                let group: Vec<_> = rest.by_ref().take(MAX_MERGE_RUNS).collect();
+                match group.len() {
+                    0 => break,
+                    1 => merged.extend(group),
+                    _ => merged.push(self.merge_to_file(group, dialect)?),
+                }
+            }
+            runs = merged;
+        }
+        self.merge(runs, dialect)
+    }
+
+    fn merge(&self, runs: Vec<TempFile>, dialect: Dialect) -> Result<Sorted> {
+        let mut heads = Vec::with_capacity(runs.len());
+        for run in runs {
+            let mut reader = Reader::new(BufReader::new(File::open(&run.0)?), dialect);
+            let head = reader.next().transpose()?;
+            heads.push((head, reader, run));
+        }
+        Ok(Sorted::Merge {
+            sort: self.clone(),
+            heads,
+        })
+    }
+
+    // Merges consecutive runs into one, so ties still go to the earliest run.
+    fn merge_to_file(&self, runs: Vec<TempFile>, dialect: Dialect) -> Result<TempFile> {
+        let run = TempFile::unique();
+        let file = BufWriter::new(File::create(&run.0)?);
+        let mut writer = Writer::new(file, QuoteStyle::Minimal, dialect);
+        for record in self.merge(runs, dialect)? {
+            writer.write_raw(&record?.raw)?;
+        }
+        writer.flush()?;
+        Ok(run)
+    }
+
+    fn spill(&self, batch: &mut Vec<Record>, dialect: Dialect) -> Result<TempFile> {
+        self.sort_by_fields(batch, |r| &r.fields);
+        let run = TempFile::unique();
+        let file = BufWriter::new(File::create(&run.0)?);
+        let mut writer = Writer::new(file, QuoteStyle::Minimal, dialect);
+        for record in batch.drain(..) {
+            if record.fitted {
+                // Re-encoded with the record's own line ending, so spilling
+                // does not mix LF into a CRLF file
+                let mut source = dialect;
+                if dialect.terminator == Terminator::Auto && record.raw.ends_with("\r\n") {
+                    source.terminator = Terminator::Crlf;
+                }
+                let mut encoded = Vec::new();
+                Writer::new(&mut encoded, QuoteStyle::Minimal, source)
+                    .write_record(&record.fields)?;
+                writer.write_raw(&String::from_utf8(encoded)?)?;
+            } else {
+                writer.write_raw(&record.raw)?;
+            }
+        }
+        writer.flush()?;
+        Ok(run)
+    }
+}
+
+// Rough in-memory footprint of a record.
+fn record_size(record: &Record) -> usize {
+    let fields: usize = record.fields.iter().map(|f| f.len() + 24).sum();
+    record.raw.len() + fields + 64
+}
+
+// Orders values that parsed before those that did not, whatever the direction.
+fn parsed_first<T>(a: Option<T>, b: Option<T>, cmp: impl Fn(&T, &T) -> Ordering) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => cmp(&a, &b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+// Compares runs of digits by numeric value and everything else by character.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+    loop {
+        match (a.peek(), b.peek()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let take_digits = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+                    let mut digits = String::new();
+                    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+                        digits.push(c);
+                    }
+                    digits
+                };
+                let x = take_digits(&mut a);
+                let y = take_digits(&mut b);
+                let x_trimmed = x.trim_start_matches('0');
+                let y_trimmed = y.trim_start_matches('0');
+                let ordering = x_trimmed
+                    .len()
+                    .cmp(&y_trimmed.len())
+                    .then_with(|| x_trimmed.cmp(y_trimmed))
+                    .then_with(|| x.len().cmp(&y.len()));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let ordering = x.cmp(y);
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+// A temporary file removed when dropped.
+pub struct TempFile(pub PathBuf);
+
+impl TempFile {
+    pub fn unique() -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let n = COUNTER.fetch_add(1, AtomicOrdering::Relaxed);
+        let name = format!("csv_handler-{}-{}.tmp", process::id(), n);
+        Self(std::env::temp_dir().join(name))
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+pub enum Sorted {
+    InMemory(vec::IntoIter<Record>),
+    // The next record of every sorted run, in run order
+    Merge {
+        sort: Sort,
+        heads: Vec<(Option<Record>, Reader<BufReader<File>>, TempFile)>,
+    },
+}
+
+impl Iterator for Sorted {
+    type Item = Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (sort, heads) = match self {
+            Sorted::InMemory(records) => return records.next().map(Ok),
+            Sorted::Merge { sort, heads } => (sort, heads),
+        };
+        // Ties go to the earliest run, which keeps the merge stable.
+        let mut smallest: Option<usize> = None;
+        for (index, (head, _, _)) in heads.iter().enumerate() {
+            let Some(record) = head else {
+                continue;
+            };
+            let smaller = match smallest.and_then(|s| heads[s].0.as_ref()) {
+                Some(best) => sort.compare(&record.fields, &best.fields) == Ordering::Less,
+                None => true,
+            };
+            if smaller {
+                smallest = Some(index);
+            }
+        }
+        let (head, reader, _) = &mut heads[smallest?];
+        let next = match reader.next().transpose() {
+            Ok(next) => next,
+            Err(e) => return Some(Err(e)),
+        };
+        std::mem::replace(head, next).map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(text: &str) -> Vec<Result<Record>> {
+        Reader::new(text.as_bytes(), Dialect::default()).collect()
+    }
+
+    fn sort(specs: &[&str], cols: usize, memory_limit: usize) -> Sort {
+        let specs: Vec<String> = specs.iter().map(|s| s.to_string()).collect();
+        Sort::new(&specs, None, cols, None, &[], false, memory_limit).unwrap()
+    }
+
+    // Sorts the rows of `text` in memory by header-named keys.
+    fn sorted_rows(text: &str, specs: &[&str], mode: Option<SortMode>) -> Vec<String> {
+        let mut rows: Vec<Vec<String>> = records(text)
+            .into_iter()
+            .map(|record| record.unwrap().fields)
+            .collect();
+        let header = rows.remove(0);
+        let specs: Vec<String> = specs.iter().map(|s| s.to_string()).collect();
+        let sort = Sort::new(
+            &specs,
+            Some(&header),
+            header.len(),
+            mode,
+            &[],
+            false,
+            1 << 20,
+        );
+        sort.unwrap().sort_by_fields(&mut rows, |row| row);
+        rows.into_iter().map(|row| row.join(",")).collect()
+    }
+
+    #[test]
+    fn keys_in_order_and_stable() {
+        let text = "team,score,name\nb,2,w\na,10,x\nb,10,y\na,10,z\na,9,v\n";
+        assert_eq!(
+            sorted_rows(text, &["team", "-score:numeric"], None),
+            ["a,10,x", "a,10,z", "a,9,v", "b,10,y", "b,2,w"]
+        );
+        // Lexicographic by default, equal keys keep their input order
+        assert_eq!(
+            sorted_rows(text, &["score"], None),
+            ["a,10,x", "b,10,y", "a,10,z", "b,2,w", "a,9,v"]
+        );
+        assert_eq!(
+            sorted_rows(text, &["score"], Some(SortMode::Numeric)),
+            ["b,2,w", "a,9,v", "a,10,x", "b,10,y", "a,10,z"]
+        );
+    }
+
+    #[test]
+    fn natural_order() {
+        let text = "file\nfile10\nfile2\nFile1\nfile02\nfile1\n";
+        assert_eq!(
+            sorted_rows(text, &["file:natural"], None),
+            ["File1", "file1", "file2", "file02", "file10"]
+        );
+    }
+
+    #[test]
+    fn date_order() {
+        let text = "when\n2024-03-01\nunknown\n1.2.2024\n2024/01/15 10:30\n2024-01-15\n";
+        assert_eq!(
+            sorted_rows(text, &["when:date"], None),
+            [
+                "2024-01-15",
+                "2024/01/15 10:30",
+                "1.2.2024",
+                "2024-03-01",
+                "unknown"
+            ]
+        );
+        // Unparsed values stay last when descending
+        assert_eq!(sorted_rows(text, &["-when:date"], None)[4], "unknown");
+    }
+
+    #[test]
+    fn spilled_runs_merge_like_memory() {
+        let text: String = (0..500)
+            .map(|n| format!("{},{}\n", (n * 37) % 11, n))
+            .collect();
+        let fields = |memory_limit| -> Vec<Vec<String>> {
+            sort(&["1:numeric"], 2, memory_limit)
+                .sorted(records(&text).into_iter(), Dialect::default())
+                .unwrap()
+                .map(|record| record.unwrap().fields)
+                .collect()
+        };
+        let in_memory = fields(1 << 20);
+        assert_eq!(in_memory.len(), 500);
+        // A few records per run, so several runs but a single merge pass
+        assert_eq!(fields(1000), in_memory);
+    }
+
+    #[test]
+    fn many_runs_merge_in_passes() {
+        // A limit of one byte spills every record into its own run
+        let text: String = (0..200).map(|n| format!("{},{}\n", n % 7, n)).collect();
+        let sorted: Vec<Vec<String>> = sort(&["1"], 2, 1)
+            .sorted(records(&text).into_iter(), Dialect::default())
+            .unwrap()
+            .map(|record| record.unwrap().fields)
+            .collect();
+        let mut expected: Vec<Vec<String>> = (0..200)
+            .map(|n| vec![(n % 7).to_string(), n.to_string()])
+            .collect();
+        expected.sort_by_key(|fields| fields[0].clone());
+        assert_eq!(sorted, expected);
+    }
+
+    #[test]
+    fn fitted_records_keep_their_line_ending() {
+        let mut input = records("b,1\r\na,2,extra\r\n");
+        let mut fitted = input.pop().unwrap().unwrap();
+        fitted.fields.truncate(2);
+        fitted.fitted = true;
+        input.push(Ok(fitted));
+        let raw: Vec<String> = sort(&["1"], 2, 1)
+            .sorted(input.into_iter(), Dialect::default())
+            .unwrap()
+            .map(|record| record.unwrap().raw)
+            .collect();
+        assert_eq!(raw, ["a,2\r\n", "b,1\r\n"]);
+    }
+}
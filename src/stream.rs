@@ -0,0 +1,613 @@
+// Streaming access to a CSV file.
+//
+// `CSVStream` never holds more than a page of records in memory: rows are
+// read lazily from disk on every pass, and edits are recorded against row
+// numbers and applied while the input is copied to the output. When the file
+// has an up to date index, pages are read starting right at their first row
+// and edits copy the records between them without parsing them.
+
+use crate::{
+    apply_modification,
+    columns::ColumnOp,
+    dialect::{Dialect, Terminator},
+    display::{self, Layout},
+    expr::Filter,
+    fit_fields, fit_record,
+    index::Index,
+    pager,
+    parser::{Reader, Record},
+    resolve_column,
+    sort::Sort,
+    writer::{self, QuoteStyle, Writer},
+    CSVManipulation, Error, FitPolicy, RaggedPolicy,
+};
+use anyhow::{bail, Context, Result};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+// Lazily yields the data records of a file, after its header if any.
+pub struct Rows {
+    pub header: Option<Record>,
+    reader: Reader<BufReader<File>>,
+    ragged: RaggedPolicy,
+    // Field count of the first record, once read
+    expected: usize,
+    // Records the filter rejects are skipped
+    filter: Option<Filter>,
+    // 1-based number of the last record read, for error messages
+    row_index: usize,
+}
+
+impl Rows {
+    pub fn open(
+        path: &Path,
+        dialect: Dialect,
+        has_header: bool,
+        ragged: RaggedPolicy,
+    ) -> Result<Self> {
+        let mut reader = Reader::new(BufReader::new(File::open(path)?), dialect);
+        let header = if has_header {
+            reader.next().transpose()?
+        } else {
+            None
+        };
+        Ok(Self {
+            expected: header.as_ref().map_or(0, |h| h.fields.len()),
+            header,
+            reader,
+            ragged,
+            filter: None,
+            row_index: 0,
+        })
+    }
+
+    pub fn filtered(mut self, filter: Option<Filter>) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    // Continues at the record starting at byte `offset` on line `line`, which
+    // is data row `row_index` + 1.
+    pub fn seek(&mut self, offset: u64, line: usize, row_index: usize) -> Result<()> {
+        if self.expected == 0 {
+            // The first record decides the field count when there is no header
+            self.next().transpose()?;
+        }
+        self.reader.seek(offset, line)?;
+        self.row_index = row_index;
+        Ok(())
+    }
+
+    // Source text left over after the last record, once the rows are exhausted.
+    pub fn trailer(&self) -> String {
+        self.reader.trailer()
+    }
+}
+
+impl Iterator for Rows {
+    type Item = Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let mut record = match self.reader.next()? {
+                Ok(record) => record,
+                Err(e) => return Some(Err(e)),
+            };
+            if let Err(e) = fit_record(&mut record, self.expected, self.ragged) {
+                return Some(Err(e));
+            }
+            if self.expected == 0 {
+                self.expected = record.fields.len();
+            }
+            self.row_index += 1;
+            let Some(filter) = &self.filter else {
+                return Some(Ok(record));
+            };
+            match filter.keeps(&record.fields) {
+                Ok(true) => return Some(Ok(record)),
+                Ok(false) => {}
+                Err(e) => return Some(Err(e.context(format!("In row {}", self.row_index)))),
+            }
+        }
+    }
+}
+
+// Rows between checkpoints of a `Cursor`.
+const CHECKPOINT: usize = 1024;
+
+// Random access to the rows of a file for the pager, reading only the rows
+// asked for. With an index rows are found through it, otherwise the position of every `CHECKPOINT`th row is remembered on the
+// way, so going back or jumping ahead seeks close to the row instead of
+// reading from the start.
+pub struct Cursor<'a> {
+    stream: &'a CSVStream,
+    // Byte offset and line of rows `CHECKPOINT`, 2 * `CHECKPOINT` and so on
+    checkpoints: Vec<(u64, usize)>,
+    // Number of rows, once the end of the file has been read
+    len: Option<usize>,
+}
+
+impl<'a> Cursor<'a> {
+    pub fn new(stream: &'a CSVStream) -> Self {
+        Self {
+            stream,
+            checkpoints: Vec::new(),
+            len: stream.indexed_count(),
+        }
+    }
+}
+
+impl pager::Source for Cursor<'_> {
+    fn rows(&mut self, start: usize, count: usize) -> Result<Vec<Vec<String>>> {
+        if self.stream.usable_index().is_some() {
+            let (rows, skip) = self.stream.rows_from(start)?;
+            let rows = rows.skip(skip).take(count);
+            return rows.map(|record| record.map(|r| r.fields)).collect();
+        }
+        let mut rows = self.stream.rows()?;
+        let checkpoint = (start / CHECKPOINT).min(self.checkpoints.len());
+        let mut index = checkpoint * CHECKPOINT;
+        if checkpoint > 0 {
+            let (offset, line) = self.checkpoints[checkpoint - 1];
+            rows.seek(offset, line, index)?;
+        }
+        let mut page = Vec::new();
+        while page.len() < count {
+            let Some(record) = rows.next().transpose()? else {
+                self.len = Some(index);
+                break;
+            };
+            if index == (self.checkpoints.len() + 1) * CHECKPOINT {
+                self.checkpoints.push((record.offset, record.line));
+            }
+            if index >= start {
+                page.push(record.fields);
+            }
+            index += 1;
+        }
+        Ok(page)
+    }
+
+    fn len(&self) -> Option<usize> {
+        self.len
+    }
+
+    fn count(&mut self) -> Result<usize> {
+        loop {
+            if let Some(len) = self.len {
+                return Ok(len);
+            }
+            // Each step reads on from the last checkpoint to the next one
+            self.rows((self.checkpoints.len() + 1) * CHECKPOINT, 1)?;
+        }
+    }
+}
+
+enum Edit {
+    Delete,
+    Modify {
+        col: Option<usize>,
+        values: Vec<String>,
+    },
+}
+
+pub struct CSVStream {
+    path: PathBuf,
+    dialect: Dialect,
+    has_header: bool,
+    ragged: RaggedPolicy,
+    header: Option<Vec<String>>,
+    cols: usize,
+    // Pending edits keyed by 1-based data row number
+    edits: BTreeMap<usize, Edit>,
+    // New rows keyed by the 1-based data row they are inserted before
+    inserts: BTreeMap<usize, Vec<Vec<String>>>,
+    appends: Vec<Vec<String>>,
+    // Column operations applied to every record of the input after its edits
+    column_ops: Vec<ColumnOp>,
+    header_changed: bool,
+    // Order to write the rows in, row numbers of edits refer to this order
+    sort: Option<Sort>,
+    // Only rows the filter keeps are read, row numbers count those alone
+    filter: Option<Filter>,
+    // Sidecar index written by the `index` command, if it matches the file
+    index: Option<Index>,
+}
+
+impl CSVStream {
+    // Reads only the first record, to learn the header and column count.
+    pub fn open(
+        path: PathBuf,
+        dialect: Dialect,
+        has_header: bool,
+        ragged: RaggedPolicy,
+    ) -> Result<Self> {
+        let mut rows = Rows::open(&path, dialect, has_header, ragged)?;
+        let (header, cols) = match rows.header.take() {
+            Some(header) => {
+                let cols = header.fields.len();
+                (Some(header.fields), cols)
+            }
+            None => (None, rows.next().transpose()?.map_or(0, |r| r.fields.len())),
+        };
+        Ok(Self {
+            dialect,
+            has_header,
+            ragged,
+            header,
+            cols,
+            edits: BTreeMap::new(),
+            inserts: BTreeMap::new(),
+            appends: Vec::new(),
+            column_ops: Vec::new(),
+            header_changed: false,
+            sort: None,
+            filter: None,
+            index: Index::load(&path, dialect)?,
+            path,
+        })
+    }
+
+    fn rows(&self) -> Result<Rows> {
+        Ok(
+            Rows::open(&self.path, self.dialect, self.has_header, self.ragged)?
+                .filtered(self.filter.clone()),
+        )
+    }
+
+    // The index, as long as it describes the rows being read: the file as it
+    // is, unfiltered.
+    fn usable_index(&self) -> Option<&Index> {
+        self.index.as_ref().filter(|_| self.filter.is_none())
+    }
+
+    fn indexed_count(&self) -> Option<usize> {
+        let index = self.usable_index()?;
+        Some(index.records().saturating_sub(usize::from(self.has_header)))
+    }
+
+    // Rows from the 0-based data row `start` on, seeking straight there when
+    // there is an index. Returns the rows and how many of them are still to be
+    // skipped.
+    fn rows_from(&self, start: usize) -> Result<(Rows, usize)> {
+        let mut rows = self.rows()?;
+        let Some(index) = self.usable_index() else {
+            return Ok((rows, start));
+        };
+        let (offset, line) = index.start(start + usize::from(self.has_header))?;
+        rows.seek(offset, line, start)?;
+        Ok((rows, 0))
+    }
+
+    pub fn has_edits(&self) -> bool {
+        !self.edits.is_empty()
+            || !self.inserts.is_empty()
+            || !self.appends.is_empty()
+            || self.header_changed
+            || self.sort.is_some()
+            || self.filter.is_some()
+    }
+
+    // Copies the input to `out`, applying pending edits. Untouched records are
+    // copied verbatim unless `quote_style` asks for every record to be
+    // re-encoded. Quoting and terminator of new and edited records follow the
+    // first record of the input.
+    pub fn write_to<W: Write>(&self, out: W, quote_style: Option<QuoteStyle>) -> Result<()> {
+        let only_edits = self.inserts.is_empty()
+            && self.appends.is_empty()
+            && self.column_ops.is_empty()
+            && !self.header_changed
+            && self.sort.is_none();
+        if let Some(index) = self.usable_index() {
+            // Records are copied without being read, so none may need fitting
+            if only_edits && quote_style.is_none() && self.ragged == RaggedPolicy::Keep {
+                return self.write_indexed(out, index);
+            }
+        }
+        let mut rows = self.rows()?;
+        let mut dialect = self.dialect;
+        let mut detected_style = QuoteStyle::Minimal;
+
+        let mut writer = None;
+        let mut out = Some(out);
+        let mut writer_for = |first: Option<&Record>| {
+            if let Some(record) = first {
+                if dialect.terminator == Terminator::Auto && record.raw.ends_with("\r\n") {
+                    dialect.terminator = Terminator::Crlf;
+                }
+                if record.quoted {
+                    detected_style = QuoteStyle::Always;
+                }
+            }
+            let style = quote_style.unwrap_or(detected_style);
+            Writer::new(out.take().expect("writer is created once"), style, dialect)
+        };
+
+        if let Some(header) = rows.header.take() {
+            let writer = writer.get_or_insert_with(|| writer_for(Some(&header)));
+            match (&self.header, quote_style) {
+                (Some(changed), _) if self.header_changed => writer.write_record(changed)?,
+                (_, Some(_)) => writer.write_record(&header.fields)?,
+                (_, None) => writer.write_raw(&header.raw)?,
+            }
+        }
+
+        let reshape = |fields: &mut Vec<String>, row_index: usize| -> Result<()> {
+            for op in &self.column_ops {
+                op.apply(fields, false)
+                    .with_context(|| format!("In row {}", row_index))?;
+            }
+            Ok(())
+        };
+        let reshaped = !self.column_ops.is_empty();
+
+        let records: Box<dyn Iterator<Item = Result<Record>>> = match &self.sort {
+            Some(sort) => Box::new(sort.sorted(rows.by_ref(), self.dialect)?),
+            None => Box::new(rows.by_ref()),
+        };
+        let mut row_count = 0;
+        for (index, record) in records.enumerate() {
+            let mut record = record?;
+            let row_index = index + 1;
+            row_count = row_index;
+            let writer = writer.get_or_insert_with(|| writer_for(Some(&record)));
+            for row in self.inserts.get(&row_index).into_iter().flatten() {
+                writer.write_record(row)?;
+            }
+            match self.edits.get(&row_index) {
+                Some(Edit::Delete) => {}
+                Some(Edit::Modify { col, values }) => {
+                    apply_modification(&mut record.fields, *col, values.clone())?;
+                    reshape(&mut record.fields, row_index)?;
+                    writer.write_record(&record.fields)?;
+                }
+                None if reshaped || quote_style.is_some() || record.fitted => {
+                    reshape(&mut record.fields, row_index)?;
+                    writer.write_record(&record.fields)?;
+                }
+                None => writer.write_raw(&record.raw)?,
+            }
+        }
+
+        let out_of_bound = self
+            .edits
+            .keys()
+            .find(|&&index| index == 0 || index > row_count);
+        if let Some(&index) = out_of_bound {
+            bail!(Error::RowIndexOutOfBound {
+                index,
+                rows: row_count,
+            });
+        }
+        let out_of_bound = self
+            .inserts
+            .keys()
+            .find(|&&index| index == 0 || index > row_count + 1);
+        if let Some(&index) = out_of_bound {
+            bail!(Error::RowIndexOutOfBound {
+                index,
+                rows: row_count + 1,
+            });
+        }
+
+        let trailing = self.inserts.get(&(row_count + 1)).into_iter().flatten();
+        let new_rows: Vec<_> = trailing.chain(&self.appends).collect();
+        if writer.is_none() && new_rows.is_empty() {
+            return Ok(());
+        }
+        let mut writer = writer.unwrap_or_else(|| writer_for(None));
+        for row in new_rows {
+            writer.write_record(row)?;
+        }
+        if quote_style.is_none() {
+            writer.write_raw(&rows.trailer())?;
+        }
+        writer.flush()
+    }
+
+    // Like `write_to` for modifications and deletions alone: the bytes between
+    // edited records are copied as they are, found through the index, so only
+    // the modified records are parsed.
+    fn write_indexed<W: Write>(&self, out: W, index: &Index) -> Result<()> {
+        let header = usize::from(self.has_header);
+        let rows = index.records().saturating_sub(header);
+        let out_of_bound = self.edits.keys().find(|&&index| index == 0 || index > rows);
+        if let Some(&index) = out_of_bound {
+            bail!(Error::RowIndexOutOfBound { index, rows });
+        }
+
+        // Quoting and terminator of edited records follow the first record
+        let mut records = Reader::new(BufReader::new(File::open(&self.path)?), self.dialect);
+        let mut dialect = self.dialect;
+        let mut style = QuoteStyle::Minimal;
+        if let Some(first) = records.next().transpose()? {
+            if dialect.terminator == Terminator::Auto && first.raw.ends_with("\r\n") {
+                dialect.terminator = Terminator::Crlf;
+            }
+            if first.quoted {
+                style = QuoteStyle::Always;
+            }
+        }
+        let mut writer = Writer::new(out, style, dialect);
+
+        let mut input = File::open(&self.path)?;
+        let mut copied = 0;
+        for (&row_index, edit) in &self.edits {
+            let (start, line) = index.start(row_index - 1 + header)?;
+            input.seek(SeekFrom::Start(copied))?;
+            writer.copy_raw((&mut input).take(start - copied))?;
+            if let Edit::Modify { col, values } = edit {
+                records.seek(start, line)?;
+                let mut fields = records
+                    .next()
+                    .transpose()?
+                    .map(|r| r.fields)
+                    .unwrap_or_default();
+                apply_modification(&mut fields, *col, values.clone())?;
+                writer.write_record(&fields)?;
+            }
+            copied = index.start(row_index + header)?.0;
+        }
+        input.seek(SeekFrom::Start(copied))?;
+        writer.copy_raw(input)?;
+        writer.flush()
+    }
+
+    // Writes through a temporary file next to the target, so the input may
+    // also be the output and a failed write leaves the target untouched.
+    pub fn to_file(&self, file_path: PathBuf, quote_style: Option<QuoteStyle>) -> Result<()> {
+        writer::replace_file(&file_path, |temp_path| {
+            self.write_to(BufWriter::new(File::create(temp_path)?), quote_style)
+        })
+    }
+}
+
+impl CSVManipulation for CSVStream {
+    // One pass over the file to measure the columns, then one to print each
+    // panel.
+    fn display(&self, options: &display::Options) -> Result<()> {
+        let mut layout = Layout::new(self.header.as_ref(), self.cols, options);
+        for record in self.rows()? {
+            layout.measure(&record?.fields);
+        }
+        layout.finish().print(self.header.as_ref(), || {
+            Ok(self
+                .rows()?
+                .enumerate()
+                .map(|(index, record)| record.map(|record| (index + 1, record.fields))))
+        })
+    }
+
+    // Reading stops as soon as the last requested row has been seen, and
+    // starts right at the first one with an index.
+    fn paginate(&self, start: usize, end: usize, options: &display::Options) -> Result<()> {
+        let buffer_end = end + 1;
+        let (rows, skip) = self.rows_from(start - 1)?;
+        let page = rows
+            .skip(skip)
+            .take(buffer_end - start)
+            .map(|record| record.map(|r| r.fields))
+            .collect::<Result<Vec<_>>>()?;
+        let mut layout = Layout::new(self.header.as_ref(), self.cols, options);
+        for row in &page {
+            layout.measure(row);
+        }
+        layout
+            .number_from(start)
+            .finish()
+            .print(self.header.as_ref(), || {
+                Ok(page
+                    .iter()
+                    .enumerate()
+                    .map(|(index, row)| Ok((start + index, row))))
+            })
+    }
+
+    // Only the rows on screen are read, see `Cursor`.
+    fn view(&self, start: usize, options: &display::Options) -> Result<()> {
+        let header = self.header.as_ref();
+        pager::run(&mut Cursor::new(self), header, self.cols, start, options)
+    }
+
+    fn modify(
+        &mut self,
+        row_index: usize,
+        col_index: Option<&str>,
+        values: Vec<String>,
+    ) -> Result<()> {
+        let col = col_index
+            .map(|column| resolve_column(self.header.as_deref(), self.cols, column))
+            .transpose()?;
+        if col.is_some() && values.len() != 1 {
+            bail!(Error::ValueLengthMismatch {
+                found: values.len()
+            });
+        }
+        // Row bounds are checked once the rows are read during the write
+        self.edits.insert(row_index, Edit::Modify { col, values });
+        Ok(())
+    }
+
+    fn delete(&mut self, row_index: usize) -> Result<()> {
+        self.edits.insert(row_index, Edit::Delete);
+        Ok(())
+    }
+
+    // The position is checked once the rows are read during the write
+    fn insert(&mut self, at: usize, values: Vec<String>, fit: FitPolicy) -> Result<()> {
+        let row = fit_fields(values, self.cols, fit)?;
+        if self.cols == 0 {
+            self.cols = row.len();
+        }
+        self.inserts.entry(at).or_default().push(row);
+        Ok(())
+    }
+
+    fn append(&mut self, values: Vec<String>, fit: FitPolicy) -> Result<()> {
+        let row = fit_fields(values, self.cols, fit)?;
+        if self.cols == 0 {
+            self.cols = row.len();
+        }
+        self.appends.push(row);
+        Ok(())
+    }
+
+    fn header(&self) -> Option<&[String]> {
+        self.header.as_deref()
+    }
+
+    fn cols(&self) -> usize {
+        self.cols
+    }
+
+    // Applied to the header right away and to the rows while writing
+    fn reshape(&mut self, op: ColumnOp) -> Result<()> {
+        if let Some(header) = &mut self.header {
+            op.apply(header, true)?;
+            self.header_changed = true;
+        }
+        self.cols = op.cols_after(self.cols);
+        if op.changes_rows() {
+            self.column_ops.push(op);
+        }
+        Ok(())
+    }
+
+    // Rows are sorted while writing, spilling to temporary files past the
+    // sort's memory limit
+    fn sort(&mut self, sort: Sort) -> Result<()> {
+        self.sort = Some(sort);
+        Ok(())
+    }
+
+    // Rows are filtered as they are read, by every later pass
+    fn filter(&mut self, filter: Filter) -> Result<()> {
+        self.filter = Some(filter);
+        Ok(())
+    }
+
+    fn scan(&self, f: &mut dyn FnMut(usize, &[String]) -> Result<bool>) -> Result<()> {
+        for (index, record) in self.rows()?.enumerate() {
+            if !f(index + 1, &record?.fields)? {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn count(&self) -> Result<usize> {
+        if let Some(count) = self.indexed_count() {
+            return Ok(count);
+        }
+        let mut count = 0;
+        for record in self.rows()? {
+            record?;
+            count += 1;
+        }
+        Ok(count)
+    }
+}
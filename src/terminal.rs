@@ -0,0 +1,273 @@
+// Facts about the terminal the output goes to, and full-screen mode for the
+// pager. Raw mode is switched with `stty` so no terminal library is needed.
+
+use std::{
+    fs::File,
+    io::{self, IsTerminal, Read, Write},
+    process::Command,
+};
+
+#[repr(C)]
+#[derive(Default)]
+struct WindowSize {
+    rows: u16,
+    cols: u16,
+    _x_pixels: u16,
+    _y_pixels: u16,
+}
+
+#[cfg(target_os = "linux")]
+const TIOCGWINSZ: std::os::raw::c_ulong = 0x5413;
+#[cfg(target_os = "macos")]
+const TIOCGWINSZ: std::os::raw::c_ulong = 0x40087468;
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+extern "C" {
+    fn ioctl(fd: std::os::raw::c_int, request: std::os::raw::c_ulong, ...) -> std::os::raw::c_int;
+}
+
+// Rows and columns of the terminal on stdout, asked from the kernel.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub fn size() -> Option<(usize, usize)> {
+    let mut size = WindowSize::default();
+    // SAFETY: TIOCGWINSZ fills in a `struct winsize`, which `WindowSize` mirrors
+    let status = unsafe { ioctl(1, TIOCGWINSZ, &mut size as *mut WindowSize) };
+    (status == 0 && size.cols > 0).then_some((size.rows as usize, size.cols as usize))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn size() -> Option<(usize, usize)> {
+    None
+}
+
+// Width to fit tables into, None when stdout is not a terminal so piped and
+// redirected output is never cut up.
+pub fn width() -> Option<usize> {
+    if !std::io::stdout().is_terminal() {
+        return None;
+    }
+    let from_env = || std::env::var("COLUMNS").ok()?.parse().ok();
+    Some(size().map(|(_, cols)| cols).or_else(from_env).unwrap_or(80))
+}
+
+// A key press read from the terminal.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Key {
+    Char(char),
+    Up,
+    Down,
+    Left,
+    Right,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    Enter,
+    Backspace,
+    Escape,
+    // Ctrl-C, which raw mode no longer turns into a signal
+    Interrupt,
+}
+
+// Full-screen mode: the terminal is switched to raw input and the alternate
+// screen until this is dropped, errors and panics included.
+pub struct Screen {
+    tty: File,
+    // Settings to restore, as printed by `stty -g`
+    saved: String,
+    // Bytes read but not yet taken as keys
+    pending: Vec<u8>,
+}
+
+fn stty(tty: &File, args: &[&str]) -> io::Result<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(tty.try_clone()?)
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+impl Screen {
+    pub fn enter() -> io::Result<Self> {
+        let tty = File::open("/dev/tty")?;
+        let saved = stty(&tty, &["-g"])?;
+        stty(&tty, &["raw", "-echo"])?;
+        let screen = Self {
+            tty,
+            saved,
+            pending: Vec::new(),
+        };
+        // Alternate screen, hidden cursor, lines cut at the right edge
+        screen.draw("\x1b[?1049h\x1b[?25l\x1b[?7l")?;
+        Ok(screen)
+    }
+
+    // Writes `text` to the terminal at once.
+    pub fn draw(&self, text: &str) -> io::Result<()> {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(text.as_bytes())?;
+        stdout.flush()
+    }
+
+    // Waits for the next key press. A read may bring several keys, typed
+    // fast or pasted, which are kept to come back one at a time.
+    pub fn read_key(&mut self) -> io::Result<Key> {
+        loop {
+            match decode(&self.pending) {
+                Some((key, len)) => {
+                    self.pending.drain(..len);
+                    if let Some(key) = key {
+                        return Ok(key);
+                    }
+                }
+                None => {
+                    let mut buffer = [0u8; 64];
+                    let read = self.tty.read(&mut buffer)?;
+                    // The terminal hung up, no key will ever come
+                    if read == 0 {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+                    self.pending.extend_from_slice(&buffer[..read]);
+                }
+            }
+        }
+    }
+}
+
+// The first key of `bytes` and the number of bytes it takes, no key for
+// bytes that are not valid input. None when the bytes end within a key.
+fn decode(bytes: &[u8]) -> Option<(Option<Key>, usize)> {
+    let key = match *bytes.first()? {
+        0x1b => return escape(bytes),
+        b'\r' | b'\n' => Key::Enter,
+        0x7f | 0x08 => Key::Backspace,
+        0x03 => Key::Interrupt,
+        _ => {
+            let text = match std::str::from_utf8(&bytes[..bytes.len().min(4)]) {
+                Ok(text) => text,
+                Err(error) if error.valid_up_to() > 0 => {
+                    std::str::from_utf8(&bytes[..error.valid_up_to()]).ok()?
+                }
+                // A character read in part
+                Err(error) if error.error_len().is_none() => return None,
+                Err(_) => return Some((None, 1)),
+            };
+            let c = text.chars().next()?;
+            return Some((Some(Key::Char(c)), c.len_utf8()));
+        }
+    };
+    Some((Some(key), 1))
+}
+
+// The key of the escape sequence `bytes` start with. Escape sequences for
+// keys not known here come back as a lone `Escape`, and so does an escape
+// not followed by a sequence. One at the end of a read is the Escape key
+// itself, as terminals write a whole sequence at once.
+fn escape(bytes: &[u8]) -> Option<(Option<Key>, usize)> {
+    let len = match bytes.get(1) {
+        Some(b'O') => 3,
+        // Parameter and intermediate bytes up to a final one
+        Some(b'[') => match bytes[2..].iter().position(|b| !(0x20..0x40).contains(b))? {
+            end if (0x40..0x7f).contains(&bytes[2 + end]) => 2 + end + 1,
+            _ => 2,
+        },
+        _ => 1,
+    };
+    let key = match bytes.get(..len)? {
+        b"\x1b[A" | b"\x1bOA" => Key::Up,
+        b"\x1b[B" | b"\x1bOB" => Key::Down,
+        b"\x1b[C" | b"\x1bOC" => Key::Right,
+        b"\x1b[D" | b"\x1bOD" => Key::Left,
+        b"\x1b[5~" => Key::PageUp,
+        b"\x1b[6~" => Key::PageDown,
+        b"\x1b[H" | b"\x1bOH" | b"\x1b[1~" | b"\x1b[7~" => Key::Home,
+        b"\x1b[F" | b"\x1bOF" | b"\x1b[4~" | b"\x1b[8~" => Key::End,
+        _ => Key::Escape,
+    };
+    Some((Some(key), len))
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        // Nothing sensible is left to do if restoring the terminal fails
+        let _ = self.draw("\x1b[?7h\x1b[?25h\x1b[?1049l");
+        let _ = stty(&self.tty, &[&self.saved]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every key of `bytes`, None where they end within one.
+    fn keys(mut bytes: &[u8]) -> Vec<Option<Key>> {
+        let mut keys = Vec::new();
+        while !bytes.is_empty() {
+            match decode(bytes) {
+                Some((key, len)) => {
+                    keys.extend(key.map(Some));
+                    bytes = &bytes[len..];
+                }
+                None => {
+                    keys.push(None);
+                    break;
+                }
+            }
+        }
+        keys
+    }
+
+    #[test]
+    fn reads_every_key_of_a_read() {
+        let keys = keys("ab\x1b[A\x1bOBé\r\x1b[5~€\x7f\x03".as_bytes());
+        let expected = [
+            Key::Char('a'),
+            Key::Char('b'),
+            Key::Up,
+            Key::Down,
+            Key::Char('é'),
+            Key::Enter,
+            Key::PageUp,
+            Key::Char('€'),
+            Key::Backspace,
+            Key::Interrupt,
+        ];
+        assert_eq!(keys, expected.map(Some));
+    }
+
+    #[test]
+    fn waits_for_the_rest_of_a_key() {
+        for bytes in [
+            &b"\x1b["[..],
+            b"\x1bO",
+            b"\x1b[5",
+            "é".as_bytes().get(..1).unwrap(),
+        ] {
+            assert_eq!(decode(bytes), None, "{:?}", bytes);
+        }
+        assert_eq!(keys(b"x\x1b[1"), [Some(Key::Char('x')), None]);
+    }
+
+    #[test]
+    fn unknown_input() {
+        // Ctrl-Right, an Alt combination and a lone escape
+        assert_eq!(
+            keys(b"\x1b[1;5Cq\x1bx\x1b"),
+            [
+                Key::Escape,
+                Key::Char('q'),
+                Key::Escape,
+                Key::Char('x'),
+                Key::Escape
+            ]
+            .map(Some)
+        );
+        // Bytes that are no UTF-8 are skipped
+        assert_eq!(keys(b"\xff\x80a"), [Some(Key::Char('a'))]);
+    }
+}
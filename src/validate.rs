@@ -0,0 +1,238 @@
+// Checks records against a schema, collecting every violation rather than
+// stopping at the first.
+
+use crate::schema::{is_null, ColumnSchema, Schema};
+use std::{cmp::Ordering, collections::HashMap, fmt};
+
+#[derive(Debug)]
+pub struct Violation {
+    // 1-based data row, None for problems with the file as a whole
+    pub row: Option<usize>,
+    pub column: Option<String>,
+    pub reason: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.row, &self.column) {
+            (Some(row), Some(column)) => {
+                write!(f, "row {}, column {:?}: {}", row, column, self.reason)
+            }
+            (Some(row), None) => write!(f, "row {}: {}", row, self.reason),
+            (None, Some(column)) => write!(f, "column {:?}: {}", column, self.reason),
+            (None, None) => write!(f, "{}", self.reason),
+        }
+    }
+}
+
+pub struct Validator<'a> {
+    // Schema columns found in the file, with their 0-based index
+    columns: Vec<(&'a ColumnSchema, usize)>,
+    // Values of unique columns seen so far and the row they were first in
+    seen: Vec<HashMap<String, usize>>,
+    // Field count every row should have
+    expected: usize,
+    pub violations: Vec<Violation>,
+}
+
+impl<'a> Validator<'a> {
+    pub fn new(schema: &'a Schema, header: Option<&[String]>, cols: usize) -> Self {
+        let mut violations = Vec::new();
+        let mut columns = Vec::new();
+        for (column, index) in schema.locate(header, cols) {
+            match index {
+                Some(index) => columns.push((column, index)),
+                None if column.required => violations.push(Violation {
+                    row: None,
+                    column: Some(column.name.clone()),
+                    reason: "required column is missing".to_string(),
+                }),
+                None => {}
+            }
+        }
+        Self {
+            seen: vec![HashMap::new(); columns.len()],
+            columns,
+            expected: cols,
+            violations,
+        }
+    }
+
+    pub fn check(&mut self, row_index: usize, row: &[String]) {
+        if row.len() != self.expected {
+            self.violations.push(Violation {
+                row: Some(row_index),
+                column: None,
+                reason: format!("has {} fields, expected {}", row.len(), self.expected),
+            });
+        }
+        for ((column, index), seen) in self.columns.iter().zip(&mut self.seen) {
+            // Cells missing from a short row are covered by the field count
+            let Some(value) = row.get(*index) else {
+                continue;
+            };
+            let mut violation = |reason: String| {
+                self.violations.push(Violation {
+                    row: Some(row_index),
+                    column: Some(column.name.clone()),
+                    reason,
+                })
+            };
+            if is_null(value) {
+                if !column.nullable {
+                    violation("is empty but the column is not nullable".to_string());
+                }
+                continue;
+            }
+            if !column.ty.accepts(value) {
+                violation(format!("{:?} is not a valid {}", value, column.ty.name()));
+            } else {
+                let bounds = [
+                    (&column.minimum, Ordering::Less, "below the minimum"),
+                    (&column.maximum, Ordering::Greater, "above the maximum"),
+                ];
+                for (limit, outside, what) in bounds {
+                    if let Some(limit) = limit {
+                        if limit.compare(value) == Some(outside) {
+                            violation(format!("{:?} is {} {}", value, what, limit));
+                        }
+                    }
+                }
+            }
+            if let Some(allowed) = &column.allowed {
+                if !allowed.contains(value) {
+                    violation(format!("{:?} is not one of {}", value, allowed.join(", ")));
+                }
+            }
+            if let Some((pattern, regex)) = &column.pattern {
+                if !regex.is_match(value) {
+                    violation(format!("{:?} does not match /{}/", value, pattern));
+                }
+            }
+            if column.unique {
+                match seen.get(value) {
+                    Some(first) => violation(format!("{:?} duplicates row {}", value, first)),
+                    None => {
+                        seen.insert(value.clone(), row_index);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json;
+
+    // Violations of `rows` against a schema with the given columns, as
+    // `row: column: reason` lines.
+    fn violations(columns: &str, header: &[&str], rows: &[&[&str]]) -> Vec<String> {
+        let source = format!("{{\"columns\":[{}]}}", columns);
+        let schema = Schema::from_json(&json::parse(&source).unwrap()).unwrap();
+        let header: Vec<String> = header.iter().map(|name| name.to_string()).collect();
+        let mut validator = Validator::new(&schema, Some(&header), header.len());
+        for (index, row) in rows.iter().enumerate() {
+            let row: Vec<String> = row.iter().map(|cell| cell.to_string()).collect();
+            validator.check(index + 1, &row);
+        }
+        validator
+            .violations
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn types() {
+        let columns = r#"{"name":"n","type":"integer"}"#;
+        assert_eq!(
+            violations(columns, &["n"], &[&["1"], &["1.5"], &[" -2 "]]),
+            [r#"row 2, column "n": "1.5" is not a valid integer"#]
+        );
+    }
+
+    #[test]
+    fn nullable() {
+        let columns = r#"{"name":"a","nullable":false},{"name":"b","nullable":true}"#;
+        assert_eq!(
+            violations(columns, &["a", "b"], &[&["x", ""], &["NA", "null"]]),
+            [r#"row 2, column "a": is empty but the column is not nullable"#]
+        );
+    }
+
+    #[test]
+    fn pattern() {
+        let columns = r#"{"name":"code","pattern":"^[A-Z]{2}$"}"#;
+        assert_eq!(
+            violations(columns, &["code"], &[&["DE"], &["de"], &[""]]),
+            [r#"row 2, column "code": "de" does not match /^[A-Z]{2}$/"#]
+        );
+    }
+
+    #[test]
+    fn allowed_values() {
+        let columns = r#"{"name":"size","enum":["S","M","L"]}"#;
+        assert_eq!(
+            violations(columns, &["size"], &[&["M"], &["XL"]]),
+            [r#"row 2, column "size": "XL" is not one of S, M, L"#]
+        );
+    }
+
+    #[test]
+    fn minimum_and_maximum() {
+        let columns = r#"{"name":"n","type":"float","minimum":0,"maximum":10},
+            {"name":"day","type":"date","minimum":"2024-01-01"}"#;
+        assert_eq!(
+            violations(
+                columns,
+                &["n", "day"],
+                &[
+                    &["0", "2024-01-01"],
+                    &["-0.5", "2023-12-31"],
+                    &["10.5", "2024-06-01"]
+                ]
+            ),
+            [
+                r#"row 2, column "n": "-0.5" is below the minimum 0"#,
+                r#"row 2, column "day": "2023-12-31" is below the minimum 2024-01-01"#,
+                r#"row 3, column "n": "10.5" is above the maximum 10"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn unique() {
+        let columns = r#"{"name":"id","unique":true}"#;
+        assert_eq!(
+            violations(columns, &["id"], &[&["a"], &["b"], &["a"], &[""], &[""]]),
+            [r#"row 3, column "id": "a" duplicates row 1"#]
+        );
+    }
+
+    #[test]
+    fn missing_columns() {
+        let columns = r#"{"name":"a"},{"name":"b"},{"name":"c","required":false}"#;
+        assert_eq!(
+            violations(columns, &["a"], &[&["x"]]),
+            [r#"column "b": required column is missing"#]
+        );
+    }
+
+    #[test]
+    fn ragged_rows() {
+        let columns = r#"{"name":"a"},{"name":"b","nullable":false}"#;
+        assert_eq!(
+            violations(
+                columns,
+                &["a", "b"],
+                &[&["x"], &["x", "y", "z"], &["x", "y"]]
+            ),
+            [
+                "row 1: has 1 fields, expected 2",
+                "row 2: has 3 fields, expected 2"
+            ]
+        );
+    }
+}
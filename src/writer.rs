@@ -0,0 +1,265 @@
+// Quote-aware record writer, the counterpart of `parser::Reader`.
+
+use crate::{dialect::Dialect, Error};
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use std::{
+    fs,
+    io::{Read, Write},
+    path::Path,
+};
+
+// Writes `path` through `write`, which is given a sibling temporary path and
+// only replaces `path` once it succeeds. The file written may be the one
+// read, which a failure partway would otherwise leave truncated.
+pub fn replace_file(path: &Path, write: impl FnOnce(&Path) -> Result<()>) -> Result<()> {
+    let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+
+    if let Err(e) = write(&temp_path) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(e);
+    }
+    fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+// When a field gets enclosed in quotes on output.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum QuoteStyle {
+    // Only fields containing a delimiter, quote, escape or terminator
+    Minimal,
+    // Every field
+    Always,
+    // Every field that does not parse as a number
+    NonNumeric,
+    // No field; special characters are escaped if the dialect has an escape
+    // character, otherwise values that need quotes are rejected
+    Never,
+}
+
+pub struct Writer<W> {
+    inner: W,
+    style: QuoteStyle,
+    dialect: Dialect,
+    terminator: String,
+    // Set after raw text that lacked a terminator (the last record of a file
+    // without a final newline), so a following record still starts on its own line
+    unterminated: bool,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(inner: W, style: QuoteStyle, dialect: Dialect) -> Self {
+        Self {
+            inner,
+            style,
+            dialect,
+            terminator: dialect.terminator.sequence(),
+            unterminated: false,
+        }
+    }
+
+    fn needs_quotes(&self, field: &str) -> bool {
+        let Dialect {
+            delimiter,
+            quote,
+            escape,
+            ..
+        } = self.dialect;
+        field
+            .bytes()
+            .any(|b| b == delimiter || b == quote || Some(b) == escape || b == b'\r' || b == b'\n')
+            || field.contains(&self.terminator)
+    }
+
+    fn should_quote(&self, field: &str) -> Result<bool> {
+        let needs_quotes = self.needs_quotes(field);
+        Ok(match self.style {
+            QuoteStyle::Minimal => needs_quotes,
+            QuoteStyle::Always => true,
+            QuoteStyle::NonNumeric => needs_quotes || field.parse::<f64>().is_err(),
+            QuoteStyle::Never => {
+                if needs_quotes && self.dialect.escape.is_none() {
+                    bail!(Error::UnquotableField(field.to_string()));
+                }
+                false
+            }
+        })
+    }
+
+    // Escapes the characters that would otherwise end the field: only the
+    // quote inside quotes, every special character outside them.
+    fn escape(&self, field: &str, quoted: bool) -> String {
+        let Dialect { quote, escape, .. } = self.dialect;
+        let quote = quote as char;
+        let Some(escape) = escape.map(char::from) else {
+            return field.replace(quote, &format!("{}{}", quote, quote));
+        };
+        let mut escaped = String::with_capacity(field.len());
+        for c in field.chars() {
+            let special = c == quote
+                || c == escape
+                || (!quoted
+                    && (c == self.dialect.delimiter as char
+                        || c == '\r'
+                        || c == '\n'
+                        || self.terminator.starts_with(c)));
+            if special {
+                escaped.push(escape);
+            }
+            escaped.push(c);
+        }
+        escaped
+    }
+
+    fn finish_previous(&mut self) -> Result<()> {
+        if self.unterminated {
+            write!(self.inner, "{}", self.terminator)?;
+            self.unterminated = false;
+        }
+        Ok(())
+    }
+
+    pub fn write_record(&mut self, fields: &[String]) -> Result<()> {
+        self.finish_previous()?;
+        let delimiter = self.dialect.delimiter as char;
+        let quote = self.dialect.quote as char;
+        for (index, field) in fields.iter().enumerate() {
+            if index > 0 {
+                write!(self.inner, "{}", delimiter)?;
+            }
+            if self.should_quote(field)? {
+                let escaped = self.escape(field, true);
+                write!(self.inner, "{}{}{}", quote, escaped, quote)?;
+            } else if self.needs_quotes(field) {
+                let escaped = self.escape(field, false);
+                write!(self.inner, "{}", escaped)?;
+            } else {
+                write!(self.inner, "{}", field)?;
+            }
+        }
+        write!(self.inner, "{}", self.terminator)?;
+        Ok(())
+    }
+
+    // Writes source text exactly as it was read.
+    pub fn write_raw(&mut self, raw: &str) -> Result<()> {
+        if raw.is_empty() {
+            return Ok(());
+        }
+        self.finish_previous()?;
+        write!(self.inner, "{}", raw)?;
+        self.unterminated = !raw.ends_with('\n') && !raw.ends_with(&self.terminator);
+        Ok(())
+    }
+
+    // Copies source bytes exactly as they are, like `write_raw` without
+    // reading them into a string first.
+    pub fn copy_raw(&mut self, mut raw: impl Read) -> Result<()> {
+        let mut buffer = [0; 64 * 1024];
+        let mut last = None;
+        loop {
+            let read = raw.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            if last.is_none() {
+                self.finish_previous()?;
+            }
+            self.inner.write_all(&buffer[..read])?;
+            last = Some(buffer[read - 1]);
+        }
+        if let Some(last) = last {
+            self.unterminated = last != b'\n' && !self.terminator.ends_with(last as char);
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.inner.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(style: QuoteStyle, dialect: Dialect, rows: &[&[&str]]) -> Result<String> {
+        let mut out = Vec::new();
+        let mut writer = Writer::new(&mut out, style, dialect);
+        for row in rows {
+            let row: Vec<String> = row.iter().map(|field| field.to_string()).collect();
+            writer.write_record(&row)?;
+        }
+        writer.flush()?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    const ROWS: &[&[&str]] = &[&["name", "note", "n"], &["a,b", "say \"hi\"", "1.5"]];
+
+    #[test]
+    fn minimal_quotes_only_what_needs_it() {
+        assert_eq!(
+            write(QuoteStyle::Minimal, Dialect::default(), ROWS).unwrap(),
+            "name,note,n\n\"a,b\",\"say \"\"hi\"\"\",1.5\n"
+        );
+        let rows: &[&[&str]] = &[&["one\ntwo", "x\ry"]];
+        assert_eq!(
+            write(QuoteStyle::Minimal, Dialect::default(), rows).unwrap(),
+            "\"one\ntwo\",\"x\ry\"\n"
+        );
+    }
+
+    #[test]
+    fn always_quotes_every_field() {
+        assert_eq!(
+            write(QuoteStyle::Always, Dialect::default(), ROWS).unwrap(),
+            "\"name\",\"note\",\"n\"\n\"a,b\",\"say \"\"hi\"\"\",\"1.5\"\n"
+        );
+    }
+
+    #[test]
+    fn non_numeric_quotes_all_but_numbers() {
+        assert_eq!(
+            write(QuoteStyle::NonNumeric, Dialect::default(), ROWS).unwrap(),
+            "\"name\",\"note\",\"n\"\n\"a,b\",\"say \"\"hi\"\"\",1.5\n"
+        );
+    }
+
+    #[test]
+    fn never_escapes_or_refuses() {
+        let rows: &[&[&str]] = &[&["plain", "1"]];
+        assert_eq!(
+            write(QuoteStyle::Never, Dialect::default(), rows).unwrap(),
+            "plain,1\n"
+        );
+        let error = write(QuoteStyle::Never, Dialect::default(), ROWS).unwrap_err();
+        assert!(matches!(
+            error.downcast::<Error>(),
+            Ok(Error::UnquotableField(field)) if field == "a,b"
+        ));
+
+        let dialect = Dialect {
+            escape: Some(b'\\'),
+            ..Dialect::default()
+        };
+        assert_eq!(
+            write(QuoteStyle::Never, dialect, ROWS).unwrap(),
+            "name,note,n\na\\,b,say \\\"hi\\\",1.5\n"
+        );
+    }
+
+    #[test]
+    fn raw_text_without_a_terminator_is_finished() {
+        let mut out = Vec::new();
+        let mut writer = Writer::new(&mut out, QuoteStyle::Minimal, Dialect::default());
+        writer.write_raw("a,b").unwrap();
+        writer
+            .write_record(&["c".to_string(), "d".to_string()])
+            .unwrap();
+        writer.flush().unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "a,b\nc,d\n");
+    }
+}
@@ -0,0 +1,293 @@
+// Just enough of the zip format for .xlsx files: reading entries stored or
+// compressed with deflate, and writing stored (uncompressed) entries.
+//
+// Zip64, encryption and multi-disk archives are not supported, which rules out
+// entries and archives over 4 GiB.
+
+use crate::{
+    compress::{crc32, inflate},
+    Error,
+};
+use anyhow::{bail, Result};
+use std::{fs, io::Write, path::Path};
+
+const LOCAL_HEADER: u32 = 0x04034b50;
+const CENTRAL_HEADER: u32 = 0x02014b50;
+const END_OF_DIRECTORY: u32 = 0x06054b50;
+
+fn invalid(reason: impl Into<String>) -> anyhow::Error {
+    Error::InvalidXlsx(reason.into()).into()
+}
+
+fn u16_at(data: &[u8], at: usize) -> Result<u16> {
+    match data.get(at..at + 2) {
+        Some(bytes) => Ok(u16::from_le_bytes([bytes[0], bytes[1]])),
+        None => Err(invalid("the zip archive is truncated")),
+    }
+}
+
+fn u32_at(data: &[u8], at: usize) -> Result<u32> {
+    match data.get(at..at + 4) {
+        Some(bytes) => Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+        None => Err(invalid("the zip archive is truncated")),
+    }
+}
+
+struct Entry {
+    name: String,
+    method: u16,
+    crc: u32,
+    compressed: usize,
+    size: usize,
+    // Offset of the local header
+    offset: usize,
+}
+
+// A zip archive read into memory.
+pub struct Archive {
+    data: Vec<u8>,
+    entries: Vec<Entry>,
+}
+
+impl Archive {
+    pub fn open(path: &Path) -> Result<Self> {
+        let data = fs::read(path)?;
+        // The end of central directory record is last, before a comment of up
+        // to 64 KiB
+        let earliest = data.len().saturating_sub(22 + 0xffff);
+        let end = (earliest..data.len().saturating_sub(21))
+            .rev()
+            .find(|&at| u32_at(&data, at).is_ok_and(|sig| sig == END_OF_DIRECTORY))
+            .ok_or_else(|| invalid("not a zip archive"))?;
+        let count = u16_at(&data, end + 10)? as usize;
+        let mut at = u32_at(&data, end + 16)? as usize;
+        if at == 0xffff_ffff {
+            bail!(invalid("zip64 archives are not supported"));
+        }
+
+        let mut entries = Vec::with_capacity(count);
+        for _ in 0..count {
+            if u32_at(&data, at)? != CENTRAL_HEADER {
+                bail!(invalid("the zip central directory is corrupt"));
+            }
+            let name_len = u16_at(&data, at + 28)? as usize;
+            let extra_len = u16_at(&data, at + 30)? as usize;
+            let comment_len = u16_at(&data, at + 32)? as usize;
+            let name = data
+                .get(at + 46..at + 46 + name_len)
+                .ok_or_else(|| invalid("the zip archive is truncated"))?;
+            entries.push(Entry {
+                name: String::from_utf8_lossy(name).into_owned(),
+                method: u16_at(&data, at + 10)?,
+                crc: u32_at(&data, at + 16)?,
+                compressed: u32_at(&data, at + 20)? as usize,
+                size: u32_at(&data, at + 24)? as usize,
+                offset: u32_at(&data, at + 42)? as usize,
+            });
+            at += 46 + name_len + extra_len + comment_len;
+        }
+        Ok(Self { data, entries })
+    }
+
+    // Contents of the entry `name`, None when there is no such entry. Names
+    // are matched ignoring case when there is no exact match, as some writers
+    // differ in case from the part names they list.
+    pub fn read(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        let entry = self
+            .entries
+            .iter()
+            .find(|entry| entry.name == name)
+            .or_else(|| {
+                let name = name.to_ascii_lowercase();
+                self.entries
+                    .iter()
+                    .find(|entry| entry.name.to_ascii_lowercase() == name)
+            });
+        let Some(entry) = entry else {
+            return Ok(None);
+        };
+        if u32_at(&self.data, entry.offset)? != LOCAL_HEADER {
+            bail!(invalid(format!("the zip entry {} is corrupt", entry.name)));
+        }
+        let name_len = u16_at(&self.data, entry.offset + 26)? as usize;
+        let extra_len = u16_at(&self.data, entry.offset + 28)? as usize;
+        let start = entry.offset + 30 + name_len + extra_len;
+        let stored = self
+            .data
+            .get(start..start + entry.compressed)
+            .ok_or_else(|| invalid("the zip archive is truncated"))?;
+        let contents = match entry.method {
+            0 => stored.to_vec(),
+            8 => inflate(stored, entry.size)
+                .ok_or_else(|| invalid(format!("the zip entry {} is corrupt", entry.name)))?,
+            method => bail!(invalid(format!(
+                "the zip entry {} uses unsupported compression method {}",
+                entry.name, method
+            ))),
+        };
+        if crc32(&contents) != entry.crc {
+            bail!(invalid(format!("the zip entry {} is corrupt", entry.name)));
+        }
+        Ok(Some(contents))
+    }
+}
+
+// Writes a zip archive whose entries are stored without compression.
+pub struct ArchiveWriter<W: Write> {
+    out: W,
+    // Name, CRC, size and local header offset of every entry written
+    entries: Vec<(String, u32, u32, u32)>,
+    offset: u64,
+}
+
+impl<W: Write> ArchiveWriter<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            entries: Vec::new(),
+            offset: 0,
+        }
+    }
+
+    pub fn add(&mut self, name: &str, contents: &[u8]) -> Result<()> {
+        let (Ok(size), Ok(offset)) = (u32::try_from(contents.len()), u32::try_from(self.offset))
+        else {
+            bail!(invalid("the spreadsheet is too large, over 4 GiB"));
+        };
+        let crc = crc32(contents);
+        let mut header = Vec::with_capacity(30 + name.len());
+        header.extend(LOCAL_HEADER.to_le_bytes());
+        // Version 2.0, no flags, stored, 1980-01-01 00:00
+        header.extend([20, 0, 0, 0, 0, 0, 0, 0, 0x21, 0]);
+        header.extend(crc.to_le_bytes());
+        header.extend(size.to_le_bytes());
+        header.extend(size.to_le_bytes());
+        header.extend((name.len() as u16).to_le_bytes());
+        header.extend(0u16.to_le_bytes());
+        header.extend(name.as_bytes());
+        self.out.write_all(&header)?;
+        self.out.write_all(contents)?;
+        self.offset += (header.len() + contents.len()) as u64;
+        self.entries.push((name.to_string(), crc, size, offset));
+        Ok(())
+    }
+
+    // Writes the central directory, returning the output.
+    pub fn finish(mut self) -> Result<W> {
+        let start = self.offset;
+        let mut directory = Vec::new();
+        for (name, crc, size, offset) in &self.entries {
+            directory.extend(CENTRAL_HEADER.to_le_bytes());
+            directory.extend([20, 0, 20, 0, 0, 0, 0, 0, 0, 0, 0x21, 0]);
+            directory.extend(crc.to_le_bytes());
+            directory.extend(size.to_le_bytes());
+            directory.extend(size.to_le_bytes());
+            directory.extend((name.len() as u16).to_le_bytes());
+            // Extra field, comment, disk, internal and external attributes
+            directory.extend([0; 12]);
+            directory.extend(offset.to_le_bytes());
+            directory.extend(name.as_bytes());
+        }
+        let Ok(start) = u32::try_from(start) else {
+            bail!(invalid("the spreadsheet is too large, over 4 GiB"));
+        };
+        let count = self.entries.len() as u16;
+        directory.extend(END_OF_DIRECTORY.to_le_bytes());
+        directory.extend([0; 4]);
+        directory.extend(count.to_le_bytes());
+        directory.extend(count.to_le_bytes());
+        directory.extend((directory.len() as u32 - 12).to_le_bytes());
+        directory.extend(start.to_le_bytes());
+        directory.extend([0; 2]);
+        self.out.write_all(&directory)?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sort::TempFile;
+
+    const SAMPLE: &[u8] = include_bytes!("../tests/fixtures/sample.csv");
+
+    fn scratch(name: &str) -> TempFile {
+        TempFile(std::env::temp_dir().join(format!(
+            "csv_handler-{}-zip-{}",
+            std::process::id(),
+            name
+        )))
+    }
+
+    fn open(name: &str, data: &[u8]) -> Result<Archive> {
+        let file = scratch(name);
+        fs::write(&file.0, data)?;
+        Archive::open(&file.0)
+    }
+
+    // Written by Python's zipfile: stored, deflated fast and deflated best,
+    // an empty entry and an archive comment.
+    #[test]
+    fn reads_fixture() {
+        let archive = open("fixture", include_bytes!("../tests/fixtures/sample.zip")).unwrap();
+        for name in ["stored.csv", "fast.csv", "best.csv"] {
+            assert_eq!(
+                archive.read(name).unwrap().as_deref(),
+                Some(SAMPLE),
+                "{}",
+                name
+            );
+        }
+        assert_eq!(archive.read("empty.txt").unwrap(), Some(Vec::new()));
+        assert_eq!(archive.read("missing.csv").unwrap(), None);
+    }
+
+    #[test]
+    fn round_trip() {
+        let entries: [(&str, &[u8]); 3] = [
+            ("[Content_Types].xml", b"<Types/>"),
+            ("xl/empty.xml", b""),
+            ("xl/sample.csv", SAMPLE),
+        ];
+        let mut writer = ArchiveWriter::new(Vec::new());
+        for (name, contents) in entries {
+            writer.add(name, contents).unwrap();
+        }
+        let archive = open("round_trip", &writer.finish().unwrap()).unwrap();
+        for (name, contents) in entries {
+            assert_eq!(
+                archive.read(name).unwrap().as_deref(),
+                Some(contents),
+                "{}",
+                name
+            );
+        }
+    }
+
+    // Damaged archives are errors, never panics or wrong contents.
+    #[test]
+    fn rejects_corrupt_input() {
+        let data = include_bytes!("../tests/fixtures/sample.zip");
+        let read_all = |data: &[u8]| -> Result<()> {
+            let archive = open("corrupt", data)?;
+            for name in ["stored.csv", "fast.csv", "best.csv"] {
+                if let Some(contents) = archive.read(name)? {
+                    assert_eq!(contents, SAMPLE, "{}", name);
+                }
+            }
+            Ok(())
+        };
+        for len in 0..data.len() {
+            // Only the comment can go without the archive noticing
+            if len < data.len() - b"written by Python zipfile".len() {
+                assert!(read_all(&data[..len]).is_err(), "{} bytes", len);
+            }
+        }
+        for position in 0..data.len() {
+            let mut data = data.to_vec();
+            data[position] ^= 0x55;
+            let _ = read_all(&data);
+        }
+    }
+}
@@ -0,0 +1,507 @@
+// End-to-end tests running the built binary on files in a scratch directory.
+
+use std::{
+    fs,
+    io::Read,
+    ops::Deref,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+};
+
+// A directory of its own for every test, emptied first and removed with
+// everything in it when the test ends, passed or not.
+struct Scratch(PathBuf);
+
+impl Deref for Scratch {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for Scratch {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn scratch(name: &str) -> Scratch {
+    let dir = std::env::temp_dir().join(format!("csv_handler-{}-{}", std::process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    Scratch(dir)
+}
+
+fn run(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_csv_handler"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn insert_and_append_rows() {
+    let dir = scratch("insert_and_append_rows");
+    let (data, inserted, appended) = (
+        dir.join("data.csv"),
+        dir.join("inserted.csv"),
+        dir.join("appended.csv"),
+    );
+    fs::write(&data, "a,b,c\n1,2,3\n").unwrap();
+    let [data, inserted, appended] = [&data, &inserted, &appended].map(|p| p.to_str().unwrap());
+
+    let output = run(&[
+        "--read-path",
+        data,
+        "--write-path",
+        inserted,
+        "insert",
+        "--at",
+        "1",
+        "--data",
+        "x,y,z",
+    ]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        fs::read_to_string(inserted).unwrap(),
+        "x,y,z\na,b,c\n1,2,3\n"
+    );
+
+    let output = run(&[
+        "--read-path",
+        inserted,
+        "--write-path",
+        appended,
+        "append",
+        "--data",
+        "7,8,9",
+    ]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        fs::read_to_string(appended).unwrap(),
+        "x,y,z\na,b,c\n1,2,3\n7,8,9\n"
+    );
+}
+
+#[test]
+fn layout_is_not_mistaken_for_fit() {
+    let dir = scratch("layout_is_not_mistaken_for_fit");
+    let (data, appended) = (dir.join("data.csv"), dir.join("appended.csv"));
+    fs::write(&data, "a,b\n1,2\n").unwrap();
+    let [data, appended] = [&data, &appended].map(|p| p.to_str().unwrap());
+
+    let output = run(&["--read-path", data, "--layout", "vertical", "display"]);
+    assert!(output.status.success(), "{:?}", output);
+    let output = run(&[
+        "--read-path",
+        data,
+        "--write-path",
+        appended,
+        "--layout",
+        "panels",
+        "append",
+        "--data",
+        "3",
+        "--fit",
+        "pad",
+    ]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(fs::read_to_string(appended).unwrap(), "a,b\n1,2\n3,\n");
+}
+
+// Rows of the wrong length are fitted as --fit says, both when the file is
+// streamed and when it is loaded up front.
+#[test]
+fn insert_and_append_fit_policies() {
+    let dir = scratch("insert_and_append_fit_policies");
+    let (data, out) = (dir.join("data.csv"), dir.join("out.csv"));
+    fs::write(&data, "a,b,c\n").unwrap();
+    let [data, out] = [&data, &out].map(|p| p.to_str().unwrap());
+
+    let cases = [
+        ("error", "1,2", None),
+        ("pad", "1,2", Some("a,b,c\n1,2,\n")),
+        ("pad", "1,2,3,4", None),
+        ("truncate", "1,2,3,4", Some("a,b,c\n1,2,3\n")),
+        ("truncate", "1,2", None),
+        ("pad-or-truncate", "1", Some("a,b,c\n1,,\n")),
+        ("pad-or-truncate", "1,2,3,4", Some("a,b,c\n1,2,3\n")),
+    ];
+    for in_memory in [false, true] {
+        for (fit, row, expected) in cases {
+            for command in [
+                vec!["insert", "--at", "2", "--data", row, "--fit", fit],
+                vec!["append", "--data", row, "--fit", fit],
+            ] {
+                let _ = fs::remove_file(out);
+                let mut args = vec!["--read-path", data, "--write-path", out];
+                if in_memory {
+                    args.push("--in-memory");
+                }
+                args.extend(&command);
+                let output = run(&args);
+                match expected {
+                    Some(expected) => {
+                        assert!(output.status.success(), "{:?}: {:?}", args, output);
+                        assert_eq!(fs::read_to_string(out).unwrap(), expected, "{:?}", args);
+                    }
+                    // Field count mismatch
+                    None => assert_eq!(output.status.code(), Some(11), "{:?}", args),
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn typed_json_keeps_leading_zeros() {
+    let dir = scratch("typed_json_keeps_leading_zeros");
+    let data = dir.join("data.csv");
+    fs::write(&data, "zip,n\n01234,5\n0,-7\n").unwrap();
+
+    let output = run(&[
+        "--read-path",
+        data.to_str().unwrap(),
+        "--has-header",
+        "convert",
+        "--to",
+        "ndjson",
+        "--typed",
+    ]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "{\"zip\":\"01234\",\"n\":5}\n{\"zip\":0,\"n\":-7}\n"
+    );
+}
+
+// Values that Parquet and Arrow would store differently keep their text.
+#[test]
+fn binary_formats_keep_every_value() {
+    let dir = scratch("binary_formats_keep_every_value");
+    let data = dir.join("data.csv");
+    let csv = "at,day,date,seen,price,count,zip\n\
+               2023-01-01T10:00:00.750+05:00,31.12.2023,2023-01-05,2023-01-05 10:20:30,1.50,NA,01234\n\
+               2023-01-01T10:00:00Z,2023-1-5,2023-01-06,2023-01-05 10:20:31,2,3,0\n";
+    fs::write(&data, csv).unwrap();
+
+    for to in ["parquet", "arrow"] {
+        let (file, back) = (dir.join(format!("data.{}", to)), dir.join("back.csv"));
+        let output = run(&[
+            "--read-path",
+            data.to_str().unwrap(),
+            "--has-header",
+            "convert",
+            "--to",
+            to,
+            "--output",
+            file.to_str().unwrap(),
+        ]);
+        assert!(output.status.success(), "{:?}", output);
+        let output = run(&[
+            "--read-path",
+            file.to_str().unwrap(),
+            "--write-path",
+            back.to_str().unwrap(),
+            "display",
+        ]);
+        assert!(output.status.success(), "{:?}", output);
+        assert_eq!(fs::read_to_string(&back).unwrap(), csv, "{}", to);
+    }
+}
+
+#[test]
+fn unsupported_arrow_codec_leaves_no_file() {
+    let dir = scratch("unsupported_arrow_codec_leaves_no_file");
+    let (data, out) = (dir.join("data.csv"), dir.join("out.arrow"));
+    fs::write(&data, "a\n1\n").unwrap();
+
+    let output = run(&[
+        "--read-path",
+        data.to_str().unwrap(),
+        "convert",
+        "--to",
+        "arrow",
+        "--compression",
+        "gzip",
+        "--output",
+        out.to_str().unwrap(),
+    ]);
+    assert_eq!(output.status.code(), Some(28), "{:?}", output);
+    assert!(!out.exists());
+}
+
+#[test]
+fn json_numbers_keep_their_digits() {
+    let dir = scratch("json_numbers_keep_their_digits");
+    let (data, out) = (dir.join("data.json"), dir.join("out.csv"));
+    fs::write(
+        &data,
+        r#"[{"id":12345678901234567890,"n":1.0e2,"x":-0.10}]"#,
+    )
+    .unwrap();
+
+    let output = run(&[
+        "--read-path",
+        data.to_str().unwrap(),
+        "--write-path",
+        out.to_str().unwrap(),
+        "display",
+    ]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        fs::read_to_string(&out).unwrap(),
+        "id,n,x\n12345678901234567890,1.0e2,-0.10\n"
+    );
+
+    for number in ["01", "1.", ".5", "1e", "-", "1.5e+"] {
+        fs::write(&data, format!("[[{}]]", number)).unwrap();
+        let output = run(&["--read-path", data.to_str().unwrap(), "display"]);
+        assert!(!output.status.success(), "{}: {:?}", number, output);
+    }
+}
+
+// Deep nesting is an error rather than a stack overflow.
+#[test]
+fn deeply_nested_json_is_rejected() {
+    let dir = scratch("deeply_nested_json_is_rejected");
+    let data = dir.join("data.json");
+    let nested = |depth: usize| format!("[{{\"a\":{}1{}}}]", "[".repeat(depth), "]".repeat(depth));
+
+    fs::write(&data, nested(100_000)).unwrap();
+    let output = run(&["--read-path", data.to_str().unwrap(), "display"]);
+    // Invalid JSON
+    assert_eq!(output.status.code(), Some(15), "{:?}", output);
+
+    fs::write(&data, nested(500)).unwrap();
+    let output = run(&["--read-path", data.to_str().unwrap(), "display"]);
+    assert!(output.status.success(), "{:?}", output);
+}
+
+#[test]
+fn colliding_json_columns_are_rejected() {
+    let dir = scratch("colliding_json_columns_are_rejected");
+    let data = dir.join("data.json");
+
+    for record in [r#"{"a":{"b":1},"a.b":2}"#, r#"{"a.b":2,"a":{"b":1}}"#] {
+        fs::write(&data, format!("[{{\"c\":0}},{}]", record)).unwrap();
+        let output = run(&["--read-path", data.to_str().unwrap(), "display"]);
+        // Invalid record
+        assert_eq!(output.status.code(), Some(23), "{}: {:?}", record, output);
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(
+            stderr.contains("record 2") && stderr.contains("\"a.b\""),
+            "{}",
+            stderr
+        );
+    }
+
+    // Kept apart when nested values stay JSON text
+    fs::write(&data, r#"[{"a":{"b":1},"a.b":2}]"#).unwrap();
+    let output = run(&[
+        "--read-path",
+        data.to_str().unwrap(),
+        "--flatten",
+        "json",
+        "display",
+    ]);
+    assert!(output.status.success(), "{:?}", output);
+}
+
+// Only true and false become spreadsheet booleans, yes and no stay text.
+#[test]
+fn xlsx_booleans_are_only_true_and_false() {
+    let dir = scratch("xlsx_booleans_are_only_true_and_false");
+    let (data, book, back) = (
+        dir.join("data.csv"),
+        dir.join("data.xlsx"),
+        dir.join("back.csv"),
+    );
+    fs::write(&data, "flag,answer\ntrue,yes\nFALSE,no\n").unwrap();
+    let [data, book, back] = [&data, &book, &back].map(|p| p.to_str().unwrap());
+
+    let output = run(&[
+        "--read-path",
+        data,
+        "--has-header",
+        "--write-path",
+        book,
+        "display",
+    ]);
+    assert!(output.status.success(), "{:?}", output);
+    let output = run(&["--read-path", book, "--write-path", back, "display"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        fs::read_to_string(back).unwrap(),
+        "flag,answer\ntrue,yes\nfalse,no\n"
+    );
+}
+
+// Cells render as their text rather than as HTML, headings or lists.
+#[test]
+fn markdown_escapes_cells() {
+    let dir = scratch("markdown_escapes_cells");
+    let data = dir.join("data.csv");
+    fs::write(&data, "a,b\n<b>x</b> & y,-5\n#h,p|q\n").unwrap();
+
+    let output = run(&[
+        "--read-path",
+        data.to_str().unwrap(),
+        "--has-header",
+        "convert",
+        "--to",
+        "markdown",
+    ]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "| a                            | b    |\n\
+         | :--------------------------- | :--- |\n\
+         | &lt;b&gt;x&lt;/b&gt; &amp; y | -5   |\n\
+         | #h                           | p\\|q |\n"
+    );
+
+    // Cut before escaping, never inside an entity
+    let output = run(&[
+        "--read-path",
+        data.to_str().unwrap(),
+        "--has-header",
+        "--style",
+        "markdown",
+        "--max-width",
+        "18",
+        "display",
+    ]);
+    assert!(output.status.success(), "{:?}", output);
+    let text = String::from_utf8(output.stdout).unwrap();
+    assert!(text.contains("| &lt;b&gt;x&lt;/b…  | -5   |\n"), "{}", text);
+}
+
+#[test]
+fn control_characters_are_shown() {
+    let dir = scratch("control_characters_are_shown");
+    let data = dir.join("data.csv");
+    fs::write(&data, "a,b\nx\ty,\x1b[31mred\nlong value,z\n").unwrap();
+
+    let output = run(&[
+        "--read-path",
+        data.to_str().unwrap(),
+        "--has-header",
+        "display",
+    ]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "a         | b        \n\
+         ----------+----------\n\
+         x␉y       | ^[[31mred\n\
+         long value| z        \n"
+    );
+}
+
+// Output piped into a reader that stops early, like `head`, ends quietly.
+#[test]
+fn closed_output_is_not_an_error() {
+    let dir = scratch("closed_output_is_not_an_error");
+    let data = dir.join("data.csv");
+    let rows: String = (0..100_000).map(|n| format!("{},{}\n", n, n * 2)).collect();
+    fs::write(&data, format!("a,b\n{}", rows)).unwrap();
+
+    for command in [&["display"][..], &["convert", "--to", "ndjson"]] {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_csv_handler"))
+            .args(["--read-path", data.to_str().unwrap(), "--has-header"])
+            .args(command)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut start = [0; 16];
+        child.stdout.take().unwrap().read_exact(&mut start).unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success(), "{:?}: {:?}", command, output);
+        assert!(output.stderr.is_empty(), "{:?}: {:?}", command, output);
+    }
+}
+
+// A write that fails partway leaves the file it would have replaced as it was.
+#[test]
+fn failed_write_keeps_the_file() {
+    let dir = scratch("failed_write_keeps_the_file");
+    let data = dir.join("data.csv");
+    fs::write(&data, "a,b\n\"x,y\",2\n").unwrap();
+    let data = data.to_str().unwrap();
+
+    let output = run(&[
+        "--read-path",
+        data,
+        "--in-memory",
+        "--write-path",
+        data,
+        "--quote-style",
+        "never",
+        "display",
+    ]);
+    assert_eq!(output.status.code(), Some(8), "{:?}", output);
+    assert_eq!(fs::read_to_string(data).unwrap(), "a,b\n\"x,y\",2\n");
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+}
+
+#[test]
+fn paginate_rejects_rows_past_the_end() {
+    let data = "tests/fixtures/sample.csv";
+    for mode in [&[][..], &["--in-memory"]] {
+        let args = [&["--read-path", data], mode, &["paginate", "500", "600"]].concat();
+        let output = run(&args);
+        assert_eq!(output.status.code(), Some(3), "{:?}", output);
+    }
+
+    let output = run(&["--read-path", data, "paginate", "1", "--page", "2"]);
+    assert_eq!(output.status.code(), Some(2), "{:?}", output);
+}
+
+// A file several times the memory limit is sorted through temporary runs.
+#[test]
+fn sort_spills_large_files() {
+    let dir = scratch("sort_spills_large_files");
+    let (data, sorted) = (dir.join("data.csv"), dir.join("sorted.csv"));
+    let mut text = String::from("key,n\r\n");
+    for n in 0..40_000 {
+        text.push_str(&format!("{},{}\r\n", (n * 7919) % 1000, n));
+    }
+    fs::write(&data, &text).unwrap();
+
+    // Runs are spilled next to the data, to check they are removed
+    let output = Command::new(env!("CARGO_BIN_EXE_csv_handler"))
+        .env("TMPDIR", dir.as_os_str())
+        .args([
+            "--read-path",
+            data.to_str().unwrap(),
+            "--write-path",
+            sorted.to_str().unwrap(),
+            "--has-header",
+            "sort",
+            "--by",
+            "key:numeric",
+            "--memory-limit",
+            "1",
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+
+    let mut rows: Vec<(usize, usize)> = (0..40_000).map(|n| ((n * 7919) % 1000, n)).collect();
+    rows.sort_by_key(|&(key, _)| key);
+    let mut expected = String::from("key,n\r\n");
+    for (key, n) in rows {
+        expected.push_str(&format!("{},{}\r\n", key, n));
+    }
+    assert!(fs::read_to_string(&sorted).unwrap() == expected);
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+}